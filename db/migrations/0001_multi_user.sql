@@ -0,0 +1,90 @@
+-- Upgrades a single user database to multiple users. Everything in it goes
+-- to an `admin` user, created with the password set by `grimoire password
+-- set` if there was one. Otherwise, or when the password came from
+-- `PASSWORD_HASH`, set it with `grimoire user password admin`.
+--
+--     psql -v ON_ERROR_STOP=1 -f db/migrations/0001_multi_user.sql grimoire
+
+BEGIN;
+
+CREATE TABLE users (
+    id INT NOT NULL PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    username TEXT NOT NULL UNIQUE,
+    password_hash TEXT NOT NULL,
+    is_admin BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+INSERT INTO users (username, password_hash, is_admin)
+SELECT 'admin', COALESCE((SELECT password_hash FROM auth_password), ''), TRUE;
+
+DROP TABLE auth_password;
+
+-- Columns are added without a default, then filled with the admin's id, so
+-- that forgetting a table fails on NOT NULL rather than mixing users up.
+ALTER TABLE watch_history ADD COLUMN user_id INT;
+UPDATE watch_history SET user_id = (SELECT id FROM users);
+ALTER TABLE watch_history
+    ALTER COLUMN user_id SET NOT NULL,
+    ADD FOREIGN KEY (user_id) REFERENCES users (id);
+
+ALTER TABLE list ADD COLUMN user_id INT;
+UPDATE list SET user_id = (SELECT id FROM users);
+ALTER TABLE list
+    ALTER COLUMN user_id SET NOT NULL,
+    ADD FOREIGN KEY (user_id) REFERENCES users (id);
+
+ALTER TABLE rating ADD COLUMN user_id INT;
+UPDATE rating SET user_id = (SELECT id FROM users);
+ALTER TABLE rating
+    ALTER COLUMN user_id SET NOT NULL,
+    DROP CONSTRAINT rating_pkey,
+    ADD PRIMARY KEY (user_id, media_id),
+    ADD FOREIGN KEY (user_id) REFERENCES users (id);
+
+ALTER TABLE import_review_item ADD COLUMN user_id INT;
+UPDATE import_review_item SET user_id = (SELECT id FROM users);
+ALTER TABLE import_review_item
+    ALTER COLUMN user_id SET NOT NULL,
+    ADD FOREIGN KEY (user_id) REFERENCES users (id);
+
+ALTER TABLE scrobble_session ADD COLUMN user_id INT;
+UPDATE scrobble_session SET user_id = (SELECT id FROM users);
+ALTER TABLE scrobble_session
+    ALTER COLUMN user_id SET NOT NULL,
+    DROP CONSTRAINT scrobble_session_pkey,
+    ADD PRIMARY KEY (user_id, media_id),
+    ADD FOREIGN KEY (user_id) REFERENCES users (id);
+
+ALTER TABLE webhook_session ADD COLUMN user_id INT;
+UPDATE webhook_session SET user_id = (SELECT id FROM users);
+ALTER TABLE webhook_session
+    ALTER COLUMN user_id SET NOT NULL,
+    DROP CONSTRAINT webhook_session_pkey,
+    ADD PRIMARY KEY (user_id, source, session_id),
+    ADD FOREIGN KEY (user_id) REFERENCES users (id);
+
+ALTER TABLE trakt_sync ADD COLUMN user_id INT;
+UPDATE trakt_sync SET user_id = (SELECT id FROM users);
+ALTER TABLE trakt_sync
+    DROP CONSTRAINT trakt_sync_pkey,
+    DROP CONSTRAINT single_row,
+    DROP COLUMN id,
+    ADD PRIMARY KEY (user_id),
+    ADD FOREIGN KEY (user_id) REFERENCES users (id);
+
+ALTER TABLE auth_session ADD COLUMN user_id INT;
+UPDATE auth_session SET user_id = (SELECT id FROM users);
+ALTER TABLE auth_session
+    ALTER COLUMN user_id SET NOT NULL,
+    ADD FOREIGN KEY (user_id) REFERENCES users (id);
+
+ALTER TABLE api_token ADD COLUMN user_id INT;
+UPDATE api_token SET user_id = (SELECT id FROM users);
+ALTER TABLE api_token
+    ALTER COLUMN user_id SET NOT NULL,
+    DROP CONSTRAINT api_token_name_key,
+    ADD FOREIGN KEY (user_id) REFERENCES users (id),
+    ADD UNIQUE (user_id, name);
+
+COMMIT;
@@ -1,3 +1,11 @@
+CREATE TABLE users (
+    id INT NOT NULL PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    username TEXT NOT NULL UNIQUE,
+    password_hash TEXT NOT NULL,
+    is_admin BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
 CREATE TYPE media_kind AS ENUM (
     'MOVIE',
     'SHOW',
@@ -8,6 +16,7 @@ CREATE TYPE media_kind AS ENUM (
 CREATE TABLE media (
     id INT NOT NULL PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
     kind media_kind NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
     UNIQUE (id, kind)
 );
 
@@ -24,22 +33,46 @@ CREATE TABLE media_external_id (
 CREATE TABLE movie (
     id INT NOT NULL PRIMARY KEY,
     kind media_kind NOT NULL GENERATED ALWAYS AS ('MOVIE'::media_kind) STORED,
+    -- Used in URLs, made from the title and release year.
+    slug TEXT NOT NULL UNIQUE,
     title TEXT NOT NULL,
     release_year INT,
     overview TEXT,
     tagline TEXT,
     runtime INT,
+    -- From TMDB, along with the columns below.
+    original_title TEXT,
+    -- ISO 639-1 code, e.g. en.
+    original_language TEXT,
+    -- e.g. Released.
+    status TEXT,
+    -- ISO 3166-1 codes, e.g. US.
+    production_countries TEXT[] NOT NULL DEFAULT '{}',
+    -- TMDB language the title was fetched in, e.g. en-US.
+    metadata_language TEXT,
     FOREIGN KEY (id, kind) REFERENCES media (id, kind)
 );
 
 CREATE TABLE show (
     id INT NOT NULL PRIMARY KEY,
     kind media_kind NOT NULL GENERATED ALWAYS AS ('SHOW'::media_kind) STORED,
+    -- Used in URLs, made from the title and release year.
+    slug TEXT NOT NULL UNIQUE,
     title TEXT NOT NULL,
     release_year INT,
     overview TEXT,
     tagline TEXT,
     episode_runtime INT,
+    -- From TMDB, along with the columns below.
+    original_title TEXT,
+    -- ISO 639-1 code, e.g. en.
+    original_language TEXT,
+    -- e.g. Ended or Returning Series.
+    status TEXT,
+    -- ISO 3166-1 codes, e.g. US.
+    production_countries TEXT[] NOT NULL DEFAULT '{}',
+    -- TMDB language the title was fetched in, e.g. en-US.
+    metadata_language TEXT,
     FOREIGN KEY (id, kind) REFERENCES media (id, kind)
 );
 
@@ -70,11 +103,95 @@ CREATE TABLE episode (
     UNIQUE (show_id, season_id, number)
 );
 
+-- Genres and keywords from TMDB, of movies and shows.
+CREATE TABLE genre (
+    id INT NOT NULL PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    tmdb_id INT NOT NULL UNIQUE,
+    name TEXT NOT NULL
+);
+
+CREATE TABLE media_genre (
+    media_id INT NOT NULL,
+    genre_id INT NOT NULL,
+    PRIMARY KEY (media_id, genre_id),
+    FOREIGN KEY (media_id) REFERENCES media (id),
+    FOREIGN KEY (genre_id) REFERENCES genre (id)
+);
+
+CREATE TABLE keyword (
+    id INT NOT NULL PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    tmdb_id INT NOT NULL UNIQUE,
+    name TEXT NOT NULL
+);
+
+CREATE TABLE media_keyword (
+    media_id INT NOT NULL,
+    keyword_id INT NOT NULL,
+    PRIMARY KEY (media_id, keyword_id),
+    FOREIGN KEY (media_id) REFERENCES media (id),
+    FOREIGN KEY (keyword_id) REFERENCES keyword (id)
+);
+
+-- Movie franchises from TMDB, e.g. The Matrix Collection.
+CREATE TABLE collection (
+    id INT NOT NULL PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    tmdb_id INT NOT NULL UNIQUE,
+    name TEXT NOT NULL,
+    overview TEXT
+);
+
+-- Every movie of a collection, whether it's in the library or not. Movies in
+-- the library are matched by their TMDB id.
+CREATE TABLE collection_part (
+    collection_id INT NOT NULL,
+    tmdb_id INT NOT NULL,
+    title TEXT NOT NULL,
+    release_date DATE,
+    PRIMARY KEY (collection_id, tmdb_id),
+    FOREIGN KEY (collection_id) REFERENCES collection (id)
+);
+
+-- People in the credits of movies, shows and episodes, from TMDB.
+CREATE TABLE person (
+    id INT NOT NULL PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    tmdb_id INT NOT NULL UNIQUE,
+    name TEXT NOT NULL
+);
+
+CREATE TYPE credit_kind AS ENUM (
+    'CAST',
+    'CREW'
+);
+
+CREATE TABLE credit (
+    media_id INT NOT NULL,
+    media_kind media_kind NOT NULL,
+    -- Order in the credits, as listed by TMDB.
+    position INT NOT NULL,
+    person_id INT NOT NULL,
+    kind credit_kind NOT NULL,
+    -- The character played by cast, the job of crew, e.g. Director.
+    role TEXT NOT NULL,
+    -- Only for crew, e.g. Directing.
+    department TEXT,
+    PRIMARY KEY (media_id, position),
+    FOREIGN KEY (media_id, media_kind) REFERENCES media (id, kind),
+    FOREIGN KEY (person_id) REFERENCES person (id),
+    CONSTRAINT valid_media_kind
+        CHECK (media_kind IN ('MOVIE'::media_kind, 'SHOW'::media_kind, 'EPISODE'::media_kind))
+);
+
+CREATE INDEX credit_person_id_idx ON credit (person_id);
+
 CREATE TABLE watch_history (
     id INT NOT NULL PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    user_id INT NOT NULL,
     media_id INT NOT NULL,
     media_kind media_kind NOT NULL,
     watched_at TIMESTAMPTZ NOT NULL,
+    -- When the play was last pulled from or pushed to Trakt.
+    trakt_synced_at TIMESTAMPTZ,
+    FOREIGN KEY (user_id) REFERENCES users (id),
     FOREIGN KEY (media_id, media_kind) REFERENCES media (id, kind),
     CONSTRAINT valid_media_kind 
         CHECK (media_kind IN ('MOVIE'::media_kind, 'EPISODE'::media_kind))
@@ -86,26 +203,159 @@ CREATE TYPE list_kind AS ENUM (
     'PERSONAL'
 );
 
+-- Every user gets a watchlist and a favorites list when created.
 CREATE TABLE list (
     id INT NOT NULL PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    user_id INT NOT NULL,
     kind list_kind NOT NULL,
     name TEXT NOT NULL,
     description TEXT,
     created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
-    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    FOREIGN KEY (user_id) REFERENCES users (id)
 );
 
--- TODO: Insert this from the app?
-INSERT INTO list (name, kind) VALUES
-    ('Watchlist', 'WATCHLIST'::list_kind),
-    ('Favorites', 'FAVORITES'::list_kind);
-
 CREATE TABLE list_item (
     list_id INT NOT NULL,
     media_id INT NOT NULL,
     media_kind media_kind NOT NULL,
     listed_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    trakt_synced_at TIMESTAMPTZ,
     PRIMARY KEY (list_id, media_id),
     FOREIGN KEY (list_id) REFERENCES list (id),
     FOREIGN KEY (media_id, media_kind) REFERENCES media (id, kind)
-);
\ No newline at end of file
+);
+
+CREATE TABLE rating (
+    user_id INT NOT NULL,
+    media_id INT NOT NULL,
+    media_kind media_kind NOT NULL,
+    rating INT NOT NULL,
+    rated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, media_id),
+    FOREIGN KEY (user_id) REFERENCES users (id),
+    FOREIGN KEY (media_id, media_kind) REFERENCES media (id, kind),
+    CONSTRAINT valid_rating CHECK (rating BETWEEN 1 AND 10)
+);
+
+-- Entries from imports that couldn't be matched to any media, waiting to be
+-- resolved by hand.
+CREATE TABLE import_review_item (
+    id INT NOT NULL PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    user_id INT NOT NULL,
+    source TEXT NOT NULL,
+    title TEXT NOT NULL,
+    release_year INT,
+    url TEXT,
+    watched_at TIMESTAMPTZ,
+    rating INT,
+    rated_at TIMESTAMPTZ,
+    list_id INT,
+    listed_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    FOREIGN KEY (user_id) REFERENCES users (id),
+    FOREIGN KEY (list_id) REFERENCES list (id)
+);
+
+CREATE TYPE scrobble_state AS ENUM (
+    'PLAYING',
+    'PAUSED'
+);
+
+-- Movies and episodes being watched right now, as reported through the
+-- scrobble API. A session ends when playback stops.
+CREATE TABLE scrobble_session (
+    user_id INT NOT NULL,
+    media_id INT NOT NULL,
+    media_kind media_kind NOT NULL,
+    state scrobble_state NOT NULL,
+    progress REAL NOT NULL,
+    started_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, media_id),
+    FOREIGN KEY (user_id) REFERENCES users (id),
+    FOREIGN KEY (media_id, media_kind) REFERENCES media (id, kind),
+    CONSTRAINT valid_media_kind
+        CHECK (media_kind IN ('MOVIE'::media_kind, 'EPISODE'::media_kind)),
+    CONSTRAINT valid_progress CHECK (progress BETWEEN 0 AND 100)
+);
+
+-- Playback sessions reported by media server webhooks, used to ignore
+-- duplicate events for the same session.
+CREATE TABLE webhook_session (
+    user_id INT NOT NULL,
+    source TEXT NOT NULL,
+    session_id TEXT NOT NULL,
+    received_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, source, session_id),
+    FOREIGN KEY (user_id) REFERENCES users (id)
+);
+
+-- Trakt API credentials and sync state of each user who logged into Trakt.
+CREATE TABLE trakt_sync (
+    user_id INT NOT NULL PRIMARY KEY,
+    access_token TEXT NOT NULL,
+    refresh_token TEXT NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    history_pulled_at TIMESTAMPTZ,
+    history_changed_at TIMESTAMPTZ,
+    FOREIGN KEY (user_id) REFERENCES users (id)
+);
+
+-- Web UI login sessions. The session cookie holds the token, which is only
+-- stored hashed.
+CREATE TABLE auth_session (
+    token_hash TEXT NOT NULL PRIMARY KEY,
+    user_id INT NOT NULL,
+    csrf_token TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users (id)
+);
+
+-- Long-lived tokens for the API and webhooks, stored hashed.
+CREATE TABLE api_token (
+    id INT NOT NULL PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    user_id INT NOT NULL,
+    name TEXT NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_used_at TIMESTAMPTZ,
+    FOREIGN KEY (user_id) REFERENCES users (id),
+    UNIQUE (user_id, name)
+);
+
+CREATE TYPE job_status AS ENUM (
+    'PENDING',
+    'RUNNING',
+    'SUCCEEDED',
+    'FAILED'
+);
+
+-- Background jobs run by the server's workers, which claim pending ones with
+-- SELECT ... FOR UPDATE SKIP LOCKED. Failed attempts are retried later, up to
+-- max_attempts.
+CREATE TABLE job (
+    id INT NOT NULL PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    -- e.g. import, also in the payload.
+    kind TEXT NOT NULL,
+    payload JSONB NOT NULL,
+    -- Uploaded file of imports, cleared once done.
+    data BYTEA,
+    -- User who enqueued the job, if any.
+    user_id INT,
+    status job_status NOT NULL DEFAULT 'PENDING',
+    attempts INT NOT NULL DEFAULT 0,
+    max_attempts INT NOT NULL,
+    -- Next attempt of pending jobs.
+    run_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_error TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    started_at TIMESTAMPTZ,
+    finished_at TIMESTAMPTZ,
+    -- Last time the job was stopped by a shutdown, to be run again.
+    interrupted_at TIMESTAMPTZ,
+    FOREIGN KEY (user_id) REFERENCES users (id)
+);
+
+CREATE INDEX job_pending_run_at ON job (run_at) WHERE status = 'PENDING';
\ No newline at end of file
@@ -0,0 +1,176 @@
+//! Password hashing, the random tokens used for sessions and API tokens, and
+//! limiting of failed logins.
+//!
+//! Tokens are only ever stored hashed, so a database leak doesn't hand out
+//! working sessions or API tokens.
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use argon2::{
+    Argon2, PasswordHash, PasswordHasher, PasswordVerifier,
+    password_hash::{self, SaltString},
+};
+use rand::Rng;
+use sha2::{Digest, Sha256};
+
+pub use crate::db::{ApiToken, User};
+
+/// Hashes a password into a PHC string, e.g. `$argon2id$v=19$...`.
+pub fn hash_password(password: &str) -> Result<String, password_hash::Error> {
+    let salt = SaltString::encode_b64(&rand::rng().random::<[u8; 16]>())?;
+
+    Ok(Argon2::default()
+        .hash_password(password.as_bytes(), &salt)?
+        .to_string())
+}
+
+/// Checks a password against a hash made by [`hash_password`]. Invalid hashes
+/// never match.
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash).is_ok_and(|hash| {
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok()
+    })
+}
+
+/// Whether a string is a valid password hash, used to catch a mistyped
+/// `ADMIN_PASSWORD_HASH` on startup.
+pub fn is_valid_password_hash(hash: &str) -> bool {
+    PasswordHash::new(hash).is_ok()
+}
+
+/// Generates a random token of 32 bytes, hex encoded.
+pub fn generate_token() -> String {
+    to_hex(&rand::rng().random::<[u8; 32]>())
+}
+
+/// Hash of a token as stored in the database.
+pub fn hash_token(token: &str) -> String {
+    to_hex(&Sha256::digest(token.as_bytes()))
+}
+
+/// Compares two strings in constant time, so a secret can't be guessed byte
+/// by byte.
+pub fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Failed logins allowed from a client IP, and for a username, within
+/// [`LOGIN_WINDOW`]. Clients behind the same proxy share the IP limit, so it's
+/// the higher one.
+const MAX_FAILED_LOGINS_PER_IP: u32 = 20;
+const MAX_FAILED_LOGINS_PER_USERNAME: u32 = 5;
+const LOGIN_WINDOW: Duration = Duration::from_secs(15 * 60);
+
+/// Failures are counted in memory, expired ones are dropped once there are
+/// this many.
+const MAX_TRACKED_LOGINS: usize = 1024;
+
+/// Counts failed logins per client IP and per username, to slow down
+/// password guessing. Once either reaches its limit, logins are refused
+/// until the window of its first failure ends.
+#[derive(Default)]
+pub struct LoginLimiter {
+    failures: Mutex<HashMap<LoginKey, FailedLogins>>,
+}
+
+#[derive(Hash, PartialEq, Eq)]
+enum LoginKey {
+    Ip(IpAddr),
+    Username(String),
+}
+
+struct FailedLogins {
+    count: u32,
+    since: Instant,
+}
+
+impl LoginLimiter {
+    /// Whether a login from `ip` for `username` may be attempted.
+    pub fn is_allowed(&self, ip: IpAddr, username: &str) -> bool {
+        let failures = self.failures.lock().unwrap();
+        let count = |key| {
+            failures
+                .get(&key)
+                .filter(|failed| failed.since.elapsed() < LOGIN_WINDOW)
+                .map_or(0, |failed| failed.count)
+        };
+
+        count(LoginKey::Ip(ip)) < MAX_FAILED_LOGINS_PER_IP
+            && count(LoginKey::Username(username.to_string())) < MAX_FAILED_LOGINS_PER_USERNAME
+    }
+
+    pub fn record_failure(&self, ip: IpAddr, username: &str) {
+        let mut failures = self.failures.lock().unwrap();
+        if failures.len() >= MAX_TRACKED_LOGINS {
+            failures.retain(|_, failed| failed.since.elapsed() < LOGIN_WINDOW);
+        }
+
+        for key in [LoginKey::Ip(ip), LoginKey::Username(username.to_string())] {
+            let failed = failures.entry(key).or_insert(FailedLogins {
+                count: 0,
+                since: Instant::now(),
+            });
+            if failed.since.elapsed() >= LOGIN_WINDOW {
+                *failed = FailedLogins {
+                    count: 0,
+                    since: Instant::now(),
+                };
+            }
+            failed.count += 1;
+        }
+    }
+
+    /// Forgets the username's failures once its user logged in.
+    pub fn record_success(&self, username: &str) {
+        self.failures
+            .lock()
+            .unwrap()
+            .remove(&LoginKey::Username(username.to_string()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    #[test]
+    fn login_limiter_limits_usernames_and_ips() {
+        let limiter = LoginLimiter::default();
+        let ip = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        let other_ip = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+
+        for _ in 0..MAX_FAILED_LOGINS_PER_USERNAME {
+            assert!(limiter.is_allowed(ip, "alice"));
+            limiter.record_failure(ip, "alice");
+        }
+        assert!(!limiter.is_allowed(ip, "alice"));
+        assert!(!limiter.is_allowed(other_ip, "alice"));
+        assert!(limiter.is_allowed(ip, "bob"));
+
+        limiter.record_success("alice");
+        assert!(limiter.is_allowed(ip, "alice"));
+
+        for idx in MAX_FAILED_LOGINS_PER_USERNAME..MAX_FAILED_LOGINS_PER_IP {
+            limiter.record_failure(ip, &format!("user{idx}"));
+        }
+        assert!(!limiter.is_allowed(ip, "bob"));
+        assert!(limiter.is_allowed(other_ip, "bob"));
+    }
+}
@@ -0,0 +1,125 @@
+//! Native backup format, holding everything needed to restore a Grimoire
+//! instance.
+//!
+//! Ids in the document are only used to reference rows within the document
+//! itself and are remapped when importing.
+
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+
+use crate::db::{ListKind, MediaExternalId};
+
+pub mod export;
+pub mod import;
+
+/// Version of the backup document. Bump it whenever the format changes in a
+/// way older importers can't read.
+pub const VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Backup {
+    pub version: u32,
+    pub exported_at: Timestamp,
+    pub movies: Vec<BackupMovie>,
+    pub shows: Vec<BackupShow>,
+    pub watch_history: Vec<BackupPlay>,
+    pub ratings: Vec<BackupRating>,
+    pub lists: Vec<BackupList>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupExternalIds {
+    pub trakt_id: Option<i32>,
+    pub trakt_slug: Option<String>,
+    pub tvdb_id: Option<i32>,
+    pub imdb_id: Option<String>,
+    pub tmdb_id: Option<i32>,
+}
+
+impl From<BackupExternalIds> for MediaExternalId {
+    fn from(ids: BackupExternalIds) -> Self {
+        MediaExternalId {
+            trakt_id: ids.trakt_id,
+            trakt_slug: ids.trakt_slug,
+            tvdb_id: ids.tvdb_id,
+            imdb_id: ids.imdb_id,
+            tmdb_id: ids.tmdb_id,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackupMovie {
+    pub id: i32,
+    #[serde(default)]
+    pub slug: Option<String>,
+    pub title: String,
+    pub release_year: Option<i32>,
+    pub overview: Option<String>,
+    pub tagline: Option<String>,
+    pub runtime: Option<i32>,
+    pub external_ids: Option<BackupExternalIds>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackupShow {
+    pub id: i32,
+    #[serde(default)]
+    pub slug: Option<String>,
+    pub title: String,
+    pub release_year: Option<i32>,
+    pub overview: Option<String>,
+    pub tagline: Option<String>,
+    pub episode_runtime: Option<i32>,
+    pub external_ids: Option<BackupExternalIds>,
+    pub seasons: Vec<BackupSeason>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackupSeason {
+    pub id: i32,
+    pub title: String,
+    pub number: i32,
+    pub overview: Option<String>,
+    pub external_ids: Option<BackupExternalIds>,
+    pub episodes: Vec<BackupEpisode>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackupEpisode {
+    pub id: i32,
+    pub title: String,
+    pub number: i32,
+    pub overview: Option<String>,
+    pub runtime: Option<i32>,
+    pub external_ids: Option<BackupExternalIds>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackupPlay {
+    /// Id of a movie or episode in this backup.
+    pub media_id: i32,
+    pub watched_at: Timestamp,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackupRating {
+    pub media_id: i32,
+    pub rating: i32,
+    pub rated_at: Timestamp,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackupList {
+    pub kind: ListKind,
+    pub name: String,
+    pub description: Option<String>,
+    pub created_at: Timestamp,
+    pub items: Vec<BackupListItem>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackupListItem {
+    pub media_id: i32,
+    pub listed_at: Timestamp,
+}
@@ -0,0 +1,278 @@
+use std::{collections::HashMap, io::Write};
+
+use deadpool_postgres::{
+    GenericClient, Transaction,
+    tokio_postgres::{Row, types::ToSql},
+};
+use jiff::Timestamp;
+use serde::Serialize;
+
+use super::{
+    BackupEpisode, BackupExternalIds, BackupList, BackupListItem, BackupMovie, BackupPlay,
+    BackupRating, BackupSeason, BackupShow, VERSION,
+};
+
+const EXTERNAL_ID_COLUMNS: &str =
+    "mei.trakt_id, mei.trakt_slug, mei.tvdb_id, mei.imdb_id, mei.tmdb_id";
+
+/// Rows fetched at once from the tables written out as they're read.
+const BATCH_SIZE: i32 = 1000;
+
+// TODO: error handling
+/// Writes a full backup of the library and of a user's history, ratings and
+/// lists as JSON, in the [`super::Backup`] format.
+///
+/// Everything is read from a single snapshot, so that plays added meanwhile
+/// can't reference media missing from the backup. Movies, plays and ratings
+/// are written as they're read rather than held in memory.
+pub async fn export_json<C: GenericClient, W: Write>(
+    conn: &mut C,
+    user_id: i32,
+    mut writer: W,
+) -> anyhow::Result<()> {
+    let tx = conn.transaction().await?;
+    tx.batch_execute("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ, READ ONLY")
+        .await?;
+
+    write!(
+        writer,
+        "{{\"version\":{VERSION},\"exported_at\":{}",
+        serde_json::to_string(&Timestamp::now())?
+    )?;
+
+    write_array(
+        &tx,
+        &mut writer,
+        "movies",
+        &format!(
+            "SELECT mo.id, mo.title, mo.release_year, mo.overview, mo.tagline, mo.runtime,
+            {EXTERNAL_ID_COLUMNS}, mo.slug FROM movie mo
+            LEFT JOIN media_external_id mei ON mei.media_id = mo.id
+            ORDER BY mo.id"
+        ),
+        &[],
+        |row| BackupMovie {
+            id: row.get(0),
+            slug: row.get(11),
+            title: row.get(1),
+            release_year: row.get(2),
+            overview: row.get(3),
+            tagline: row.get(4),
+            runtime: row.get(5),
+            external_ids: external_ids_from_row(row, 6),
+        },
+    )
+    .await?;
+
+    let mut episodes_by_season: HashMap<i32, Vec<BackupEpisode>> = HashMap::new();
+    for row in tx
+        .query(
+            &format!(
+                "SELECT ep.season_id, ep.id, ep.title, ep.number, ep.overview, ep.runtime,
+                {EXTERNAL_ID_COLUMNS} FROM episode ep
+                LEFT JOIN media_external_id mei ON mei.media_id = ep.id
+                ORDER BY ep.season_id, ep.number"
+            ),
+            &[],
+        )
+        .await?
+    {
+        episodes_by_season
+            .entry(row.get(0))
+            .or_default()
+            .push(BackupEpisode {
+                id: row.get(1),
+                title: row.get(2),
+                number: row.get(3),
+                overview: row.get(4),
+                runtime: row.get(5),
+                external_ids: external_ids_from_row(&row, 6),
+            });
+    }
+
+    let mut seasons_by_show: HashMap<i32, Vec<BackupSeason>> = HashMap::new();
+    for row in tx
+        .query(
+            &format!(
+                "SELECT se.show_id, se.id, se.title, se.number, se.overview,
+                {EXTERNAL_ID_COLUMNS} FROM season se
+                LEFT JOIN media_external_id mei ON mei.media_id = se.id
+                ORDER BY se.show_id, se.number"
+            ),
+            &[],
+        )
+        .await?
+    {
+        let id: i32 = row.get(1);
+        seasons_by_show
+            .entry(row.get(0))
+            .or_default()
+            .push(BackupSeason {
+                id,
+                title: row.get(2),
+                number: row.get(3),
+                overview: row.get(4),
+                external_ids: external_ids_from_row(&row, 5),
+                episodes: episodes_by_season.remove(&id).unwrap_or_default(),
+            });
+    }
+
+    write_array(
+        &tx,
+        &mut writer,
+        "shows",
+        &format!(
+            "SELECT sh.id, sh.title, sh.release_year, sh.overview, sh.tagline, sh.episode_runtime,
+            {EXTERNAL_ID_COLUMNS}, sh.slug FROM show sh
+            LEFT JOIN media_external_id mei ON mei.media_id = sh.id
+            ORDER BY sh.id"
+        ),
+        &[],
+        |row| {
+            let id: i32 = row.get(0);
+            BackupShow {
+                id,
+                slug: row.get(11),
+                title: row.get(1),
+                release_year: row.get(2),
+                overview: row.get(3),
+                tagline: row.get(4),
+                episode_runtime: row.get(5),
+                external_ids: external_ids_from_row(row, 6),
+                seasons: seasons_by_show.remove(&id).unwrap_or_default(),
+            }
+        },
+    )
+    .await?;
+
+    write_array(
+        &tx,
+        &mut writer,
+        "watch_history",
+        "SELECT wh.media_id, wh.watched_at FROM watch_history wh
+        WHERE wh.user_id = $1
+        ORDER BY wh.watched_at, wh.id",
+        &[&user_id],
+        |row| BackupPlay {
+            media_id: row.get(0),
+            watched_at: row.get(1),
+        },
+    )
+    .await?;
+
+    write_array(
+        &tx,
+        &mut writer,
+        "ratings",
+        "SELECT ra.media_id, ra.rating, ra.rated_at FROM rating ra
+        WHERE ra.user_id = $1
+        ORDER BY ra.rated_at",
+        &[&user_id],
+        |row| BackupRating {
+            media_id: row.get(0),
+            rating: row.get(1),
+            rated_at: row.get(2),
+        },
+    )
+    .await?;
+
+    let mut items_by_list: HashMap<i32, Vec<BackupListItem>> = HashMap::new();
+    for row in tx
+        .query(
+            "SELECT li.list_id, li.media_id, li.listed_at FROM list_item li
+            INNER JOIN list l ON l.id = li.list_id
+            WHERE l.user_id = $1
+            ORDER BY li.list_id, li.listed_at",
+            &[&user_id],
+        )
+        .await?
+    {
+        items_by_list
+            .entry(row.get(0))
+            .or_default()
+            .push(BackupListItem {
+                media_id: row.get(1),
+                listed_at: row.get(2),
+            });
+    }
+
+    write_array(
+        &tx,
+        &mut writer,
+        "lists",
+        "SELECT l.id, l.kind, l.name, l.description, l.created_at FROM list l
+        WHERE l.user_id = $1
+        ORDER BY l.id",
+        &[&user_id],
+        |row| {
+            let id: i32 = row.get(0);
+            BackupList {
+                kind: row.get(1),
+                name: row.get(2),
+                description: row.get(3),
+                created_at: row.get(4),
+                items: items_by_list.remove(&id).unwrap_or_default(),
+            }
+        },
+    )
+    .await?;
+
+    writer.write_all(b"}")?;
+    writer.flush()?;
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Writes the rows of the query as the `name` array of the backup object,
+/// fetching them in batches.
+async fn write_array<T: Serialize, W: Write>(
+    tx: &Transaction<'_>,
+    writer: &mut W,
+    name: &str,
+    query: &str,
+    params: &[&(dyn ToSql + Sync)],
+    mut to_item: impl FnMut(&Row) -> T,
+) -> anyhow::Result<()> {
+    write!(writer, ",\"{name}\":[")?;
+
+    let portal = tx.bind(query, params).await?;
+    let mut first = true;
+    loop {
+        let rows = tx.query_portal(&portal, BATCH_SIZE).await?;
+        for row in &rows {
+            if !first {
+                writer.write_all(b",")?;
+            }
+            first = false;
+            serde_json::to_writer(&mut *writer, &to_item(row))?;
+        }
+
+        if rows.len() < BATCH_SIZE as usize {
+            break;
+        }
+    }
+
+    writer.write_all(b"]")?;
+
+    Ok(())
+}
+
+/// Reads the columns selected by [`EXTERNAL_ID_COLUMNS`], starting at `idx`.
+fn external_ids_from_row(row: &Row, idx: usize) -> Option<BackupExternalIds> {
+    let ids = BackupExternalIds {
+        trakt_id: row.get(idx),
+        trakt_slug: row.get(idx + 1),
+        tvdb_id: row.get(idx + 2),
+        imdb_id: row.get(idx + 3),
+        tmdb_id: row.get(idx + 4),
+    };
+
+    let is_empty = ids.trakt_id.is_none()
+        && ids.trakt_slug.is_none()
+        && ids.tvdb_id.is_none()
+        && ids.imdb_id.is_none()
+        && ids.tmdb_id.is_none();
+
+    (!is_empty).then_some(ids)
+}
@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+
+use deadpool_postgres::GenericClient;
+
+use super::{Backup, BackupExternalIds, VERSION};
+use crate::db::{
+    ListKind, Media, MediaKind, MediaMetadata, NewEpisode, NewList, NewMovie, NewSeason, NewShow,
+    WatchHistory, get_episode_by_season_and_number, get_list_id_by_kind, get_list_id_by_name,
+    get_media_by_external_ids, get_season_by_show_and_number, get_watch_history_id, insert_episode,
+    insert_list, insert_list_item, insert_movie, insert_season, insert_show, insert_watch_history,
+    upsert_rating,
+};
+
+// TODO: error handling
+/// Restores a backup written by [`super::export::export_json`], adding the
+/// history, ratings and lists to the given user.
+///
+/// Media already in the library is matched by its external ids, and plays
+/// already recorded are skipped, so restoring the same backup twice doesn't
+/// duplicate anything. Everything is imported in a single transaction.
+pub async fn import_json<C: GenericClient, R: std::io::Read>(
+    conn: &mut C,
+    user_id: i32,
+    reader: R,
+) -> anyhow::Result<()> {
+    let backup: Backup = serde_json::from_reader(reader)?;
+
+    if backup.version != VERSION {
+        anyhow::bail!("unsupported backup version {}", backup.version);
+    }
+
+    let mut tx = conn.transaction().await?;
+
+    // Maps ids in the backup to media in this instance.
+    let mut media_by_id: HashMap<i32, Media> = HashMap::new();
+
+    for movie in backup.movies {
+        let media = match find_media(&tx, movie.external_ids.as_ref(), MediaKind::Movie).await? {
+            Some(media) => media,
+            None => {
+                insert_movie(
+                    &mut tx,
+                    &NewMovie {
+                        slug: movie.slug,
+                        title: movie.title,
+                        release_year: movie.release_year,
+                        overview: movie.overview,
+                        tagline: movie.tagline,
+                        runtime: movie.runtime,
+                        external_ids: movie.external_ids.map(Into::into),
+                        metadata: MediaMetadata::default(),
+                        credits: vec![],
+                    },
+                )
+                .await?
+            }
+        };
+
+        media_by_id.insert(movie.id, media);
+    }
+
+    for show in backup.shows {
+        let show_media = match find_media(&tx, show.external_ids.as_ref(), MediaKind::Show).await? {
+            Some(media) => media,
+            None => {
+                insert_show(
+                    &mut tx,
+                    &NewShow {
+                        slug: show.slug,
+                        title: show.title,
+                        release_year: show.release_year,
+                        overview: show.overview,
+                        tagline: show.tagline,
+                        episode_runtime: show.episode_runtime,
+                        external_ids: show.external_ids.map(Into::into),
+                        seasons: None,
+                        metadata: MediaMetadata::default(),
+                        credits: vec![],
+                    },
+                )
+                .await?
+            }
+        };
+
+        for season in show.seasons {
+            let season_media =
+                match get_season_by_show_and_number(&tx, &show_media, season.number).await? {
+                    Some(media) => media,
+                    None => {
+                        insert_season(
+                            &mut tx,
+                            &show_media,
+                            &NewSeason {
+                                title: season.title,
+                                number: season.number,
+                                overview: season.overview,
+                                external_ids: season.external_ids.map(Into::into),
+                                episodes: None,
+                            },
+                        )
+                        .await?
+                    }
+                };
+
+            for episode in season.episodes {
+                let episode_media =
+                    match get_episode_by_season_and_number(&tx, &season_media, episode.number)
+                        .await?
+                    {
+                        Some(media) => media,
+                        None => {
+                            insert_episode(
+                                &mut tx,
+                                &show_media,
+                                &season_media,
+                                &NewEpisode {
+                                    title: episode.title,
+                                    number: episode.number,
+                                    overview: episode.overview,
+                                    runtime: episode.runtime,
+                                    external_ids: episode.external_ids.map(Into::into),
+                                    credits: vec![],
+                                },
+                            )
+                            .await?
+                        }
+                    };
+
+                media_by_id.insert(episode.id, episode_media);
+            }
+
+            media_by_id.insert(season.id, season_media);
+        }
+
+        media_by_id.insert(show.id, show_media);
+    }
+
+    for play in backup.watch_history {
+        let media = get_media(&media_by_id, play.media_id)?;
+        let watch_history = WatchHistory {
+            watched_at: play.watched_at,
+            media,
+        };
+
+        if get_watch_history_id(&tx, user_id, &watch_history)
+            .await?
+            .is_none()
+        {
+            insert_watch_history(&tx, user_id, &watch_history).await?;
+        }
+    }
+
+    for rating in backup.ratings {
+        let media = get_media(&media_by_id, rating.media_id)?;
+        upsert_rating(&tx, user_id, &media, rating.rating, &rating.rated_at).await?;
+    }
+
+    for list in backup.lists {
+        let existing_list_id = match list.kind {
+            ListKind::Watchlist | ListKind::Favorites => {
+                get_list_id_by_kind(&tx, user_id, list.kind).await?
+            }
+            ListKind::Personal => {
+                get_list_id_by_name(&tx, user_id, ListKind::Personal, &list.name).await?
+            }
+        };
+
+        let list_id = match existing_list_id {
+            Some(list_id) => list_id,
+            None => {
+                insert_list(
+                    &tx,
+                    user_id,
+                    &NewList {
+                        kind: list.kind,
+                        name: list.name,
+                        description: list.description,
+                        created_at: Some(list.created_at),
+                    },
+                )
+                .await?
+            }
+        };
+
+        for item in list.items {
+            let media = get_media(&media_by_id, item.media_id)?;
+            insert_list_item(&mut tx, &list_id, &media, Some(&item.listed_at)).await?;
+        }
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+async fn find_media<C: GenericClient>(
+    conn: &C,
+    external_ids: Option<&BackupExternalIds>,
+    media_kind: MediaKind,
+) -> anyhow::Result<Option<Media>> {
+    let Some(external_ids) = external_ids else {
+        return Ok(None);
+    };
+
+    let media = get_media_by_external_ids(conn, &external_ids.clone().into(), media_kind).await?;
+
+    Ok(media)
+}
+
+fn get_media(media_by_id: &HashMap<i32, Media>, id: i32) -> anyhow::Result<Media> {
+    let Some(media) = media_by_id.get(&id) else {
+        anyhow::bail!("backup references unknown media id {id}");
+    };
+
+    Ok(Media {
+        id: media.id,
+        kind: media.kind,
+    })
+}
@@ -1,23 +1,59 @@
-use deadpool_postgres::{Config, GenericClient, Pool, Runtime, tokio_postgres};
+use std::collections::HashSet;
+
+use deadpool_postgres::{
+    GenericClient, Manager, ManagerConfig, Pool, RecyclingMethod, Runtime, tokio_postgres,
+};
+use jiff::civil::Date;
+use postgres_native_tls::MakeTlsConnector;
 use postgres_types::{FromSql, ToSql};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
-use tokio_postgres::NoTls;
 
-use crate::{config::AppConfig, tmdb::TmdbId};
+use crate::{
+    config::{AppConfig, DbSslMode},
+    slug::media_slug,
+    tmdb::TmdbId,
+};
+
+#[derive(Debug, Error)]
+pub enum CreatePoolError {
+    #[error("failed to set up TLS")]
+    Tls(#[source] native_tls::Error),
+    #[error("failed to build the pool")]
+    Build(#[source] deadpool_postgres::BuildError),
+}
 
-pub fn create_pool(config: &AppConfig) -> Result<Pool, deadpool_postgres::CreatePoolError> {
-    let mut cfg = Config::new();
-    cfg.host = Some(config.db_host.clone());
-    cfg.port = Some(config.db_port.clone());
-    cfg.dbname = Some(config.db_name.clone());
-    cfg.user = Some(config.db_user.clone());
-    cfg.password = Some(config.db_password.clone());
+pub fn create_pool(config: &AppConfig) -> Result<Pool, CreatePoolError> {
+    let mut tls = native_tls::TlsConnector::builder();
+    for cert in config.db_ca_certs.iter() {
+        tls.add_root_certificate(cert.clone());
+    }
+    if config.db_ssl_mode != DbSslMode::VerifyFull {
+        // Like libpq, only encrypt.
+        tls.danger_accept_invalid_certs(true)
+            .danger_accept_invalid_hostnames(true);
+    }
+    let tls = tls.build().map_err(CreatePoolError::Tls)?;
+
+    let manager = Manager::from_config(
+        config.database.clone(),
+        MakeTlsConnector::new(tls),
+        ManagerConfig {
+            recycling_method: RecyclingMethod::Fast,
+        },
+    );
+
+    let mut builder = Pool::builder(manager)
+        .runtime(Runtime::Tokio1)
+        .wait_timeout(config.db_pool_timeout);
+    if let Some(size) = config.db_pool_size {
+        builder = builder.max_size(size);
+    }
 
-    Ok(cfg.create_pool(Some(Runtime::Tokio1), NoTls)?)
+    builder.build().map_err(CreatePoolError::Build)
 }
 
-#[derive(Debug, Deserialize, ToSql, FromSql)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ToSql, FromSql)]
 #[postgres(name = "media_kind", rename_all = "UPPERCASE")]
 #[serde(rename_all = "lowercase")]
 pub enum MediaKind {
@@ -33,7 +69,7 @@ pub struct Media {
     pub kind: MediaKind,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Default)]
 pub struct MediaExternalId {
     pub trakt_id: Option<i32>,
     pub trakt_slug: Option<String>,
@@ -101,16 +137,30 @@ pub async fn get_media_by_tmdb_id<C: GenericClient>(
     })
 }
 
-pub async fn get_media_by_trakt_id<C: GenericClient>(
+/// Looks up media of the given kind by its Trakt id, falling back to its TMDB,
+/// IMDb and TVDB ids, in that order.
+pub async fn get_media_by_external_ids<C: GenericClient>(
     conn: &C,
-    trakt_id: i32,
+    external_ids: &MediaExternalId,
+    media_kind: MediaKind,
 ) -> Result<Option<Media>, GetMediaIdError> {
     conn.query_opt(
-        "SELECT m.id, m.kind FROM media_external_id mei 
+        "SELECT m.id, m.kind FROM media_external_id mei
             INNER JOIN media m ON mei.media_id = m.id
-            WHERE mei.trakt_id = $1
+            WHERE m.kind = $1
+                AND (mei.trakt_id = $2 OR mei.tmdb_id = $3 OR mei.imdb_id = $4 OR mei.tvdb_id = $5)
+            ORDER BY mei.trakt_id = $2 DESC NULLS LAST,
+                mei.tmdb_id = $3 DESC NULLS LAST,
+                mei.imdb_id = $4 DESC NULLS LAST
+            LIMIT 1
             ",
-        &[&trakt_id],
+        &[
+            &media_kind,
+            &external_ids.trakt_id,
+            &external_ids.tmdb_id,
+            &external_ids.imdb_id,
+            &external_ids.tvdb_id,
+        ],
     )
     .await
     .map_err(GetMediaIdError)
@@ -178,8 +228,14 @@ pub enum InsertMovieError {
     InsertMedia(#[source] InsertMediaError),
     #[error("failed to insert media external id")]
     InsertMediaExternalId(#[source] InsertMediaExternalIdError),
+    #[error("failed to pick a slug")]
+    PickSlug(#[source] tokio_postgres::Error),
     #[error("failed to insert movie")]
     InsertMovie(#[source] tokio_postgres::Error),
+    #[error("failed to set metadata")]
+    SetMetadata(#[source] SetMetadataError),
+    #[error("failed to insert credits")]
+    InsertCredits(#[source] InsertCreditsError),
     #[error("failed to start transaction")]
     StartTransaction(#[source] tokio_postgres::Error),
     #[error("failed to commit transaction")]
@@ -187,12 +243,16 @@ pub enum InsertMovieError {
 }
 
 pub struct NewMovie {
+    /// Slug to start from instead of making one, e.g. from a backup.
+    pub slug: Option<String>,
     pub title: String,
     pub release_year: Option<i32>,
     pub overview: Option<String>,
     pub tagline: Option<String>,
     pub runtime: Option<i32>,
     pub external_ids: Option<MediaExternalId>,
+    pub metadata: MediaMetadata,
+    pub credits: Vec<NewCredit>,
 }
 
 pub async fn insert_movie<C: GenericClient>(
@@ -214,10 +274,24 @@ pub async fn insert_movie<C: GenericClient>(
             .map_err(InsertMovieError::InsertMediaExternalId)?;
     }
 
+    let slug = unique_slug(
+        &tx,
+        MediaKind::Movie,
+        &media_slug(
+            "movie",
+            &new_movie.title,
+            new_movie.release_year,
+            slug_seed(new_movie.slug.as_deref(), new_movie.external_ids.as_ref()),
+        ),
+    )
+    .await
+    .map_err(InsertMovieError::PickSlug)?;
+
     tx.execute(
-        "INSERT INTO movie (id, title, release_year, overview, tagline, runtime) VALUES ($1, $2, $3, $4, $5, $6)",
+        "INSERT INTO movie (id, slug, title, release_year, overview, tagline, runtime) VALUES ($1, $2, $3, $4, $5, $6, $7)",
         &[
             &media.id,
+            &slug,
             &new_movie.title,
             &new_movie.release_year,
             &new_movie.overview,
@@ -228,6 +302,14 @@ pub async fn insert_movie<C: GenericClient>(
     .await
     .map_err(InsertMovieError::InsertMovie)?;
 
+    set_media_metadata(&tx, &media, &new_movie.metadata)
+        .await
+        .map_err(InsertMovieError::SetMetadata)?;
+
+    replace_credits(&tx, &media, &new_movie.credits)
+        .await
+        .map_err(InsertMovieError::InsertCredits)?;
+
     tx.commit()
         .await
         .map_err(InsertMovieError::CommitTransaction)?;
@@ -244,8 +326,14 @@ pub enum InsertShowError {
     InsertMedia(#[source] InsertMediaError),
     #[error("failed to insert media external id")]
     InsertMediaExternalId(#[source] InsertMediaExternalIdError),
+    #[error("failed to pick a slug")]
+    PickSlug(#[source] tokio_postgres::Error),
     #[error("failed to insert show")]
     InsertShow(#[source] tokio_postgres::Error),
+    #[error("failed to set metadata")]
+    SetMetadata(#[source] SetMetadataError),
+    #[error("failed to insert credits")]
+    InsertCredits(#[source] InsertCreditsError),
     #[error("failed to insert season")]
     InsertSeason(#[source] InsertSeasonError),
     #[error("failed to start transaction")]
@@ -255,6 +343,8 @@ pub enum InsertShowError {
 }
 
 pub struct NewShow {
+    /// Slug to start from instead of making one, e.g. from a backup.
+    pub slug: Option<String>,
     pub title: String,
     pub release_year: Option<i32>,
     pub overview: Option<String>,
@@ -262,6 +352,8 @@ pub struct NewShow {
     pub episode_runtime: Option<i32>,
     pub external_ids: Option<MediaExternalId>,
     pub seasons: Option<Vec<NewSeason>>,
+    pub metadata: MediaMetadata,
+    pub credits: Vec<NewCredit>,
 }
 
 pub async fn insert_show<C: GenericClient>(
@@ -283,13 +375,34 @@ pub async fn insert_show<C: GenericClient>(
             .map_err(InsertShowError::InsertMediaExternalId)?;
     }
 
+    let slug = unique_slug(
+        &tx,
+        MediaKind::Show,
+        &media_slug(
+            "show",
+            &new_show.title,
+            new_show.release_year,
+            slug_seed(new_show.slug.as_deref(), new_show.external_ids.as_ref()),
+        ),
+    )
+    .await
+    .map_err(InsertShowError::PickSlug)?;
+
     tx.execute(
-        "INSERT INTO show (id, title, release_year, overview, tagline, episode_runtime) VALUES ($1, $2, $3, $4, $5, $6)",
-        &[&media.id, &new_show.title, &new_show.release_year, &new_show.overview, &new_show.tagline, &new_show.episode_runtime],
+        "INSERT INTO show (id, slug, title, release_year, overview, tagline, episode_runtime) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        &[&media.id, &slug, &new_show.title, &new_show.release_year, &new_show.overview, &new_show.tagline, &new_show.episode_runtime],
     )
     .await
     .map_err(InsertShowError::InsertShow)?;
 
+    set_media_metadata(&tx, &media, &new_show.metadata)
+        .await
+        .map_err(InsertShowError::SetMetadata)?;
+
+    replace_credits(&tx, &media, &new_show.credits)
+        .await
+        .map_err(InsertShowError::InsertCredits)?;
+
     if let Some(seasons) = &new_show.seasons {
         for season in seasons {
             insert_season(&mut tx, &media, season)
@@ -305,6 +418,216 @@ pub async fn insert_show<C: GenericClient>(
     Ok(media)
 }
 
+/// Details of a movie or show only known when it comes from TMDB.
+#[derive(Default)]
+pub struct MediaMetadata {
+    /// TMDB language the title was fetched in, see
+    /// [`get_media_without_metadata`].
+    pub language: Option<String>,
+    pub original_title: Option<String>,
+    pub original_language: Option<String>,
+    pub status: Option<String>,
+    pub production_countries: Vec<String>,
+    pub genres: Vec<NewTag>,
+    pub keywords: Vec<NewTag>,
+}
+
+/// A genre or keyword.
+pub struct NewTag {
+    pub tmdb_id: i32,
+    pub name: String,
+}
+
+#[derive(Debug, Error)]
+#[error("failed to set metadata")]
+pub struct SetMetadataError(#[source] tokio_postgres::Error);
+
+/// Sets the metadata of a movie or show, replacing its genres and keywords.
+pub async fn set_media_metadata<C: GenericClient>(
+    conn: &C,
+    media: &Media,
+    metadata: &MediaMetadata,
+) -> Result<(), SetMetadataError> {
+    conn.execute(
+        &format!(
+            "
+            UPDATE {} SET original_title = $2, original_language = $3, status = $4,
+            production_countries = $5, metadata_language = $6
+            WHERE id = $1
+            ",
+            slug_table(media.kind)
+        ),
+        &[
+            &media.id,
+            &metadata.original_title,
+            &metadata.original_language,
+            &metadata.status,
+            &metadata.production_countries,
+            &metadata.language,
+        ],
+    )
+    .await
+    .map_err(SetMetadataError)?;
+
+    replace_tags(conn, media, "genre", &metadata.genres).await?;
+    replace_tags(conn, media, "keyword", &metadata.keywords).await?;
+
+    Ok(())
+}
+
+/// Sets the title of a movie or show, keeping its slug so its URL doesn't
+/// change.
+pub async fn set_media_title<C: GenericClient>(
+    conn: &C,
+    media: &Media,
+    title: &str,
+) -> Result<(), SetMetadataError> {
+    conn.execute(
+        &format!(
+            "UPDATE {} SET title = $2 WHERE id = $1",
+            slug_table(media.kind)
+        ),
+        &[&media.id, &title],
+    )
+    .await
+    .map_err(SetMetadataError)
+    .map(|_| ())
+}
+
+/// Replaces the genres or keywords of a movie or show, adding the ones that
+/// aren't known yet.
+async fn replace_tags<C: GenericClient>(
+    conn: &C,
+    media: &Media,
+    table: &str,
+    tags: &[NewTag],
+) -> Result<(), SetMetadataError> {
+    conn.execute(
+        &format!("DELETE FROM media_{table} WHERE media_id = $1"),
+        &[&media.id],
+    )
+    .await
+    .map_err(SetMetadataError)?;
+
+    if tags.is_empty() {
+        return Ok(());
+    }
+
+    let tmdb_ids: Vec<i32> = tags.iter().map(|tag| tag.tmdb_id).collect();
+    let names: Vec<&str> = tags.iter().map(|tag| tag.name.as_str()).collect();
+
+    conn.execute(
+        &format!(
+            "
+            INSERT INTO {table} (tmdb_id, name)
+            SELECT DISTINCT ON (tmdb_id) tmdb_id, name
+            FROM UNNEST($1::INT[], $2::TEXT[]) AS t (tmdb_id, name)
+            ON CONFLICT (tmdb_id) DO UPDATE SET name = EXCLUDED.name
+            "
+        ),
+        &[&tmdb_ids, &names],
+    )
+    .await
+    .map_err(SetMetadataError)?;
+
+    conn.execute(
+        &format!(
+            "
+            INSERT INTO media_{table} (media_id, {table}_id)
+            SELECT DISTINCT $1::INT, t.id FROM {table} t
+            WHERE t.tmdb_id = ANY($2::INT[])
+            "
+        ),
+        &[&media.id, &tmdb_ids],
+    )
+    .await
+    .map_err(SetMetadataError)?;
+
+    Ok(())
+}
+
+/// The slug to make a new movie's or show's slug from, if any.
+fn slug_seed<'a>(
+    slug: Option<&'a str>,
+    external_ids: Option<&'a MediaExternalId>,
+) -> Option<&'a str> {
+    slug.or_else(|| external_ids.and_then(|ids| ids.trakt_slug.as_deref()))
+}
+
+fn slug_table(kind: MediaKind) -> &'static str {
+    match kind {
+        MediaKind::Movie => "movie",
+        MediaKind::Show => "show",
+        _ => unreachable!("only movies and shows have slugs"),
+    }
+}
+
+/// Returns the slug if it's free, otherwise the slug with the first free
+/// numbered suffix, e.g. `the-office-2005-2`.
+async fn unique_slug<C: GenericClient>(
+    conn: &C,
+    kind: MediaKind,
+    slug: &str,
+) -> Result<String, tokio_postgres::Error> {
+    // Slugs only have letters, digits and dashes, so they can't contain LIKE
+    // wildcards.
+    let taken: HashSet<String> = conn
+        .query(
+            &format!(
+                "SELECT slug FROM {} WHERE slug = $1 OR slug LIKE $1 || '-%'",
+                slug_table(kind)
+            ),
+            &[&slug],
+        )
+        .await?
+        .iter()
+        .map(|row| row.get(0))
+        .collect();
+
+    if !taken.contains(slug) {
+        return Ok(slug.to_string());
+    }
+
+    Ok((2..)
+        .map(|n| format!("{slug}-{n}"))
+        .find(|candidate| !taken.contains(candidate))
+        .expect("there's always a free suffix"))
+}
+
+#[derive(Debug, Error)]
+#[error("failed to query slug")]
+pub struct SlugError(#[source] tokio_postgres::Error);
+
+/// Looks up the id of the movie or show with the given slug.
+pub async fn get_media_id_by_slug<C: GenericClient>(
+    conn: &C,
+    kind: MediaKind,
+    slug: &str,
+) -> Result<Option<i32>, SlugError> {
+    conn.query_opt(
+        &format!("SELECT id FROM {} WHERE slug = $1", slug_table(kind)),
+        &[&slug],
+    )
+    .await
+    .map_err(SlugError)
+    .map(|opt_row| opt_row.map(|row| row.get(0)))
+}
+
+/// Looks up the slug of a movie or show.
+pub async fn get_media_slug<C: GenericClient>(
+    conn: &C,
+    kind: MediaKind,
+    id: i32,
+) -> Result<Option<String>, SlugError> {
+    conn.query_opt(
+        &format!("SELECT slug FROM {} WHERE id = $1", slug_table(kind)),
+        &[&id],
+    )
+    .await
+    .map_err(SlugError)
+    .map(|opt_row| opt_row.map(|row| row.get(0)))
+}
+
 pub async fn get_season_by_show_and_number<C: GenericClient>(
     conn: &C,
     show: &Media,
@@ -324,6 +647,25 @@ pub async fn get_season_by_show_and_number<C: GenericClient>(
     })
 }
 
+pub async fn get_episode_by_season_and_number<C: GenericClient>(
+    conn: &C,
+    season: &Media,
+    number: i32,
+) -> Result<Option<Media>, GetMediaIdError> {
+    conn.query_opt(
+        "SELECT ep.id, ep.kind FROM episode ep WHERE ep.season_id = $1 AND ep.number = $2",
+        &[&season.id, &number],
+    )
+    .await
+    .map_err(GetMediaIdError)
+    .map(|opt_row| {
+        opt_row.map(|row| Media {
+            id: row.get(0),
+            kind: row.get(1),
+        })
+    })
+}
+
 #[derive(Debug, Error)]
 pub enum InsertSeasonError {
     #[error("failed to insert media")]
@@ -383,7 +725,7 @@ pub async fn insert_season<C: GenericClient>(
 
     if let Some(episodes) = &new_season.episodes {
         for episode in episodes {
-            insert_episode(&mut tx, &show, &media, episode)
+            insert_episode(&mut tx, show, &media, episode)
                 .await
                 .map_err(InsertSeasonError::InsertEpisode)?;
         }
@@ -404,6 +746,8 @@ pub enum InsertEpisodeError {
     InsertMediaExternalId(#[source] InsertMediaExternalIdError),
     #[error("failed to insert episode")]
     InsertEpisode(#[source] tokio_postgres::Error),
+    #[error("failed to insert credits")]
+    InsertCredits(#[source] InsertCreditsError),
     #[error("failed to start transaction")]
     StartTransaction(#[source] tokio_postgres::Error),
     #[error("failed to commit transaction")]
@@ -416,6 +760,7 @@ pub struct NewEpisode {
     pub overview: Option<String>,
     pub runtime: Option<i32>,
     pub external_ids: Option<MediaExternalId>,
+    pub credits: Vec<NewCredit>,
 }
 
 pub async fn insert_episode<C: GenericClient>(
@@ -454,6 +799,10 @@ pub async fn insert_episode<C: GenericClient>(
     .await
     .map_err(InsertEpisodeError::InsertEpisode)?;
 
+    replace_credits(&tx, &media, &new_episode.credits)
+        .await
+        .map_err(InsertEpisodeError::InsertCredits)?;
+
     tx.commit()
         .await
         .map_err(InsertEpisodeError::CommitTransaction)?;
@@ -472,20 +821,59 @@ pub struct WatchHistory {
 
 pub async fn insert_watch_history<C: GenericClient>(
     conn: &C,
+    user_id: i32,
     watch_history: &WatchHistory,
-) -> Result<(), InsertWatchHistoryError> {
-    conn.execute(
-        "INSERT INTO watch_history (media_id, media_kind, watched_at) VALUES ($1, $2, $3)",
+) -> Result<i32, InsertWatchHistoryError> {
+    conn.query_one(
+        "INSERT INTO watch_history (user_id, media_id, media_kind, watched_at)
+        VALUES ($1, $2, $3, $4)
+        RETURNING id",
         &[
+            &user_id,
             &watch_history.media.id,
             &watch_history.media.kind,
             &watch_history.watched_at,
         ],
     )
     .await
-    .map_err(InsertWatchHistoryError)?;
+    .map_err(InsertWatchHistoryError)
+    .map(|row| row.get(0))
+}
 
-    Ok(())
+/// Returns the id of a play of the same media at the same time, if any.
+pub async fn get_watch_history_id<C: GenericClient>(
+    conn: &C,
+    user_id: i32,
+    watch_history: &WatchHistory,
+) -> Result<Option<i32>, GetWatchHistoryError> {
+    conn.query_opt(
+        "SELECT wh.id FROM watch_history wh
+        WHERE wh.user_id = $1 AND wh.media_id = $2 AND wh.watched_at = $3
+        ORDER BY wh.id LIMIT 1",
+        &[&user_id, &watch_history.media.id, &watch_history.watched_at],
+    )
+    .await
+    .map_err(GetWatchHistoryError)
+    .map(|opt_row| opt_row.map(|row| row.get(0)))
+}
+
+/// Returns whether the user played the media since `since`.
+pub async fn has_play_since<C: GenericClient>(
+    conn: &C,
+    user_id: i32,
+    media: &Media,
+    since: &jiff::Timestamp,
+) -> Result<bool, GetWatchHistoryError> {
+    conn.query_one(
+        "SELECT EXISTS (
+            SELECT 1 FROM watch_history wh
+            WHERE wh.user_id = $1 AND wh.media_id = $2 AND wh.watched_at >= $3
+        )",
+        &[&user_id, &media.id, since],
+    )
+    .await
+    .map_err(GetWatchHistoryError)
+    .map(|row| row.get(0))
 }
 
 #[derive(Debug, Error)]
@@ -496,7 +884,10 @@ pub struct GetWatchHistoryError(#[source] tokio_postgres::Error);
 pub enum WatchHistoryEntryMedia {
     Movie {
         id: i32,
+        slug: String,
         title: String,
+        /// Only set when it differs from the title.
+        original_title: Option<String>,
     },
     Episode {
         episode_id: i32,
@@ -504,52 +895,166 @@ pub enum WatchHistoryEntryMedia {
         episode_number: i32,
         season_number: i32,
         show_id: i32,
+        show_slug: String,
         show_title: String,
+        /// Only set when it differs from the show's title.
+        show_original_title: Option<String>,
     },
 }
 
 pub struct WatchHistoryEntry {
+    pub id: i32,
     pub watched_at: jiff::Timestamp,
     pub media: WatchHistoryEntryMedia,
 }
 
-pub enum GetWatchHistoryFilter {
-    Movie(i32),
-    Episode(i32),
-    Show(i32),
+/// Narrows down [`get_watch_history`]. Every given condition must match.
+#[derive(Debug, Clone, Default)]
+pub struct WatchHistoryFilter {
+    pub kind: Option<MediaKind>,
+    pub movie_id: Option<i32>,
+    pub show_id: Option<i32>,
+    /// Usually along with `show_id`.
+    pub season_number: Option<i32>,
+    pub episode_id: Option<i32>,
+    /// Plays at or after this time.
+    pub watched_from: Option<jiff::Timestamp>,
+    /// Plays before this time.
+    pub watched_until: Option<jiff::Timestamp>,
+    /// Matched case insensitively against the movie, episode and show titles.
+    pub text: Option<String>,
+}
+
+/// Position in the watch history, which is sorted by `watched_at` then `id`,
+/// both descending. Encoded cursors are opaque to API clients, they hold the
+/// play's timestamp in microseconds and its id.
+#[derive(Debug, Clone, Copy)]
+pub struct WatchHistoryCursor {
+    pub watched_at: jiff::Timestamp,
+    pub id: i32,
+}
+
+impl WatchHistoryCursor {
+    /// Encodes the cursor for URLs, e.g. `1700000000000000_42`.
+    pub fn encode(&self) -> String {
+        format!("{}_{}", self.watched_at.as_microsecond(), self.id)
+    }
+
+    /// Decodes a cursor made by [`WatchHistoryCursor::encode`].
+    pub fn decode(cursor: &str) -> Option<Self> {
+        let (watched_at, id) = cursor.split_once('_')?;
+
+        Some(WatchHistoryCursor {
+            watched_at: jiff::Timestamp::from_microsecond(watched_at.parse().ok()?).ok()?,
+            id: id.parse().ok()?,
+        })
+    }
+}
+
+/// Where a page of [`get_watch_history`] starts.
+#[derive(Debug, Clone, Copy)]
+pub enum WatchHistoryPage {
+    /// The plays right after the cursor in the history order, i.e. older ones.
+    After(WatchHistoryCursor),
+    /// The plays right before the cursor in the history order, i.e. newer
+    /// ones.
+    Before(WatchHistoryCursor),
+}
+
+impl WatchHistoryEntry {
+    pub fn cursor(&self) -> WatchHistoryCursor {
+        WatchHistoryCursor {
+            watched_at: self.watched_at,
+            id: self.id,
+        }
+    }
 }
 
+/// Returns up to `limit` plays matching the filter, most recent first. Starts
+/// with the most recent play unless a page is given.
 pub async fn get_watch_history<C: GenericClient>(
     conn: &C,
+    user_id: i32,
     limit: i64,
-    filter_opt: Option<GetWatchHistoryFilter>,
+    filter: &WatchHistoryFilter,
+    page: Option<WatchHistoryPage>,
 ) -> Result<Vec<WatchHistoryEntry>, GetWatchHistoryError> {
     let mut query = "
         SELECT wh.watched_at, wh.media_kind, wh.media_id,
         COALESCE(ep.title, mo.title) AS title,
         ep.number AS episode_number, se.number AS season_number,
-        sh.id AS show_id, sh.title AS show_title FROM watch_history wh
+        sh.id AS show_id, sh.title AS show_title, wh.id,
+        COALESCE(sh.slug, mo.slug) AS slug,
+        NULLIF(COALESCE(sh.original_title, mo.original_title), COALESCE(sh.title, mo.title))
+            AS original_title
+        FROM watch_history wh
         LEFT JOIN movie mo ON wh.media_id = mo.id AND wh.media_kind = 'MOVIE'
         LEFT JOIN episode ep ON wh.media_id = ep.id AND wh.media_kind = 'EPISODE'
         LEFT JOIN season se ON ep.season_id = se.id AND ep.show_id = se.show_id
         LEFT JOIN show sh ON ep.show_id = sh.id
-        WHERE 1 > 0"
+        WHERE wh.user_id = $2"
         .to_string();
-    let mut args: Vec<Box<dyn ToSql + Sync + Send>> = vec![Box::new(limit)];
+    let mut args: Vec<Box<dyn ToSql + Sync + Send>> = vec![Box::new(limit), Box::new(user_id)];
 
     let mut where_stmt = String::new();
-    if let Some(filter) = filter_opt {
-        let (stmt, id) = match filter {
-            GetWatchHistoryFilter::Movie(id) => (" AND mo.id = $2", id),
-            GetWatchHistoryFilter::Episode(id) => (" AND ep.id = $2", id),
-            GetWatchHistoryFilter::Show(id) => (" AND sh.id = $2", id),
-        };
-        where_stmt += stmt;
-        args.push(Box::new(id));
+    let mut add_condition = |condition: &str, arg: Box<dyn ToSql + Sync + Send>| {
+        args.push(arg);
+        where_stmt += &condition.replace('?', &format!("${}", args.len()));
+    };
+
+    if let Some(kind) = filter.kind {
+        add_condition(" AND wh.media_kind = ?", Box::new(kind));
+    }
+    if let Some(movie_id) = filter.movie_id {
+        add_condition(" AND mo.id = ?", Box::new(movie_id));
+    }
+    if let Some(show_id) = filter.show_id {
+        add_condition(" AND sh.id = ?", Box::new(show_id));
+    }
+    if let Some(season_number) = filter.season_number {
+        add_condition(" AND se.number = ?", Box::new(season_number));
+    }
+    if let Some(episode_id) = filter.episode_id {
+        add_condition(" AND ep.id = ?", Box::new(episode_id));
+    }
+    if let Some(watched_from) = filter.watched_from {
+        add_condition(" AND wh.watched_at >= ?", Box::new(watched_from));
+    }
+    if let Some(watched_until) = filter.watched_until {
+        add_condition(" AND wh.watched_at < ?", Box::new(watched_until));
+    }
+    if let Some(text) = &filter.text {
+        add_condition(
+            " AND (COALESCE(ep.title, mo.title) ILIKE ? OR sh.title ILIKE ?)",
+            Box::new(format!("%{}%", escape_like(text))),
+        );
     }
 
+    let cursor = match page {
+        Some(WatchHistoryPage::After(cursor)) => Some(("<", cursor)),
+        Some(WatchHistoryPage::Before(cursor)) => Some((">", cursor)),
+        None => None,
+    };
+    if let Some((op, cursor)) = cursor {
+        where_stmt += &format!(
+            " AND (wh.watched_at, wh.id) {op} (${}, ${})",
+            args.len() + 1,
+            args.len() + 2
+        );
+        args.push(Box::new(cursor.watched_at));
+        args.push(Box::new(cursor.id));
+    }
+
+    // Pages before the cursor are the plays closest to it, so they're
+    // fetched oldest first and reversed below.
+    let before = matches!(page, Some(WatchHistoryPage::Before(_)));
+
     query += &where_stmt;
-    query += " ORDER BY wh.watched_at DESC LIMIT $1";
+    query += if before {
+        " ORDER BY wh.watched_at ASC, wh.id ASC LIMIT $1"
+    } else {
+        " ORDER BY wh.watched_at DESC, wh.id DESC LIMIT $1"
+    };
 
     // ugly.
     let args: Vec<&(dyn ToSql + Sync)> = args
@@ -562,7 +1067,7 @@ pub async fn get_watch_history<C: GenericClient>(
         .await
         .map_err(GetWatchHistoryError)?;
 
-    let history = rows
+    let mut history: Vec<WatchHistoryEntry> = rows
         .iter()
         .map(|row| {
             let media_kind: MediaKind = row.get(1);
@@ -570,7 +1075,9 @@ pub async fn get_watch_history<C: GenericClient>(
             let media = match media_kind {
                 MediaKind::Movie => WatchHistoryEntryMedia::Movie {
                     id: row.get(2),
+                    slug: row.get(9),
                     title: row.get(3),
+                    original_title: row.get(10),
                 },
                 MediaKind::Episode => WatchHistoryEntryMedia::Episode {
                     episode_id: row.get(2),
@@ -578,21 +1085,280 @@ pub async fn get_watch_history<C: GenericClient>(
                     episode_number: row.get(4),
                     season_number: row.get(5),
                     show_id: row.get(6),
+                    show_slug: row.get(9),
                     show_title: row.get(7),
+                    show_original_title: row.get(10),
                 },
                 _ => unreachable!("invalid media_kind in watch_history table"),
             };
 
             WatchHistoryEntry {
+                id: row.get(8),
                 watched_at: row.get(0),
                 media,
             }
         })
         .collect();
 
+    if before {
+        history.reverse();
+    }
+
     Ok(history)
 }
 
+pub struct WatchedShow {
+    pub slug: String,
+    pub title: String,
+}
+
+/// Returns the shows the user watched episodes of, sorted by title.
+pub async fn get_watched_shows<C: GenericClient>(
+    conn: &C,
+    user_id: i32,
+) -> Result<Vec<WatchedShow>, GetWatchHistoryError> {
+    conn.query(
+        "SELECT sh.slug, sh.title FROM show sh
+        WHERE EXISTS (
+            SELECT 1 FROM watch_history wh
+            INNER JOIN episode ep ON wh.media_id = ep.id AND wh.media_kind = 'EPISODE'
+            WHERE wh.user_id = $1 AND ep.show_id = sh.id
+        )
+        ORDER BY sh.title",
+        &[&user_id],
+    )
+    .await
+    .map_err(GetWatchHistoryError)
+    .map(|rows| {
+        rows.iter()
+            .map(|row| WatchedShow {
+                slug: row.get(0),
+                title: row.get(1),
+            })
+            .collect()
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatsDimension {
+    Genre,
+    Language,
+    Country,
+}
+
+impl StatsDimension {
+    /// Values of the dimension for a row of the `plays` CTE of
+    /// [`get_watch_stats`].
+    fn values(self) -> &'static str {
+        match self {
+            StatsDimension::Genre => {
+                "SELECT ge.name FROM media_genre mg
+                INNER JOIN genre ge ON ge.id = mg.genre_id
+                WHERE mg.media_id = plays.title_id"
+            }
+            StatsDimension::Language => {
+                "SELECT plays.original_language WHERE plays.original_language IS NOT NULL"
+            }
+            StatsDimension::Country => "SELECT UNNEST(plays.production_countries)",
+        }
+    }
+}
+
+pub struct WatchStats {
+    pub value: String,
+    pub play_count: i64,
+    /// Sum of the runtimes of the plays, missing ones counting as zero.
+    pub minutes: i64,
+}
+
+impl WatchStats {
+    pub fn hours(&self) -> String {
+        format!("{:.1}", self.minutes as f64 / 60.0)
+    }
+}
+
+/// Returns the plays of the user grouped by the values of the dimension,
+/// sorted by time spent. A play counts towards every value of its movie or
+/// show, e.g. each of its genres.
+pub async fn get_watch_stats<C: GenericClient>(
+    conn: &C,
+    user_id: i32,
+    dimension: StatsDimension,
+) -> Result<Vec<WatchStats>, GetWatchHistoryError> {
+    conn.query(
+        &format!(
+            "
+            WITH plays AS (
+                SELECT
+                    COALESCE(ep.show_id, wh.media_id) AS title_id,
+                    COALESCE(mo.runtime, ep.runtime, sh.episode_runtime, 0) AS runtime,
+                    COALESCE(mo.original_language, sh.original_language) AS original_language,
+                    COALESCE(mo.production_countries, sh.production_countries)
+                        AS production_countries
+                FROM watch_history wh
+                LEFT JOIN movie mo ON mo.id = wh.media_id AND wh.media_kind = 'MOVIE'
+                LEFT JOIN episode ep ON ep.id = wh.media_id AND wh.media_kind = 'EPISODE'
+                LEFT JOIN show sh ON sh.id = ep.show_id
+                WHERE wh.user_id = $1
+            )
+            SELECT dim.value, COUNT(*), SUM(plays.runtime)::BIGINT
+            FROM plays
+            INNER JOIN LATERAL ({}) dim (value) ON TRUE
+            GROUP BY dim.value
+            ORDER BY 3 DESC, 2 DESC, dim.value
+            ",
+            dimension.values()
+        ),
+        &[&user_id],
+    )
+    .await
+    .map_err(GetWatchHistoryError)
+    .map(|rows| {
+        rows.iter()
+            .map(|row| WatchStats {
+                value: row.get(0),
+                play_count: row.get(1),
+                minutes: row.get(2),
+            })
+            .collect()
+    })
+}
+
+/// Escapes the LIKE wildcards in a string, to match it literally.
+fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSql, FromSql)]
+#[postgres(name = "list_kind", rename_all = "UPPERCASE")]
+#[serde(rename_all = "lowercase")]
+pub enum ListKind {
+    Watchlist,
+    Favorites,
+    Personal,
+}
+
+#[derive(Debug, Error)]
+#[error("failed to get list")]
+pub struct GetListError(#[source] tokio_postgres::Error);
+
+/// Returns the id of the user's first list of the given kind. Mostly useful
+/// for the built-in watchlist and favorites lists.
+pub async fn get_list_id_by_kind<C: GenericClient>(
+    conn: &C,
+    user_id: i32,
+    kind: ListKind,
+) -> Result<Option<i32>, GetListError> {
+    conn.query_opt(
+        "SELECT l.id FROM list l WHERE l.user_id = $1 AND l.kind = $2 ORDER BY l.id LIMIT 1",
+        &[&user_id, &kind],
+    )
+    .await
+    .map_err(GetListError)
+    .map(|opt_row| opt_row.map(|row| row.get(0)))
+}
+
+pub async fn get_list_id_by_name<C: GenericClient>(
+    conn: &C,
+    user_id: i32,
+    kind: ListKind,
+    name: &str,
+) -> Result<Option<i32>, GetListError> {
+    conn.query_opt(
+        "SELECT l.id FROM list l WHERE l.user_id = $1 AND l.kind = $2 AND l.name = $3
+        ORDER BY l.id LIMIT 1",
+        &[&user_id, &kind, &name],
+    )
+    .await
+    .map_err(GetListError)
+    .map(|opt_row| opt_row.map(|row| row.get(0)))
+}
+
+#[derive(Debug, Error)]
+#[error("failed to insert list")]
+pub struct InsertListError(#[source] tokio_postgres::Error);
+
+pub struct NewList {
+    pub kind: ListKind,
+    pub name: String,
+    pub description: Option<String>,
+    pub created_at: Option<jiff::Timestamp>,
+}
+
+pub async fn insert_list<C: GenericClient>(
+    conn: &C,
+    user_id: i32,
+    new_list: &NewList,
+) -> Result<i32, InsertListError> {
+    conn.query_one(
+        "INSERT INTO list (user_id, kind, name, description, created_at)
+        VALUES ($1, $2, $3, $4, COALESCE($5, NOW()))
+        RETURNING id",
+        &[
+            &user_id,
+            &new_list.kind,
+            &new_list.name,
+            &new_list.description,
+            &new_list.created_at,
+        ],
+    )
+    .await
+    .map_err(InsertListError)
+    .map(|row| row.get(0))
+}
+
+pub struct MoviePlay {
+    pub title: String,
+    pub release_year: Option<i32>,
+    pub imdb_id: Option<String>,
+    pub tmdb_id: Option<i32>,
+    pub watched_at: jiff::Timestamp,
+    pub rating: Option<i32>,
+    /// Whether the movie had been played before this play.
+    pub rewatch: bool,
+}
+
+/// Returns every movie play of a user, oldest first, along with the movie's
+/// external ids and the user's rating.
+pub async fn get_movie_plays<C: GenericClient>(
+    conn: &C,
+    user_id: i32,
+) -> Result<Vec<MoviePlay>, GetWatchHistoryError> {
+    let rows = conn
+        .query(
+            "
+            SELECT mo.title, mo.release_year, mei.imdb_id, mei.tmdb_id, wh.watched_at, ra.rating,
+            ROW_NUMBER() OVER (PARTITION BY wh.media_id ORDER BY wh.watched_at, wh.id) > 1 AS rewatch
+            FROM watch_history wh
+            INNER JOIN movie mo ON mo.id = wh.media_id
+            LEFT JOIN media_external_id mei ON mei.media_id = mo.id
+            LEFT JOIN rating ra ON ra.media_id = mo.id AND ra.user_id = wh.user_id
+            WHERE wh.user_id = $1 AND wh.media_kind = 'MOVIE'
+            ORDER BY wh.watched_at, wh.id
+            ",
+            &[&user_id],
+        )
+        .await
+        .map_err(GetWatchHistoryError)?;
+
+    let plays = rows
+        .iter()
+        .map(|row| MoviePlay {
+            title: row.get(0),
+            release_year: row.get(1),
+            imdb_id: row.get(2),
+            tmdb_id: row.get(3),
+            watched_at: row.get(4),
+            rating: row.get(5),
+            rewatch: row.get(6),
+        })
+        .collect();
+
+    Ok(plays)
+}
+
 #[derive(Debug, Error)]
 #[error("failed to insert list item")]
 pub struct InsertListItemError(#[source] tokio_postgres::Error);
@@ -604,7 +1370,8 @@ pub async fn insert_list_item<C: GenericClient>(
     listed_at: Option<&jiff::Timestamp>,
 ) -> Result<(), InsertListItemError> {
     conn.execute(
-        "INSERT INTO list_item (list_id, media_id, media_kind, listed_at) VALUES ($1, $2, $3, $4)",
+        "INSERT INTO list_item (list_id, media_id, media_kind, listed_at) VALUES ($1, $2, $3, COALESCE($4, NOW()))
+        ON CONFLICT (list_id, media_id) DO NOTHING",
         &[&list_id, &media.id, &media.kind, &listed_at],
     )
     .await
@@ -612,3 +1379,2212 @@ pub async fn insert_list_item<C: GenericClient>(
 
     Ok(())
 }
+
+#[derive(Debug, Error)]
+#[error("failed to delete list items")]
+pub struct DeleteListItemsError(#[source] tokio_postgres::Error);
+
+pub async fn delete_list_items<C: GenericClient>(
+    conn: &C,
+    list_id: i32,
+    media_ids: &[i32],
+) -> Result<(), DeleteListItemsError> {
+    conn.execute(
+        "DELETE FROM list_item WHERE list_id = $1 AND media_id = ANY($2)",
+        &[&list_id, &media_ids],
+    )
+    .await
+    .map_err(DeleteListItemsError)?;
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+#[error("failed to upsert rating")]
+pub struct UpsertRatingError(#[source] tokio_postgres::Error);
+
+/// Sets the rating (1 to 10) for a media, replacing any previous rating.
+pub async fn upsert_rating<C: GenericClient>(
+    conn: &C,
+    user_id: i32,
+    media: &Media,
+    rating: i32,
+    rated_at: &jiff::Timestamp,
+) -> Result<(), UpsertRatingError> {
+    conn.execute(
+        "INSERT INTO rating (user_id, media_id, media_kind, rating, rated_at)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (user_id, media_id) DO UPDATE SET rating = EXCLUDED.rating,
+        rated_at = EXCLUDED.rated_at",
+        &[&user_id, &media.id, &media.kind, &rating, &rated_at],
+    )
+    .await
+    .map_err(UpsertRatingError)?;
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+#[error("failed to query import review items")]
+pub struct ImportReviewItemError(#[source] tokio_postgres::Error);
+
+pub struct NewImportReviewItem {
+    pub source: String,
+    pub title: String,
+    pub release_year: Option<i32>,
+    pub url: Option<String>,
+    pub watched_at: Option<jiff::Timestamp>,
+    pub rating: Option<i32>,
+    pub rated_at: Option<jiff::Timestamp>,
+    pub list_id: Option<i32>,
+    pub listed_at: Option<jiff::Timestamp>,
+}
+
+pub struct ImportReviewItem {
+    pub id: i32,
+    pub source: String,
+    pub title: String,
+    pub release_year: Option<i32>,
+    pub url: Option<String>,
+    pub watched_at: Option<jiff::Timestamp>,
+    pub rating: Option<i32>,
+    pub rated_at: Option<jiff::Timestamp>,
+    pub list_id: Option<i32>,
+    pub listed_at: Option<jiff::Timestamp>,
+}
+
+/// Queues an entry for review, unless the same one is queued already, e.g.
+/// when the same file is imported twice.
+pub async fn insert_import_review_item<C: GenericClient>(
+    conn: &C,
+    user_id: i32,
+    item: &NewImportReviewItem,
+) -> Result<(), ImportReviewItemError> {
+    conn.execute(
+        "INSERT INTO import_review_item
+        (user_id, source, title, release_year, url, watched_at, rating, rated_at, list_id,
+        listed_at)
+        SELECT $1, $2, $3, $4, $5, $6, $7, $8, $9, $10
+        WHERE NOT EXISTS (
+            SELECT 1 FROM import_review_item iri
+            WHERE iri.user_id = $1 AND iri.source = $2 AND iri.title = $3
+            AND iri.release_year IS NOT DISTINCT FROM $4::INT
+            AND iri.watched_at IS NOT DISTINCT FROM $6::TIMESTAMPTZ
+            AND iri.rating IS NOT DISTINCT FROM $7::INT
+            AND iri.rated_at IS NOT DISTINCT FROM $8::TIMESTAMPTZ
+            AND iri.list_id IS NOT DISTINCT FROM $9::INT
+        )",
+        &[
+            &user_id,
+            &item.source,
+            &item.title,
+            &item.release_year,
+            &item.url,
+            &item.watched_at,
+            &item.rating,
+            &item.rated_at,
+            &item.list_id,
+            &item.listed_at,
+        ],
+    )
+    .await
+    .map_err(ImportReviewItemError)?;
+
+    Ok(())
+}
+
+const IMPORT_REVIEW_ITEM_COLUMNS: &str = "iri.id, iri.source, iri.title, iri.release_year, iri.url,
+    iri.watched_at, iri.rating, iri.rated_at, iri.list_id, iri.listed_at";
+
+fn import_review_item_from_row(row: &tokio_postgres::Row) -> ImportReviewItem {
+    ImportReviewItem {
+        id: row.get(0),
+        source: row.get(1),
+        title: row.get(2),
+        release_year: row.get(3),
+        url: row.get(4),
+        watched_at: row.get(5),
+        rating: row.get(6),
+        rated_at: row.get(7),
+        list_id: row.get(8),
+        listed_at: row.get(9),
+    }
+}
+
+pub async fn get_import_review_items<C: GenericClient>(
+    conn: &C,
+    user_id: i32,
+) -> Result<Vec<ImportReviewItem>, ImportReviewItemError> {
+    let rows = conn
+        .query(
+            &format!(
+                "SELECT {IMPORT_REVIEW_ITEM_COLUMNS} FROM import_review_item iri
+                WHERE iri.user_id = $1
+                ORDER BY iri.source, iri.title, iri.release_year, iri.id"
+            ),
+            &[&user_id],
+        )
+        .await
+        .map_err(ImportReviewItemError)?;
+
+    Ok(rows.iter().map(import_review_item_from_row).collect())
+}
+
+/// Returns the number of review items of all users by source.
+pub async fn count_import_review_items<C: GenericClient>(
+    conn: &C,
+) -> Result<Vec<(String, i64)>, ImportReviewItemError> {
+    conn.query(
+        "SELECT source, COUNT(*) FROM import_review_item GROUP BY source",
+        &[],
+    )
+    .await
+    .map_err(ImportReviewItemError)
+    .map(|rows| rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+}
+
+/// Returns the user's review item with the given id along with every other
+/// pending item of theirs from the same source referring to the same title
+/// and year.
+pub async fn get_related_import_review_items<C: GenericClient>(
+    conn: &C,
+    user_id: i32,
+    id: i32,
+) -> Result<Vec<ImportReviewItem>, ImportReviewItemError> {
+    let rows = conn
+        .query(
+            &format!(
+                "SELECT {IMPORT_REVIEW_ITEM_COLUMNS} FROM import_review_item iri
+                INNER JOIN import_review_item target ON target.user_id = iri.user_id
+                    AND target.source = iri.source
+                    AND target.title = iri.title
+                    AND target.release_year IS NOT DISTINCT FROM iri.release_year
+                WHERE target.user_id = $1 AND target.id = $2
+                ORDER BY iri.id"
+            ),
+            &[&user_id, &id],
+        )
+        .await
+        .map_err(ImportReviewItemError)?;
+
+    Ok(rows.iter().map(import_review_item_from_row).collect())
+}
+
+pub async fn delete_import_review_items<C: GenericClient>(
+    conn: &C,
+    ids: &[i32],
+) -> Result<(), ImportReviewItemError> {
+    conn.execute("DELETE FROM import_review_item WHERE id = ANY($1)", &[&ids])
+        .await
+        .map_err(ImportReviewItemError)?;
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+#[error("failed to query trakt sync state")]
+pub struct TraktSyncError(#[source] tokio_postgres::Error);
+
+pub struct TraktSyncState {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub expires_at: jiff::Timestamp,
+    /// When the last successful history pull started, none if history was
+    /// never pulled.
+    pub history_pulled_at: Option<jiff::Timestamp>,
+    /// When the history last changed on Trakt, as of the last pull.
+    pub history_changed_at: Option<jiff::Timestamp>,
+}
+
+/// Returns the user's Trakt credentials, none if they never logged into
+/// Trakt.
+pub async fn get_trakt_sync_state<C: GenericClient>(
+    conn: &C,
+    user_id: i32,
+) -> Result<Option<TraktSyncState>, TraktSyncError> {
+    conn.query_opt(
+        "SELECT ts.access_token, ts.refresh_token, ts.expires_at, ts.history_pulled_at,
+        ts.history_changed_at
+        FROM trakt_sync ts WHERE ts.user_id = $1",
+        &[&user_id],
+    )
+    .await
+    .map_err(TraktSyncError)
+    .map(|opt_row| {
+        opt_row.map(|row| TraktSyncState {
+            access_token: row.get(0),
+            refresh_token: row.get(1),
+            expires_at: row.get(2),
+            history_pulled_at: row.get(3),
+            history_changed_at: row.get(4),
+        })
+    })
+}
+
+pub async fn set_trakt_tokens<C: GenericClient>(
+    conn: &C,
+    user_id: i32,
+    access_token: &str,
+    refresh_token: &str,
+    expires_at: &jiff::Timestamp,
+) -> Result<(), TraktSyncError> {
+    conn.execute(
+        "INSERT INTO trakt_sync (user_id, access_token, refresh_token, expires_at)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (user_id) DO UPDATE SET access_token = EXCLUDED.access_token,
+        refresh_token = EXCLUDED.refresh_token, expires_at = EXCLUDED.expires_at",
+        &[&user_id, &access_token, &refresh_token, &expires_at],
+    )
+    .await
+    .map_err(TraktSyncError)?;
+
+    Ok(())
+}
+
+pub async fn set_trakt_history_pulled_at<C: GenericClient>(
+    conn: &C,
+    user_id: i32,
+    pulled_at: &jiff::Timestamp,
+    changed_at: &jiff::Timestamp,
+) -> Result<(), TraktSyncError> {
+    conn.execute(
+        "UPDATE trakt_sync SET history_pulled_at = $2, history_changed_at = $3
+        WHERE user_id = $1",
+        &[&user_id, &pulled_at, &changed_at],
+    )
+    .await
+    .map_err(TraktSyncError)?;
+
+    Ok(())
+}
+
+pub async fn set_watch_history_trakt_synced<C: GenericClient>(
+    conn: &C,
+    ids: &[i32],
+) -> Result<(), TraktSyncError> {
+    conn.execute(
+        "UPDATE watch_history SET trakt_synced_at = NOW() WHERE id = ANY($1)",
+        &[&ids],
+    )
+    .await
+    .map_err(TraktSyncError)?;
+
+    Ok(())
+}
+
+pub async fn set_list_items_trakt_synced<C: GenericClient>(
+    conn: &C,
+    list_id: i32,
+    media_ids: &[i32],
+) -> Result<(), TraktSyncError> {
+    conn.execute(
+        "UPDATE list_item SET trakt_synced_at = NOW() WHERE list_id = $1 AND media_id = ANY($2)",
+        &[&list_id, &media_ids],
+    )
+    .await
+    .map_err(TraktSyncError)?;
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+#[error("failed to claim webhook session")]
+pub struct ClaimWebhookSessionError(#[source] tokio_postgres::Error);
+
+/// Records that a webhook event was received for a playback session. Returns
+/// false if the session was already claimed in the last `window_hours`, in
+/// which case the event is a duplicate.
+pub async fn claim_webhook_session<C: GenericClient>(
+    conn: &C,
+    user_id: i32,
+    source: &str,
+    session_id: &str,
+    window_hours: i32,
+) -> Result<bool, ClaimWebhookSessionError> {
+    conn.query_opt(
+        "INSERT INTO webhook_session (user_id, source, session_id) VALUES ($1, $2, $3)
+        ON CONFLICT (user_id, source, session_id) DO UPDATE SET received_at = EXCLUDED.received_at
+        WHERE webhook_session.received_at < NOW() - make_interval(hours => $4)
+        RETURNING 1",
+        &[&user_id, &source, &session_id, &window_hours],
+    )
+    .await
+    .map_err(ClaimWebhookSessionError)
+    .map(|opt_row| opt_row.is_some())
+}
+
+/// Deletes the sessions claimed more than `window_hours` ago, which can't
+/// cause events to be ignored anymore. Returns how many were deleted.
+pub async fn delete_expired_webhook_sessions<C: GenericClient>(
+    conn: &C,
+    window_hours: i32,
+) -> Result<u64, ClaimWebhookSessionError> {
+    conn.execute(
+        "DELETE FROM webhook_session WHERE received_at < NOW() - make_interval(hours => $1)",
+        &[&window_hours],
+    )
+    .await
+    .map_err(ClaimWebhookSessionError)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ToSql, FromSql)]
+#[postgres(name = "scrobble_state", rename_all = "UPPERCASE")]
+pub enum ScrobbleState {
+    Playing,
+    Paused,
+}
+
+#[derive(Debug, Error)]
+#[error("failed to query scrobble sessions")]
+pub struct ScrobbleSessionError(#[source] tokio_postgres::Error);
+
+pub struct ScrobbleSession {
+    pub state: ScrobbleState,
+    /// Percent of the movie or episode watched so far.
+    pub progress: f32,
+    pub updated_at: jiff::Timestamp,
+    pub media: WatchHistoryEntryMedia,
+}
+
+/// Starts a session of the user for the media or updates their current one.
+pub async fn upsert_scrobble_session<C: GenericClient>(
+    conn: &C,
+    user_id: i32,
+    media: &Media,
+    state: ScrobbleState,
+    progress: f32,
+) -> Result<(), ScrobbleSessionError> {
+    conn.execute(
+        "INSERT INTO scrobble_session (user_id, media_id, media_kind, state, progress)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (user_id, media_id) DO UPDATE SET state = EXCLUDED.state,
+        progress = EXCLUDED.progress, updated_at = NOW()",
+        &[&user_id, &media.id, &media.kind, &state, &progress],
+    )
+    .await
+    .map_err(ScrobbleSessionError)?;
+
+    Ok(())
+}
+
+/// Ends the user's session for the media, returning whether there was one.
+pub async fn delete_scrobble_session<C: GenericClient>(
+    conn: &C,
+    user_id: i32,
+    media: &Media,
+) -> Result<bool, ScrobbleSessionError> {
+    let count = conn
+        .execute(
+            "DELETE FROM scrobble_session WHERE user_id = $1 AND media_id = $2",
+            &[&user_id, &media.id],
+        )
+        .await
+        .map_err(ScrobbleSessionError)?;
+
+    Ok(count > 0)
+}
+
+/// Returns the user's sessions updated since `active_since`, most recent
+/// first. Older sessions were most likely abandoned without a stop.
+pub async fn get_scrobble_sessions<C: GenericClient>(
+    conn: &C,
+    user_id: i32,
+    active_since: &jiff::Timestamp,
+) -> Result<Vec<ScrobbleSession>, ScrobbleSessionError> {
+    let rows = conn
+        .query(
+            "SELECT ss.state, ss.progress, ss.updated_at, ss.media_kind, ss.media_id,
+            COALESCE(ep.title, mo.title) AS title,
+            ep.number AS episode_number, se.number AS season_number,
+            sh.id AS show_id, sh.title AS show_title,
+            COALESCE(sh.slug, mo.slug) AS slug,
+            NULLIF(COALESCE(sh.original_title, mo.original_title), COALESCE(sh.title, mo.title))
+                AS original_title
+            FROM scrobble_session ss
+            LEFT JOIN movie mo ON ss.media_id = mo.id AND ss.media_kind = 'MOVIE'
+            LEFT JOIN episode ep ON ss.media_id = ep.id AND ss.media_kind = 'EPISODE'
+            LEFT JOIN season se ON ep.season_id = se.id AND ep.show_id = se.show_id
+            LEFT JOIN show sh ON ep.show_id = sh.id
+            WHERE ss.user_id = $1 AND ss.updated_at >= $2
+            ORDER BY ss.updated_at DESC",
+            &[&user_id, &active_since],
+        )
+        .await
+        .map_err(ScrobbleSessionError)?;
+
+    let sessions = rows
+        .iter()
+        .map(|row| {
+            let media_kind: MediaKind = row.get(3);
+
+            let media = match media_kind {
+                MediaKind::Movie => WatchHistoryEntryMedia::Movie {
+                    id: row.get(4),
+                    slug: row.get(10),
+                    title: row.get(5),
+                    original_title: row.get(11),
+                },
+                MediaKind::Episode => WatchHistoryEntryMedia::Episode {
+                    episode_id: row.get(4),
+                    episode_title: row.get(5),
+                    episode_number: row.get(6),
+                    season_number: row.get(7),
+                    show_id: row.get(8),
+                    show_slug: row.get(10),
+                    show_title: row.get(9),
+                    show_original_title: row.get(11),
+                },
+                _ => unreachable!("invalid media_kind in scrobble_session table"),
+            };
+
+            ScrobbleSession {
+                state: row.get(0),
+                progress: row.get(1),
+                updated_at: row.get(2),
+                media,
+            }
+        })
+        .collect();
+
+    Ok(sessions)
+}
+
+#[derive(Debug, Error)]
+#[error("failed to get media details")]
+pub struct GetMediaDetailsError(#[source] tokio_postgres::Error);
+
+pub struct MovieDetails {
+    pub id: i32,
+    pub slug: String,
+    pub title: String,
+    pub release_year: Option<i32>,
+    pub overview: Option<String>,
+    pub tagline: Option<String>,
+    pub runtime: Option<i32>,
+    pub play_count: i64,
+}
+
+pub async fn get_movie_details<C: GenericClient>(
+    conn: &C,
+    user_id: i32,
+    movie_id: i32,
+) -> Result<Option<MovieDetails>, GetMediaDetailsError> {
+    conn.query_opt(
+        "
+        SELECT mo.id, mo.title, mo.release_year, COUNT(wh.watched_at) AS play_count,
+        mo.overview, mo.tagline, mo.runtime, mo.slug FROM movie mo
+        LEFT JOIN watch_history wh ON mo.id = wh.media_id AND wh.media_kind = 'MOVIE'
+            AND wh.user_id = $1
+        WHERE mo.id = $2
+        GROUP BY mo.id
+        ",
+        &[&user_id, &movie_id],
+    )
+    .await
+    .map_err(GetMediaDetailsError)
+    .map(|opt_row| {
+        opt_row.map(|row| MovieDetails {
+            id: row.get(0),
+            title: row.get(1),
+            release_year: row.get(2),
+            play_count: row.get(3),
+            overview: row.get(4),
+            tagline: row.get(5),
+            runtime: row.get(6),
+            slug: row.get(7),
+        })
+    })
+}
+
+/// Metadata of a movie or show, see [`MediaMetadata`].
+pub struct MetadataDetails {
+    pub original_title: Option<String>,
+    pub original_language: Option<String>,
+    pub status: Option<String>,
+    pub production_countries: Vec<String>,
+    pub genres: Vec<String>,
+    pub keywords: Vec<String>,
+}
+
+pub async fn get_media_metadata<C: GenericClient>(
+    conn: &C,
+    kind: MediaKind,
+    media_id: i32,
+) -> Result<Option<MetadataDetails>, GetMediaDetailsError> {
+    conn.query_opt(
+        &format!(
+            "
+            SELECT me.original_title, me.original_language, me.status, me.production_countries,
+            ARRAY(
+                SELECT ge.name FROM media_genre mg
+                INNER JOIN genre ge ON ge.id = mg.genre_id
+                WHERE mg.media_id = me.id
+                ORDER BY ge.name
+            ),
+            ARRAY(
+                SELECT ke.name FROM media_keyword mk
+                INNER JOIN keyword ke ON ke.id = mk.keyword_id
+                WHERE mk.media_id = me.id
+                ORDER BY ke.name
+            )
+            FROM {} me
+            WHERE me.id = $1
+            ",
+            slug_table(kind)
+        ),
+        &[&media_id],
+    )
+    .await
+    .map_err(GetMediaDetailsError)
+    .map(|opt_row| {
+        opt_row.map(|row| MetadataDetails {
+            original_title: row.get(0),
+            original_language: row.get(1),
+            status: row.get(2),
+            production_countries: row.get(3),
+            genres: row.get(4),
+            keywords: row.get(5),
+        })
+    })
+}
+
+pub struct ShowDetails {
+    pub id: i32,
+    pub slug: String,
+    pub title: String,
+    pub release_year: Option<i32>,
+    pub overview: Option<String>,
+    pub tagline: Option<String>,
+    pub episode_runtime: Option<i32>,
+    pub episodes_count: i64,
+    pub episodes_watched: i64,
+    pub play_count: i64,
+    pub seasons: Vec<ShowSeasonSummary>,
+}
+
+pub struct ShowSeasonSummary {
+    pub title: String,
+    pub number: i32,
+    pub episodes_count: i64,
+    pub episodes_watched: i64,
+    pub play_count: i64,
+}
+
+pub async fn get_show_details<C: GenericClient>(
+    conn: &C,
+    user_id: i32,
+    show_id: i32,
+) -> Result<Option<ShowDetails>, GetMediaDetailsError> {
+    let rows = conn
+        .query(
+            "
+            SELECT sh.id AS show_id, sh.title AS show_title, sh.release_year,
+            sh.overview AS show_overview, sh.tagline AS show_tagline,
+            sh.episode_runtime, se.id AS season_id, se.title AS season_title,
+            se.number AS season_number, COUNT(DISTINCT(ep.id)) AS episodes_count,
+            COUNT(DISTINCT(wh.media_id)) AS episodes_watched, COUNT(wh.watched_at) AS play_count,
+            sh.slug
+            FROM show sh
+            LEFT JOIN season se ON se.show_id = sh.id
+            LEFT JOIN episode ep ON ep.season_id = se.id
+            LEFT JOIN watch_history wh ON wh.media_id = ep.id AND wh.media_kind = 'EPISODE'
+                AND wh.user_id = $2
+            WHERE sh.id = $1
+            GROUP BY sh.id, se.id
+            ORDER BY se.number
+            ",
+            &[&show_id, &user_id],
+        )
+        .await
+        .map_err(GetMediaDetailsError)?;
+
+    let Some(first_row) = rows.first() else {
+        return Ok(None);
+    };
+
+    let mut show = ShowDetails {
+        id: first_row.get(0),
+        slug: first_row.get(12),
+        title: first_row.get(1),
+        release_year: first_row.get(2),
+        overview: first_row.get(3),
+        tagline: first_row.get(4),
+        episode_runtime: first_row.get(5),
+        episodes_count: 0,
+        episodes_watched: 0,
+        play_count: 0,
+        seasons: vec![],
+    };
+
+    for row in rows.iter() {
+        // Shows without seasons still get a single row, with no season.
+        let Some(season_number) = row.get::<_, Option<i32>>(8) else {
+            continue;
+        };
+
+        let season = ShowSeasonSummary {
+            title: row.get(7),
+            number: season_number,
+            episodes_count: row.get(9),
+            episodes_watched: row.get(10),
+            play_count: row.get(11),
+        };
+
+        show.episodes_count += season.episodes_count;
+        show.episodes_watched += season.episodes_watched;
+        show.play_count += season.play_count;
+        show.seasons.push(season);
+    }
+
+    Ok(Some(show))
+}
+
+pub struct SeasonDetails {
+    pub show_id: i32,
+    pub show_slug: String,
+    pub show_title: String,
+    pub title: String,
+    pub number: i32,
+    pub overview: Option<String>,
+    pub episodes_count: i64,
+    pub episodes_watched: i64,
+    pub play_count: i64,
+    pub episodes: Vec<SeasonEpisodeSummary>,
+}
+
+pub struct SeasonEpisodeSummary {
+    pub id: i32,
+    pub title: String,
+    pub number: i32,
+    pub overview: Option<String>,
+    pub play_count: i64,
+    pub last_watched_at: Option<jiff::Timestamp>,
+}
+
+pub async fn get_season_details<C: GenericClient>(
+    conn: &C,
+    user_id: i32,
+    show_id: i32,
+    season_number: i32,
+) -> Result<Option<SeasonDetails>, GetMediaDetailsError> {
+    let rows = conn
+        .query(
+            "
+            SELECT se.id AS season_id, se.title AS season_title, se.overview, sh.title AS show_title,
+            ep.id AS episode_id, ep.title AS episode_title, ep.number AS episode_number,
+            ep.overview AS episode_overview, COUNT(wh.watched_at) AS play_count,
+            se.number AS season_number, sh.slug AS show_slug,
+            MAX(wh.watched_at) AS last_watched_at FROM season se
+            INNER JOIN show sh ON sh.id = se.show_id
+            INNER JOIN episode ep ON ep.season_id = se.id
+            LEFT JOIN watch_history wh ON wh.media_id = ep.id AND wh.media_kind = 'EPISODE'
+                AND wh.user_id = $3
+            WHERE sh.id = $1 AND se.number = $2
+            GROUP BY se.id, sh.title, sh.slug, ep.id
+            ORDER BY ep.number
+            ",
+            &[&show_id, &season_number, &user_id],
+        )
+        .await
+        .map_err(GetMediaDetailsError)?;
+
+    let Some(first_row) = rows.first() else {
+        return Ok(None);
+    };
+
+    let mut season = SeasonDetails {
+        show_id,
+        show_slug: first_row.get(10),
+        show_title: first_row.get(3),
+        title: first_row.get(1),
+        number: first_row.get(9),
+        overview: first_row.get(2),
+        episodes_count: 0,
+        episodes_watched: 0,
+        play_count: 0,
+        episodes: Vec::new(),
+    };
+
+    for row in rows.iter() {
+        let episode = SeasonEpisodeSummary {
+            id: row.get(4),
+            title: row.get(5),
+            number: row.get(6),
+            overview: row.get(7),
+            play_count: row.get(8),
+            last_watched_at: row.get(11),
+        };
+
+        season.episodes_count += 1;
+        season.play_count += episode.play_count;
+        if episode.play_count > 0 {
+            season.episodes_watched += 1;
+        }
+        season.episodes.push(episode);
+    }
+
+    Ok(Some(season))
+}
+
+pub struct EpisodeDetails {
+    pub id: i32,
+    pub title: String,
+    pub number: i32,
+    pub overview: Option<String>,
+    pub show_id: i32,
+    pub show_slug: String,
+    pub show_title: String,
+    pub season_title: String,
+    pub season_number: i32,
+    pub play_count: i64,
+}
+
+pub async fn get_episode_details<C: GenericClient>(
+    conn: &C,
+    user_id: i32,
+    show_id: i32,
+    season_number: i32,
+    episode_number: i32,
+) -> Result<Option<EpisodeDetails>, GetMediaDetailsError> {
+    conn.query_opt(
+        "
+        SELECT sh.id AS show_id, sh.title AS show_title, se.title AS season_title,
+        se.number AS season_number, ep.id AS episode_id,
+        ep.title AS episode_title, ep.number AS episode_number,
+        ep.overview AS episode_overview, COUNT(wh.watched_at) AS play_count,
+        sh.slug AS show_slug FROM episode ep
+        INNER JOIN season se ON se.id = ep.season_id
+        INNER JOIN show sh ON sh.id = ep.show_id
+        LEFT JOIN watch_history wh ON wh.media_id = ep.id AND wh.media_kind = 'EPISODE'
+            AND wh.user_id = $4
+        WHERE sh.id = $1 AND se.number = $2 AND ep.number = $3
+        GROUP BY se.id, sh.id, ep.id
+        ",
+        &[&show_id, &season_number, &episode_number, &user_id],
+    )
+    .await
+    .map_err(GetMediaDetailsError)
+    .map(|opt_row| {
+        opt_row.map(|row| EpisodeDetails {
+            id: row.get(4),
+            title: row.get(5),
+            number: row.get(6),
+            overview: row.get(7),
+            show_id: row.get(0),
+            show_slug: row.get(9),
+            show_title: row.get(1),
+            season_title: row.get(2),
+            season_number: row.get(3),
+            play_count: row.get(8),
+        })
+    })
+}
+
+/// Order of the movies and shows in the library pages. Each sort has its
+/// natural direction, e.g. most played first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LibrarySort {
+    #[default]
+    Title,
+    ReleaseYear,
+    LastWatched,
+    PlayCount,
+    Added,
+}
+
+impl LibrarySort {
+    fn order_by(self) -> &'static str {
+        match self {
+            LibrarySort::Title => "title ASC",
+            LibrarySort::ReleaseYear => "release_year DESC NULLS LAST, title ASC",
+            LibrarySort::LastWatched => "last_watched_at DESC NULLS LAST, title ASC",
+            LibrarySort::PlayCount => "play_count DESC, title ASC",
+            LibrarySort::Added => "added_at DESC, id DESC",
+        }
+    }
+}
+
+/// Whether the user watched a movie or show. Movies are in progress while
+/// they have a scrobble session, shows while some but not all of their
+/// episodes are watched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchStatus {
+    Watched,
+    Unwatched,
+    InProgress,
+}
+
+pub struct LibraryQuery {
+    pub sort: LibrarySort,
+    pub status: Option<WatchStatus>,
+    pub genre: Option<String>,
+    /// Matched case insensitively.
+    pub keyword: Option<String>,
+    pub original_language: Option<String>,
+    pub country: Option<String>,
+    /// TMDB's status, e.g. Ended.
+    pub release_status: Option<String>,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+impl LibraryQuery {
+    /// Conditions on the metadata of the movies or shows aliased `alias`,
+    /// with the filters bound as `$4` to `$8`, see [`LibraryQuery::params`].
+    fn metadata_conditions(alias: &str) -> String {
+        format!(
+            "
+            ($4::TEXT IS NULL OR EXISTS (
+                SELECT 1 FROM media_genre mg INNER JOIN genre ge ON ge.id = mg.genre_id
+                WHERE mg.media_id = {alias}.id AND ge.name = $4
+            ))
+            AND ($5::TEXT IS NULL OR EXISTS (
+                SELECT 1 FROM media_keyword mk INNER JOIN keyword ke ON ke.id = mk.keyword_id
+                WHERE mk.media_id = {alias}.id AND LOWER(ke.name) = LOWER($5)
+            ))
+            AND ($6::TEXT IS NULL OR {alias}.original_language = $6)
+            AND ($7::TEXT IS NULL OR $7 = ANY({alias}.production_countries))
+            AND ($8::TEXT IS NULL OR {alias}.status = $8)
+            "
+        )
+    }
+
+    fn params<'a>(&'a self, user_id: &'a i32) -> [&'a (dyn ToSql + Sync); 8] {
+        [
+            user_id,
+            &self.limit,
+            &self.offset,
+            &self.genre,
+            &self.keyword,
+            &self.original_language,
+            &self.country,
+            &self.release_status,
+        ]
+    }
+}
+
+/// Values to filter the movies or shows of the library by, see
+/// [`LibraryQuery`].
+pub struct LibraryFacets {
+    pub genres: Vec<String>,
+    pub languages: Vec<String>,
+    pub countries: Vec<String>,
+    pub release_statuses: Vec<String>,
+}
+
+pub async fn get_library_facets<C: GenericClient>(
+    conn: &C,
+    kind: MediaKind,
+) -> Result<LibraryFacets, GetMediaDetailsError> {
+    let table = slug_table(kind);
+    let rows = conn
+        .query(
+            &format!(
+                "
+                SELECT 'genre', ge.name FROM genre ge
+                WHERE EXISTS (
+                    SELECT 1 FROM media_genre mg INNER JOIN {table} me ON me.id = mg.media_id
+                    WHERE mg.genre_id = ge.id
+                )
+                UNION
+                SELECT 'language', original_language FROM {table}
+                WHERE original_language IS NOT NULL
+                UNION
+                SELECT 'country', UNNEST(production_countries) FROM {table}
+                UNION
+                SELECT 'status', status FROM {table} WHERE status IS NOT NULL
+                ORDER BY 2
+                "
+            ),
+            &[],
+        )
+        .await
+        .map_err(GetMediaDetailsError)?;
+
+    let mut facets = LibraryFacets {
+        genres: vec![],
+        languages: vec![],
+        countries: vec![],
+        release_statuses: vec![],
+    };
+    for row in rows.iter() {
+        let values = match row.get(0) {
+            "genre" => &mut facets.genres,
+            "language" => &mut facets.languages,
+            "country" => &mut facets.countries,
+            _ => &mut facets.release_statuses,
+        };
+        values.push(row.get(1));
+    }
+
+    Ok(facets)
+}
+
+pub struct MovieSummary {
+    pub slug: String,
+    pub title: String,
+    /// Only set when it differs from the title.
+    pub original_title: Option<String>,
+    pub release_year: Option<i32>,
+    pub added_at: jiff::Timestamp,
+    pub play_count: i64,
+    pub last_watched_at: Option<jiff::Timestamp>,
+}
+
+/// Returns a page of the movies in the library, with the user's plays.
+pub async fn get_movies<C: GenericClient>(
+    conn: &C,
+    user_id: i32,
+    query: &LibraryQuery,
+) -> Result<Vec<MovieSummary>, GetMediaDetailsError> {
+    let status_stmt = match query.status {
+        None => "",
+        Some(WatchStatus::Watched) => "WHERE play_count > 0",
+        Some(WatchStatus::Unwatched) => "WHERE play_count = 0",
+        Some(WatchStatus::InProgress) => "WHERE in_progress",
+    };
+
+    conn.query(
+        &format!(
+            "
+            SELECT * FROM (
+                SELECT mo.id, mo.slug, mo.title, mo.release_year, me.created_at AS added_at,
+                COUNT(wh.watched_at) AS play_count, MAX(wh.watched_at) AS last_watched_at,
+                EXISTS (
+                    SELECT 1 FROM scrobble_session ss
+                    WHERE ss.user_id = $1 AND ss.media_id = mo.id
+                ) AS in_progress,
+                NULLIF(mo.original_title, mo.title) AS original_title
+                FROM movie mo
+                INNER JOIN media me ON me.id = mo.id
+                LEFT JOIN watch_history wh ON mo.id = wh.media_id AND wh.media_kind = 'MOVIE'
+                    AND wh.user_id = $1
+                WHERE {}
+                GROUP BY mo.id, me.created_at
+            ) movies
+            {status_stmt}
+            ORDER BY {}, id
+            LIMIT $2 OFFSET $3
+            ",
+            LibraryQuery::metadata_conditions("mo"),
+            query.sort.order_by()
+        ),
+        &query.params(&user_id),
+    )
+    .await
+    .map_err(GetMediaDetailsError)
+    .map(|rows| {
+        rows.iter()
+            .map(|row| MovieSummary {
+                slug: row.get(1),
+                title: row.get(2),
+                original_title: row.get(8),
+                release_year: row.get(3),
+                added_at: row.get(4),
+                play_count: row.get(5),
+                last_watched_at: row.get(6),
+            })
+            .collect()
+    })
+}
+
+pub struct ShowSummary {
+    pub slug: String,
+    pub title: String,
+    /// Only set when it differs from the title.
+    pub original_title: Option<String>,
+    pub release_year: Option<i32>,
+    pub added_at: jiff::Timestamp,
+    pub episodes_count: i64,
+    pub episodes_watched: i64,
+    pub play_count: i64,
+    pub last_watched_at: Option<jiff::Timestamp>,
+}
+
+/// Returns a page of the shows in the library, with the user's plays.
+pub async fn get_shows<C: GenericClient>(
+    conn: &C,
+    user_id: i32,
+    query: &LibraryQuery,
+) -> Result<Vec<ShowSummary>, GetMediaDetailsError> {
+    let status_stmt = match query.status {
+        None => "",
+        Some(WatchStatus::Watched) => {
+            "WHERE episodes_watched > 0 AND episodes_watched = episodes_count"
+        }
+        Some(WatchStatus::Unwatched) => "WHERE episodes_watched = 0",
+        Some(WatchStatus::InProgress) => {
+            "WHERE episodes_watched > 0 AND episodes_watched < episodes_count"
+        }
+    };
+
+    conn.query(
+        &format!(
+            "
+            SELECT * FROM (
+                SELECT sh.id, sh.slug, sh.title, sh.release_year, me.created_at AS added_at,
+                COUNT(DISTINCT(ep.id)) AS episodes_count,
+                COUNT(DISTINCT(wh.media_id)) AS episodes_watched,
+                COUNT(wh.watched_at) AS play_count, MAX(wh.watched_at) AS last_watched_at,
+                NULLIF(sh.original_title, sh.title) AS original_title
+                FROM show sh
+                INNER JOIN media me ON me.id = sh.id
+                LEFT JOIN season se ON se.show_id = sh.id
+                LEFT JOIN episode ep ON ep.season_id = se.id
+                LEFT JOIN watch_history wh ON wh.media_id = ep.id AND wh.media_kind = 'EPISODE'
+                    AND wh.user_id = $1
+                WHERE {}
+                GROUP BY sh.id, me.created_at
+            ) shows
+            {status_stmt}
+            ORDER BY {}, id
+            LIMIT $2 OFFSET $3
+            ",
+            LibraryQuery::metadata_conditions("sh"),
+            query.sort.order_by()
+        ),
+        &query.params(&user_id),
+    )
+    .await
+    .map_err(GetMediaDetailsError)
+    .map(|rows| {
+        rows.iter()
+            .map(|row| ShowSummary {
+                slug: row.get(1),
+                title: row.get(2),
+                original_title: row.get(9),
+                release_year: row.get(3),
+                added_at: row.get(4),
+                episodes_count: row.get(5),
+                episodes_watched: row.get(6),
+                play_count: row.get(7),
+                last_watched_at: row.get(8),
+            })
+            .collect()
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ToSql, FromSql)]
+#[postgres(name = "credit_kind", rename_all = "UPPERCASE")]
+pub enum CreditKind {
+    Cast,
+    Crew,
+}
+
+pub struct NewCredit {
+    pub person_tmdb_id: i32,
+    pub person_name: String,
+    pub kind: CreditKind,
+    /// The character played by cast, the job of crew.
+    pub role: String,
+    pub department: Option<String>,
+}
+
+#[derive(Debug, Error)]
+#[error("failed to insert credits")]
+pub struct InsertCreditsError(#[source] tokio_postgres::Error);
+
+/// Replaces the credits of a movie, show or episode, keeping their order.
+/// People who aren't known yet are added, the names of known ones updated.
+pub async fn replace_credits<C: GenericClient>(
+    conn: &C,
+    media: &Media,
+    credits: &[NewCredit],
+) -> Result<(), InsertCreditsError> {
+    conn.execute("DELETE FROM credit WHERE media_id = $1", &[&media.id])
+        .await
+        .map_err(InsertCreditsError)?;
+
+    if credits.is_empty() {
+        return Ok(());
+    }
+
+    let person_tmdb_ids: Vec<i32> = credits.iter().map(|c| c.person_tmdb_id).collect();
+    let person_names: Vec<&str> = credits.iter().map(|c| c.person_name.as_str()).collect();
+    let kinds: Vec<CreditKind> = credits.iter().map(|c| c.kind).collect();
+    let roles: Vec<&str> = credits.iter().map(|c| c.role.as_str()).collect();
+    let departments: Vec<Option<&str>> = credits.iter().map(|c| c.department.as_deref()).collect();
+
+    conn.execute(
+        "
+        INSERT INTO person (tmdb_id, name)
+        SELECT DISTINCT ON (tmdb_id) tmdb_id, name
+        FROM UNNEST($1::INT[], $2::TEXT[]) AS p (tmdb_id, name)
+        ON CONFLICT (tmdb_id) DO UPDATE SET name = EXCLUDED.name
+        ",
+        &[&person_tmdb_ids, &person_names],
+    )
+    .await
+    .map_err(InsertCreditsError)?;
+
+    conn.execute(
+        "
+        INSERT INTO credit (media_id, media_kind, position, person_id, kind, role, department)
+        SELECT $1::INT, $2::media_kind, c.position, pe.id, c.kind, c.role, c.department
+        FROM UNNEST($3::INT[], $4::credit_kind[], $5::TEXT[], $6::TEXT[])
+            WITH ORDINALITY AS c (tmdb_id, kind, role, department, position)
+        INNER JOIN person pe ON pe.tmdb_id = c.tmdb_id
+        ",
+        &[
+            &media.id,
+            &media.kind,
+            &person_tmdb_ids,
+            &kinds,
+            &roles,
+            &departments,
+        ],
+    )
+    .await
+    .map_err(InsertCreditsError)?;
+
+    Ok(())
+}
+
+/// Returns the movies and shows with a TMDB id but without metadata or
+/// credits, e.g. the ones imported from backups, or with metadata fetched in
+/// another `language` than the given one.
+pub async fn get_media_without_metadata<C: GenericClient>(
+    conn: &C,
+    language: &str,
+) -> Result<Vec<(Media, TmdbId)>, GetMediaIdError> {
+    conn.query(
+        "
+        SELECT me.id, me.kind, ex.tmdb_id FROM media me
+        INNER JOIN media_external_id ex ON ex.media_id = me.id
+        LEFT JOIN movie mo ON mo.id = me.id
+        LEFT JOIN show sh ON sh.id = me.id
+        WHERE me.kind IN ('MOVIE', 'SHOW') AND ex.tmdb_id IS NOT NULL
+            AND (
+                COALESCE(mo.original_title, sh.original_title) IS NULL
+                OR NOT EXISTS (SELECT 1 FROM credit cr WHERE cr.media_id = me.id)
+                OR COALESCE(mo.metadata_language, sh.metadata_language) IS DISTINCT FROM $1
+            )
+        ORDER BY me.id
+        ",
+        &[&language],
+    )
+    .await
+    .map_err(GetMediaIdError)
+    .map(|rows| {
+        rows.iter()
+            .map(|row| {
+                (
+                    Media {
+                        id: row.get(0),
+                        kind: row.get(1),
+                    },
+                    TmdbId(row.get(2)),
+                )
+            })
+            .collect()
+    })
+}
+
+/// A person in the credits of a movie, show or episode.
+pub struct Credit {
+    pub person_id: i32,
+    pub person_name: String,
+    pub kind: CreditKind,
+    pub role: String,
+}
+
+#[derive(Debug, Error)]
+#[error("failed to query credits")]
+pub struct GetCreditsError(#[source] tokio_postgres::Error);
+
+pub async fn get_credits<C: GenericClient>(
+    conn: &C,
+    media_id: i32,
+) -> Result<Vec<Credit>, GetCreditsError> {
+    conn.query(
+        "
+        SELECT pe.id, pe.name, cr.kind, cr.role FROM credit cr
+        INNER JOIN person pe ON pe.id = cr.person_id
+        WHERE cr.media_id = $1
+        ORDER BY cr.position
+        ",
+        &[&media_id],
+    )
+    .await
+    .map_err(GetCreditsError)
+    .map(|rows| {
+        rows.iter()
+            .map(|row| Credit {
+                person_id: row.get(0),
+                person_name: row.get(1),
+                kind: row.get(2),
+                role: row.get(3),
+            })
+            .collect()
+    })
+}
+
+pub struct Person {
+    pub id: i32,
+    pub tmdb_id: i32,
+    pub name: String,
+}
+
+#[derive(Debug, Error)]
+#[error("failed to query person")]
+pub struct PersonError(#[source] tokio_postgres::Error);
+
+pub async fn get_person<C: GenericClient>(
+    conn: &C,
+    person_id: i32,
+) -> Result<Option<Person>, PersonError> {
+    conn.query_opt(
+        "SELECT id, tmdb_id, name FROM person WHERE id = $1",
+        &[&person_id],
+    )
+    .await
+    .map_err(PersonError)
+    .map(|opt_row| {
+        opt_row.map(|row| Person {
+            id: row.get(0),
+            tmdb_id: row.get(1),
+            name: row.get(2),
+        })
+    })
+}
+
+pub async fn get_person_id_by_tmdb_id<C: GenericClient>(
+    conn: &C,
+    tmdb_id: &TmdbId,
+) -> Result<Option<i32>, PersonError> {
+    conn.query_opt("SELECT id FROM person WHERE tmdb_id = $1", &[&tmdb_id.0])
+        .await
+        .map_err(PersonError)
+        .map(|opt_row| opt_row.map(|row| row.get(0)))
+}
+
+/// Adds a person, or updates their name if they're known already, and
+/// returns their id.
+pub async fn upsert_person<C: GenericClient>(
+    conn: &C,
+    tmdb_id: &TmdbId,
+    name: &str,
+) -> Result<i32, PersonError> {
+    conn.query_one(
+        "
+        INSERT INTO person (tmdb_id, name) VALUES ($1, $2)
+        ON CONFLICT (tmdb_id) DO UPDATE SET name = EXCLUDED.name
+        RETURNING id
+        ",
+        &[&tmdb_id.0, &name],
+    )
+    .await
+    .map_err(PersonError)
+    .map(|row| row.get(0))
+}
+
+/// What a person did in a movie or show, e.g. played a character.
+pub struct PersonRole {
+    pub kind: CreditKind,
+    pub role: String,
+}
+
+fn person_roles(kinds: Vec<CreditKind>, roles: Vec<String>) -> Vec<PersonRole> {
+    kinds
+        .into_iter()
+        .zip(roles)
+        .map(|(kind, role)| PersonRole { kind, role })
+        .collect()
+}
+
+pub struct PersonMovie {
+    pub slug: String,
+    pub title: String,
+    pub release_year: Option<i32>,
+    pub roles: Vec<PersonRole>,
+    pub play_count: i64,
+    pub last_watched_at: Option<jiff::Timestamp>,
+}
+
+/// Returns the movies in the library a person is credited in, with the
+/// user's plays.
+pub async fn get_person_movies<C: GenericClient>(
+    conn: &C,
+    user_id: i32,
+    person_id: i32,
+) -> Result<Vec<PersonMovie>, GetCreditsError> {
+    conn.query(
+        "
+        SELECT mo.slug, mo.title, mo.release_year,
+        ARRAY_AGG(cr.kind ORDER BY cr.position), ARRAY_AGG(cr.role ORDER BY cr.position),
+        plays.play_count, plays.last_watched_at
+        FROM credit cr
+        INNER JOIN movie mo ON mo.id = cr.media_id
+        CROSS JOIN LATERAL (
+            SELECT COUNT(*) AS play_count, MAX(wh.watched_at) AS last_watched_at
+            FROM watch_history wh
+            WHERE wh.media_id = mo.id AND wh.user_id = $1
+        ) plays
+        WHERE cr.person_id = $2
+        GROUP BY mo.id, plays.play_count, plays.last_watched_at
+        ORDER BY mo.release_year DESC NULLS LAST, mo.title
+        ",
+        &[&user_id, &person_id],
+    )
+    .await
+    .map_err(GetCreditsError)
+    .map(|rows| {
+        rows.iter()
+            .map(|row| PersonMovie {
+                slug: row.get(0),
+                title: row.get(1),
+                release_year: row.get(2),
+                roles: person_roles(row.get(3), row.get(4)),
+                play_count: row.get(5),
+                last_watched_at: row.get(6),
+            })
+            .collect()
+    })
+}
+
+pub struct PersonShow {
+    pub slug: String,
+    pub title: String,
+    pub release_year: Option<i32>,
+    pub roles: Vec<PersonRole>,
+    /// Episodes the person is credited in by themselves, e.g. as a guest
+    /// star, rather than through the show's credits.
+    pub episodes_count: i64,
+    pub play_count: i64,
+    pub last_watched_at: Option<jiff::Timestamp>,
+}
+
+/// Returns the shows in the library a person is credited in, either for the
+/// whole show or some episodes, with the user's plays of those episodes.
+pub async fn get_person_shows<C: GenericClient>(
+    conn: &C,
+    user_id: i32,
+    person_id: i32,
+) -> Result<Vec<PersonShow>, GetCreditsError> {
+    conn.query(
+        "
+        WITH person_credit AS (
+            SELECT cr.media_kind, cr.kind, cr.role, cr.position,
+            COALESCE(ep.show_id, cr.media_id) AS show_id, ep.id AS episode_id
+            FROM credit cr
+            LEFT JOIN episode ep ON ep.id = cr.media_id
+            WHERE cr.person_id = $2 AND cr.media_kind IN ('SHOW', 'EPISODE')
+        ),
+        person_role AS (
+            SELECT show_id, kind, role, MIN(position) AS position FROM person_credit
+            GROUP BY show_id, kind, role
+        )
+        SELECT sh.slug, sh.title, sh.release_year,
+        (
+            SELECT ARRAY_AGG(pr.kind ORDER BY pr.kind, pr.position) FROM person_role pr
+            WHERE pr.show_id = sh.id
+        ),
+        (
+            SELECT ARRAY_AGG(pr.role ORDER BY pr.kind, pr.position) FROM person_role pr
+            WHERE pr.show_id = sh.id
+        ),
+        (
+            SELECT COUNT(DISTINCT pc.episode_id) FROM person_credit pc
+            WHERE pc.show_id = sh.id
+        ),
+        plays.play_count, plays.last_watched_at
+        FROM show sh
+        CROSS JOIN LATERAL (
+            SELECT COUNT(*) AS play_count, MAX(wh.watched_at) AS last_watched_at
+            FROM watch_history wh
+            INNER JOIN episode ep ON ep.id = wh.media_id
+            WHERE ep.show_id = sh.id AND wh.user_id = $1
+                AND EXISTS (
+                    SELECT 1 FROM person_credit pc
+                    WHERE pc.show_id = sh.id
+                        AND (pc.media_kind = 'SHOW' OR pc.episode_id = ep.id)
+                )
+        ) plays
+        WHERE sh.id IN (SELECT show_id FROM person_credit)
+        ORDER BY sh.release_year DESC NULLS LAST, sh.title
+        ",
+        &[&user_id, &person_id],
+    )
+    .await
+    .map_err(GetCreditsError)
+    .map(|rows| {
+        rows.iter()
+            .map(|row| PersonShow {
+                slug: row.get(0),
+                title: row.get(1),
+                release_year: row.get(2),
+                roles: person_roles(row.get(3), row.get(4)),
+                episodes_count: row.get(5),
+                play_count: row.get(6),
+                last_watched_at: row.get(7),
+            })
+            .collect()
+    })
+}
+
+pub struct NewCollection {
+    pub tmdb_id: i32,
+    pub name: String,
+    pub overview: Option<String>,
+    pub parts: Vec<NewCollectionPart>,
+}
+
+pub struct NewCollectionPart {
+    pub tmdb_id: i32,
+    pub title: String,
+    pub release_date: Option<Date>,
+}
+
+#[derive(Debug, Error)]
+pub enum UpsertCollectionError {
+    #[error("failed to upsert collection")]
+    UpsertCollection(#[source] tokio_postgres::Error),
+    #[error("failed to replace collection parts")]
+    ReplaceParts(#[source] tokio_postgres::Error),
+    #[error("failed to start transaction")]
+    StartTransaction(#[source] tokio_postgres::Error),
+    #[error("failed to commit transaction")]
+    CommitTransaction(#[source] tokio_postgres::Error),
+}
+
+/// Adds a collection, or updates it and replaces its parts if it's known
+/// already, and returns its id.
+pub async fn upsert_collection<C: GenericClient>(
+    conn: &mut C,
+    new_collection: &NewCollection,
+) -> Result<i32, UpsertCollectionError> {
+    let tx = conn
+        .transaction()
+        .await
+        .map_err(UpsertCollectionError::StartTransaction)?;
+
+    let collection_id: i32 = tx
+        .query_one(
+            "
+            INSERT INTO collection (tmdb_id, name, overview) VALUES ($1, $2, $3)
+            ON CONFLICT (tmdb_id) DO UPDATE SET name = EXCLUDED.name, overview = EXCLUDED.overview
+            RETURNING id
+            ",
+            &[
+                &new_collection.tmdb_id,
+                &new_collection.name,
+                &new_collection.overview,
+            ],
+        )
+        .await
+        .map_err(UpsertCollectionError::UpsertCollection)?
+        .get(0);
+
+    tx.execute(
+        "DELETE FROM collection_part WHERE collection_id = $1",
+        &[&collection_id],
+    )
+    .await
+    .map_err(UpsertCollectionError::ReplaceParts)?;
+
+    let tmdb_ids: Vec<i32> = new_collection.parts.iter().map(|p| p.tmdb_id).collect();
+    let titles: Vec<&str> = new_collection
+        .parts
+        .iter()
+        .map(|p| p.title.as_str())
+        .collect();
+    let release_dates: Vec<Option<Date>> = new_collection
+        .parts
+        .iter()
+        .map(|p| p.release_date)
+        .collect();
+
+    tx.execute(
+        "
+        INSERT INTO collection_part (collection_id, tmdb_id, title, release_date)
+        SELECT DISTINCT ON (tmdb_id) $1::INT, tmdb_id, title, release_date
+        FROM UNNEST($2::INT[], $3::TEXT[], $4::DATE[]) AS p (tmdb_id, title, release_date)
+        ",
+        &[&collection_id, &tmdb_ids, &titles, &release_dates],
+    )
+    .await
+    .map_err(UpsertCollectionError::ReplaceParts)?;
+
+    tx.commit()
+        .await
+        .map_err(UpsertCollectionError::CommitTransaction)?;
+
+    Ok(collection_id)
+}
+
+/// A movie of a collection, with the user's plays when it's in the library.
+#[derive(Clone)]
+pub struct CollectionPart {
+    pub tmdb_id: i32,
+    pub title: String,
+    pub release_date: Option<Date>,
+    /// Set when the movie is in the library.
+    pub movie_id: Option<i32>,
+    pub slug: Option<String>,
+    pub play_count: i64,
+    pub last_watched_at: Option<jiff::Timestamp>,
+}
+
+impl CollectionPart {
+    pub fn is_released(&self, today: &Date) -> bool {
+        self.release_date.is_some_and(|date| date <= *today)
+    }
+}
+
+pub struct CollectionDetails {
+    pub id: i32,
+    pub tmdb_id: i32,
+    pub name: String,
+    pub overview: Option<String>,
+    /// In release order, unreleased movies last.
+    pub parts: Vec<CollectionPart>,
+}
+
+impl CollectionDetails {
+    /// The first movie released by `today` the user hasn't watched, leaving
+    /// out the movie with the given id, e.g. the one being looked at.
+    pub fn next_unwatched(
+        &self,
+        today: Date,
+        except_movie_id: Option<i32>,
+    ) -> Option<&CollectionPart> {
+        self.parts.iter().find(|part| {
+            part.play_count == 0
+                && part.is_released(&today)
+                && (part.movie_id.is_none() || part.movie_id != except_movie_id)
+        })
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("failed to query collection")]
+pub struct GetCollectionError(#[source] tokio_postgres::Error);
+
+pub async fn get_collection<C: GenericClient>(
+    conn: &C,
+    user_id: i32,
+    collection_id: i32,
+) -> Result<Option<CollectionDetails>, GetCollectionError> {
+    let Some(row) = conn
+        .query_opt(
+            "SELECT id, tmdb_id, name, overview FROM collection WHERE id = $1",
+            &[&collection_id],
+        )
+        .await
+        .map_err(GetCollectionError)?
+    else {
+        return Ok(None);
+    };
+
+    let parts = conn
+        .query(
+            "
+            SELECT cp.tmdb_id, COALESCE(mo.title, cp.title), cp.release_date, mo.id, mo.slug,
+            COUNT(wh.watched_at) AS play_count, MAX(wh.watched_at) AS last_watched_at
+            FROM collection_part cp
+            LEFT JOIN (
+                media_external_id ex INNER JOIN movie mo ON mo.id = ex.media_id
+            ) ON ex.tmdb_id = cp.tmdb_id
+            LEFT JOIN watch_history wh ON wh.media_id = mo.id AND wh.user_id = $2
+            WHERE cp.collection_id = $1
+            GROUP BY cp.tmdb_id, cp.title, cp.release_date, mo.id
+            ORDER BY cp.release_date NULLS LAST, cp.title
+            ",
+            &[&collection_id, &user_id],
+        )
+        .await
+        .map_err(GetCollectionError)?
+        .iter()
+        .map(|row| CollectionPart {
+            tmdb_id: row.get(0),
+            title: row.get(1),
+            release_date: row.get(2),
+            movie_id: row.get(3),
+            slug: row.get(4),
+            play_count: row.get(5),
+            last_watched_at: row.get(6),
+        })
+        .collect();
+
+    Ok(Some(CollectionDetails {
+        id: row.get(0),
+        tmdb_id: row.get(1),
+        name: row.get(2),
+        overview: row.get(3),
+        parts,
+    }))
+}
+
+/// Returns the id of the collection a movie in the library is part of.
+pub async fn get_movie_collection_id<C: GenericClient>(
+    conn: &C,
+    movie_id: i32,
+) -> Result<Option<i32>, GetCollectionError> {
+    conn.query_opt(
+        "
+        SELECT cp.collection_id FROM collection_part cp
+        INNER JOIN media_external_id ex ON ex.tmdb_id = cp.tmdb_id
+        WHERE ex.media_id = $1
+        LIMIT 1
+        ",
+        &[&movie_id],
+    )
+    .await
+    .map_err(GetCollectionError)
+    .map(|opt_row| opt_row.map(|row| row.get(0)))
+}
+
+#[derive(Debug, Error)]
+#[error("failed to query users")]
+pub struct UserError(#[source] tokio_postgres::Error);
+
+pub struct User {
+    pub id: i32,
+    pub username: String,
+    pub password_hash: String,
+    pub is_admin: bool,
+    pub created_at: jiff::Timestamp,
+}
+
+const USER_COLUMNS: &str = "u.id, u.username, u.password_hash, u.is_admin, u.created_at";
+
+fn user_from_row(row: &tokio_postgres::Row) -> User {
+    User {
+        id: row.get(0),
+        username: row.get(1),
+        password_hash: row.get(2),
+        is_admin: row.get(3),
+        created_at: row.get(4),
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum InsertUserError {
+    #[error("failed to start transaction")]
+    StartTransaction(#[source] tokio_postgres::Error),
+    #[error("failed to insert user")]
+    InsertUser(#[source] tokio_postgres::Error),
+    #[error("failed to insert default lists")]
+    InsertLists(#[source] tokio_postgres::Error),
+    #[error("failed to commit transaction")]
+    CommitTransaction(#[source] tokio_postgres::Error),
+}
+
+/// Creates a user along with their watchlist and favorites lists. Returns
+/// none if the username is taken.
+pub async fn insert_user<C: GenericClient>(
+    conn: &mut C,
+    username: &str,
+    password_hash: &str,
+    is_admin: bool,
+) -> Result<Option<i32>, InsertUserError> {
+    let tx = conn
+        .transaction()
+        .await
+        .map_err(InsertUserError::StartTransaction)?;
+
+    let Some(row) = tx
+        .query_opt(
+            "INSERT INTO users (username, password_hash, is_admin) VALUES ($1, $2, $3)
+            ON CONFLICT (username) DO NOTHING
+            RETURNING id",
+            &[&username, &password_hash, &is_admin],
+        )
+        .await
+        .map_err(InsertUserError::InsertUser)?
+    else {
+        return Ok(None);
+    };
+    let user_id: i32 = row.get(0);
+
+    tx.execute(
+        "INSERT INTO list (user_id, name, kind) VALUES
+        ($1, 'Watchlist', 'WATCHLIST'::list_kind),
+        ($1, 'Favorites', 'FAVORITES'::list_kind)",
+        &[&user_id],
+    )
+    .await
+    .map_err(InsertUserError::InsertLists)?;
+
+    tx.commit()
+        .await
+        .map_err(InsertUserError::CommitTransaction)?;
+
+    Ok(Some(user_id))
+}
+
+pub async fn get_user_by_username<C: GenericClient>(
+    conn: &C,
+    username: &str,
+) -> Result<Option<User>, UserError> {
+    conn.query_opt(
+        &format!("SELECT {USER_COLUMNS} FROM users u WHERE u.username = $1"),
+        &[&username],
+    )
+    .await
+    .map_err(UserError)
+    .map(|opt_row| opt_row.as_ref().map(user_from_row))
+}
+
+pub async fn get_users<C: GenericClient>(conn: &C) -> Result<Vec<User>, UserError> {
+    conn.query(
+        &format!("SELECT {USER_COLUMNS} FROM users u ORDER BY u.id"),
+        &[],
+    )
+    .await
+    .map_err(UserError)
+    .map(|rows| rows.iter().map(user_from_row).collect())
+}
+
+/// Sets a user's password and logs out all their sessions. Returns false if
+/// there's no such user.
+pub async fn set_user_password_hash<C: GenericClient>(
+    conn: &mut C,
+    user_id: i32,
+    password_hash: &str,
+) -> Result<bool, UserError> {
+    let tx = conn.transaction().await.map_err(UserError)?;
+
+    let updated = tx
+        .execute(
+            "UPDATE users SET password_hash = $2 WHERE id = $1",
+            &[&user_id, &password_hash],
+        )
+        .await
+        .map_err(UserError)?;
+
+    tx.execute("DELETE FROM auth_session WHERE user_id = $1", &[&user_id])
+        .await
+        .map_err(UserError)?;
+
+    tx.commit().await.map_err(UserError)?;
+
+    Ok(updated > 0)
+}
+
+#[derive(Debug, Error)]
+#[error("failed to query auth data")]
+pub struct AuthError(#[source] tokio_postgres::Error);
+
+pub struct AuthSession {
+    pub user_id: i32,
+    pub is_admin: bool,
+    pub csrf_token: String,
+}
+
+/// Stores a new session, cleaning up expired ones along the way.
+pub async fn insert_auth_session<C: GenericClient>(
+    conn: &C,
+    user_id: i32,
+    token_hash: &str,
+    csrf_token: &str,
+    expires_at: &jiff::Timestamp,
+) -> Result<(), AuthError> {
+    conn.execute("DELETE FROM auth_session WHERE expires_at < NOW()", &[])
+        .await
+        .map_err(AuthError)?;
+
+    conn.execute(
+        "INSERT INTO auth_session (token_hash, user_id, csrf_token, expires_at)
+        VALUES ($1, $2, $3, $4)",
+        &[&token_hash, &user_id, &csrf_token, &expires_at],
+    )
+    .await
+    .map_err(AuthError)?;
+
+    Ok(())
+}
+
+/// Returns the session with the given token hash, unless it expired.
+pub async fn get_auth_session<C: GenericClient>(
+    conn: &C,
+    token_hash: &str,
+) -> Result<Option<AuthSession>, AuthError> {
+    conn.query_opt(
+        "SELECT s.user_id, u.is_admin, s.csrf_token FROM auth_session s
+        INNER JOIN users u ON u.id = s.user_id
+        WHERE s.token_hash = $1 AND s.expires_at > NOW()",
+        &[&token_hash],
+    )
+    .await
+    .map_err(AuthError)
+    .map(|opt_row| {
+        opt_row.map(|row| AuthSession {
+            user_id: row.get(0),
+            is_admin: row.get(1),
+            csrf_token: row.get(2),
+        })
+    })
+}
+
+pub async fn delete_auth_session<C: GenericClient>(
+    conn: &C,
+    token_hash: &str,
+) -> Result<(), AuthError> {
+    conn.execute(
+        "DELETE FROM auth_session WHERE token_hash = $1",
+        &[&token_hash],
+    )
+    .await
+    .map_err(AuthError)?;
+
+    Ok(())
+}
+
+pub struct ApiToken {
+    pub name: String,
+    pub created_at: jiff::Timestamp,
+    pub last_used_at: Option<jiff::Timestamp>,
+}
+
+/// Stores a new API token of a user. Returns false if they already have a
+/// token with that name.
+pub async fn insert_api_token<C: GenericClient>(
+    conn: &C,
+    user_id: i32,
+    name: &str,
+    token_hash: &str,
+) -> Result<bool, AuthError> {
+    conn.query_opt(
+        "INSERT INTO api_token (user_id, name, token_hash) VALUES ($1, $2, $3)
+        ON CONFLICT (user_id, name) DO NOTHING
+        RETURNING 1",
+        &[&user_id, &name, &token_hash],
+    )
+    .await
+    .map_err(AuthError)
+    .map(|opt_row| opt_row.is_some())
+}
+
+pub async fn get_api_tokens<C: GenericClient>(
+    conn: &C,
+    user_id: i32,
+) -> Result<Vec<ApiToken>, AuthError> {
+    conn.query(
+        "SELECT t.name, t.created_at, t.last_used_at FROM api_token t
+        WHERE t.user_id = $1
+        ORDER BY t.created_at",
+        &[&user_id],
+    )
+    .await
+    .map_err(AuthError)
+    .map(|rows| {
+        rows.iter()
+            .map(|row| ApiToken {
+                name: row.get(0),
+                created_at: row.get(1),
+                last_used_at: row.get(2),
+            })
+            .collect()
+    })
+}
+
+/// Deletes the user's API token with the given name. Returns false if there's
+/// none.
+pub async fn delete_api_token<C: GenericClient>(
+    conn: &C,
+    user_id: i32,
+    name: &str,
+) -> Result<bool, AuthError> {
+    conn.execute(
+        "DELETE FROM api_token WHERE user_id = $1 AND name = $2",
+        &[&user_id, &name],
+    )
+    .await
+    .map_err(AuthError)
+    .map(|count| count > 0)
+}
+
+/// Marks the API token with the given hash as used. Returns the id of the
+/// user it belongs to, none if there's no such token.
+pub async fn use_api_token<C: GenericClient>(
+    conn: &C,
+    token_hash: &str,
+) -> Result<Option<i32>, AuthError> {
+    conn.query_opt(
+        "UPDATE api_token SET last_used_at = NOW() WHERE token_hash = $1
+        RETURNING user_id",
+        &[&token_hash],
+    )
+    .await
+    .map_err(AuthError)
+    .map(|opt_row| opt_row.map(|row| row.get(0)))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ToSql, FromSql)]
+#[postgres(name = "job_status", rename_all = "UPPERCASE")]
+pub enum JobStatus {
+    Pending,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+impl JobStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobStatus::Pending => "pending",
+            JobStatus::Running => "running",
+            JobStatus::Succeeded => "succeeded",
+            JobStatus::Failed => "failed",
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("failed to query jobs")]
+pub struct JobError(#[source] tokio_postgres::Error);
+
+pub struct NewJob<'a, T> {
+    pub kind: &'a str,
+    pub payload: &'a T,
+    pub data: Option<&'a [u8]>,
+    pub user_id: Option<i32>,
+    pub max_attempts: i32,
+}
+
+/// Enqueues a job to be run as soon as possible. Returns its id.
+pub async fn insert_job<C: GenericClient, T: Serialize + std::fmt::Debug + Sync>(
+    conn: &C,
+    job: &NewJob<'_, T>,
+) -> Result<i32, JobError> {
+    conn.query_one(
+        "INSERT INTO job (kind, payload, data, user_id, max_attempts)
+        VALUES ($1, $2, $3, $4, $5) RETURNING id",
+        &[
+            &job.kind,
+            &postgres_types::Json(job.payload),
+            &job.data,
+            &job.user_id,
+            &job.max_attempts,
+        ],
+    )
+    .await
+    .map_err(JobError)
+    .map(|row| row.get(0))
+}
+
+pub struct ClaimedJob {
+    pub id: i32,
+    pub kind: String,
+    pub payload: serde_json::Value,
+    pub data: Option<Vec<u8>>,
+    pub user_id: Option<i32>,
+    /// Including the current one.
+    pub attempts: i32,
+    pub max_attempts: i32,
+}
+
+/// Marks the next pending job that is due as running and returns it. Jobs
+/// claimed by other workers are skipped.
+pub async fn claim_job<C: GenericClient>(conn: &C) -> Result<Option<ClaimedJob>, JobError> {
+    conn.query_opt(
+        "UPDATE job SET status = 'RUNNING', attempts = attempts + 1, started_at = NOW()
+        WHERE id = (
+            SELECT id FROM job WHERE status = 'PENDING' AND run_at <= NOW()
+            ORDER BY run_at, id LIMIT 1 FOR UPDATE SKIP LOCKED
+        )
+        RETURNING id, kind, payload, data, user_id, attempts, max_attempts",
+        &[],
+    )
+    .await
+    .map_err(JobError)
+    .map(|opt_row| {
+        opt_row.map(|row| ClaimedJob {
+            id: row.get(0),
+            kind: row.get(1),
+            payload: row.get(2),
+            data: row.get(3),
+            user_id: row.get(4),
+            attempts: row.get(5),
+            max_attempts: row.get(6),
+        })
+    })
+}
+
+pub async fn complete_job<C: GenericClient>(conn: &C, id: i32) -> Result<(), JobError> {
+    conn.execute(
+        "UPDATE job SET status = 'SUCCEEDED', finished_at = NOW(), data = NULL
+        WHERE id = $1",
+        &[&id],
+    )
+    .await
+    .map_err(JobError)?;
+
+    Ok(())
+}
+
+/// Records the error of the job's current attempt. The job is retried at
+/// `retry_at` if given, otherwise it's failed for good.
+pub async fn fail_job<C: GenericClient>(
+    conn: &C,
+    id: i32,
+    error: &str,
+    retry_at: Option<&jiff::Timestamp>,
+) -> Result<(), JobError> {
+    let result = match retry_at {
+        Some(retry_at) => {
+            conn.execute(
+                "UPDATE job SET status = 'PENDING', last_error = $2, run_at = $3
+                WHERE id = $1",
+                &[&id, &error, retry_at],
+            )
+            .await
+        }
+        None => {
+            conn.execute(
+                "UPDATE job SET status = 'FAILED', last_error = $2, finished_at = NOW(),
+                data = NULL WHERE id = $1",
+                &[&id, &error],
+            )
+            .await
+        }
+    };
+    result.map_err(JobError)?;
+
+    Ok(())
+}
+
+/// Puts the running job back in the queue, without counting the current
+/// attempt.
+pub async fn interrupt_job<C: GenericClient>(conn: &C, id: i32) -> Result<(), JobError> {
+    conn.execute(
+        "UPDATE job SET status = 'PENDING', attempts = attempts - 1,
+        interrupted_at = NOW() WHERE id = $1 AND status = 'RUNNING'",
+        &[&id],
+    )
+    .await
+    .map_err(JobError)?;
+
+    Ok(())
+}
+
+/// Puts all running jobs back in the queue. Meant to be called at startup,
+/// to resume the jobs of a server that was killed, so only a single server
+/// may use the database. Unlike [`interrupt_job`], the attempts still count,
+/// as the job may have been what brought the server down, and jobs out of
+/// attempts are failed. Returns the number of jobs.
+pub async fn interrupt_running_jobs<C: GenericClient>(conn: &C) -> Result<u64, JobError> {
+    conn.execute(
+        "UPDATE job SET interrupted_at = NOW(),
+        status = CASE WHEN attempts < max_attempts THEN 'PENDING'::job_status
+            ELSE 'FAILED' END,
+        last_error = CASE WHEN attempts < max_attempts THEN last_error
+            ELSE 'interrupted by a server stop' END,
+        finished_at = CASE WHEN attempts < max_attempts THEN NULL ELSE NOW() END,
+        data = CASE WHEN attempts < max_attempts THEN data END
+        WHERE status = 'RUNNING'",
+        &[],
+    )
+    .await
+    .map_err(JobError)
+}
+
+/// Returns whether a job of the kind is pending or running.
+pub async fn has_unfinished_job<C: GenericClient>(conn: &C, kind: &str) -> Result<bool, JobError> {
+    conn.query_one(
+        "SELECT EXISTS (
+            SELECT 1 FROM job WHERE kind = $1 AND status IN ('PENDING', 'RUNNING')
+        )",
+        &[&kind],
+    )
+    .await
+    .map_err(JobError)
+    .map(|row| row.get(0))
+}
+
+pub struct JobSummary {
+    pub id: i32,
+    pub kind: String,
+    pub username: Option<String>,
+    pub status: JobStatus,
+    pub attempts: i32,
+    pub max_attempts: i32,
+    pub run_at: jiff::Timestamp,
+    pub last_error: Option<String>,
+    pub created_at: jiff::Timestamp,
+    pub finished_at: Option<jiff::Timestamp>,
+    pub interrupted_at: Option<jiff::Timestamp>,
+}
+
+/// Returns the most recent jobs, only the user's if given.
+pub async fn get_jobs<C: GenericClient>(
+    conn: &C,
+    user_id: Option<i32>,
+    limit: i64,
+) -> Result<Vec<JobSummary>, JobError> {
+    conn.query(
+        "SELECT j.id, j.kind, u.username, j.status, j.attempts, j.max_attempts,
+        j.run_at, j.last_error, j.created_at, j.finished_at, j.interrupted_at
+        FROM job j LEFT JOIN users u ON j.user_id = u.id
+        WHERE $1::INT IS NULL OR j.user_id = $1
+        ORDER BY j.id DESC LIMIT $2",
+        &[&user_id, &limit],
+    )
+    .await
+    .map_err(JobError)
+    .map(|rows| {
+        rows.iter()
+            .map(|row| JobSummary {
+                id: row.get(0),
+                kind: row.get(1),
+                username: row.get(2),
+                status: row.get(3),
+                attempts: row.get(4),
+                max_attempts: row.get(5),
+                run_at: row.get(6),
+                last_error: row.get(7),
+                created_at: row.get(8),
+                finished_at: row.get(9),
+                interrupted_at: row.get(10),
+            })
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watch_history_cursor_round_trips() {
+        let cursor = WatchHistoryCursor {
+            watched_at: "2024-03-01T20:15:30.123456Z".parse().unwrap(),
+            id: 42,
+        };
+
+        let encoded = cursor.encode();
+        assert_eq!(encoded, "1709324130123456_42");
+
+        let decoded = WatchHistoryCursor::decode(&encoded).unwrap();
+        assert_eq!(decoded.watched_at, cursor.watched_at);
+        assert_eq!(decoded.id, cursor.id);
+    }
+
+    #[test]
+    fn watch_history_cursor_rejects_invalid_cursors() {
+        for cursor in [
+            "",
+            "42",
+            "_42",
+            "1709324130123456_",
+            "abc_42",
+            "1_2_3",
+            "1_x",
+        ] {
+            assert!(
+                WatchHistoryCursor::decode(cursor).is_none(),
+                "{cursor:?} was decoded"
+            );
+        }
+        // Out of the range of timestamps.
+        assert!(WatchHistoryCursor::decode(&format!("{}_1", i64::MAX)).is_none());
+    }
+
+    #[test]
+    fn escape_like_escapes_wildcards() {
+        assert_eq!(escape_like("The Matrix"), "The Matrix");
+        assert_eq!(escape_like("100%_done"), "100\\%\\_done");
+        assert_eq!(escape_like("back\\slash%"), "back\\\\slash\\%");
+    }
+}
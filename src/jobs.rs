@@ -0,0 +1,277 @@
+//! Background jobs, stored in the database to survive restarts and run by
+//! the server's workers.
+
+use std::{io::Cursor, sync::Arc, time::Duration};
+
+use deadpool_postgres::GenericClient;
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+use tracing::{Instrument, info, info_span, warn};
+
+use crate::{
+    AppState, DataFormat,
+    db::{self, ClaimedJob, JobError, NewJob},
+    library,
+    tmdb::TmdbId,
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Job {
+    /// See [`library::fetch_missing_metadata`].
+    FetchMetadata,
+    /// See [`library::add_collection_from_tmdb`].
+    AddCollection { tmdb_id: i32 },
+    /// Imports the job's data into its user's library.
+    Import { format: DataFormat },
+}
+
+impl Job {
+    pub const FETCH_METADATA: &str = "fetch_metadata";
+    pub const ADD_COLLECTION: &str = "add_collection";
+    pub const IMPORT: &str = "import";
+
+    /// Same as the serialized tag.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Job::FetchMetadata => Self::FETCH_METADATA,
+            Job::AddCollection { .. } => Self::ADD_COLLECTION,
+            Job::Import { .. } => Self::IMPORT,
+        }
+    }
+
+    fn max_attempts(&self) -> i32 {
+        match self {
+            // Mostly fails on bad files, which retries won't fix.
+            Job::Import { .. } => 3,
+            Job::FetchMetadata | Job::AddCollection { .. } => 5,
+        }
+    }
+}
+
+/// Enqueues the job, along with data such as an uploaded file, and returns
+/// its id.
+pub async fn enqueue<C: GenericClient>(
+    conn: &C,
+    job: &Job,
+    user_id: Option<i32>,
+    data: Option<&[u8]>,
+) -> Result<i32, JobError> {
+    db::insert_job(
+        conn,
+        &NewJob {
+            kind: job.kind(),
+            payload: job,
+            data,
+            user_id,
+            max_attempts: job.max_attempts(),
+        },
+    )
+    .await
+}
+
+/// How often idle workers look for due jobs.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Delay before retrying a failed job, doubled after each attempt.
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+
+/// Runs due jobs one at a time until cancelled. A job still running then is
+/// dropped and put back in the queue.
+pub async fn run_worker(state: Arc<AppState>, cancel: CancellationToken) -> anyhow::Result<()> {
+    loop {
+        if cancel.is_cancelled() {
+            return Ok(());
+        }
+
+        let claimed = async {
+            let conn = state.pool.get().await?;
+            anyhow::Ok(db::claim_job(&conn).await?)
+        }
+        .await;
+
+        match claimed {
+            Ok(Some(job)) => {
+                let span = info_span!("job", id = job.id, kind = job.kind);
+                run_job(&state, job, &cancel).instrument(span).await;
+                continue;
+            }
+            Ok(None) => {}
+            // The database may just be unreachable for now.
+            Err(err) => warn!(err = format!("{err:#}"), "failed to claim job"),
+        }
+
+        tokio::select! {
+            _ = cancel.cancelled() => return Ok(()),
+            _ = tokio::time::sleep(POLL_INTERVAL) => {}
+        }
+    }
+}
+
+enum Outcome {
+    Succeeded,
+    Failed {
+        error: String,
+        retry_at: Option<jiff::Timestamp>,
+    },
+    Interrupted,
+}
+
+impl Outcome {
+    /// Retried later unless the job is out of attempts.
+    fn failed(claimed: &ClaimedJob, error: String) -> Self {
+        Outcome::Failed {
+            error,
+            retry_at: (claimed.attempts < claimed.max_attempts)
+                .then(|| jiff::Timestamp::now() + retry_delay(claimed.attempts)),
+        }
+    }
+}
+
+async fn run_job(state: &Arc<AppState>, mut claimed: ClaimedJob, cancel: &CancellationToken) {
+    let (kind, outcome) = match serde_json::from_value::<Job>(claimed.payload.clone()) {
+        Ok(job) => {
+            let kind = job.kind();
+            // In its own task, so that a panic only fails this attempt.
+            let mut task = tokio::spawn(
+                execute(
+                    state.clone(),
+                    job,
+                    claimed.user_id,
+                    claimed.data.take(),
+                    cancel.clone(),
+                )
+                .in_current_span(),
+            );
+            let result = tokio::select! {
+                result = &mut task => Some(result),
+                // Dropping the job rolls back its transactions, so it can run
+                // again later.
+                _ = cancel.cancelled() => {
+                    task.abort();
+                    let _ = task.await;
+                    None
+                }
+            };
+
+            let outcome = match result {
+                Some(Ok(Ok(()))) => Outcome::Succeeded,
+                Some(Ok(Err(err))) => Outcome::failed(&claimed, format!("{err:#}")),
+                // Only aborted on shutdown, so it panicked.
+                Some(Err(err)) => Outcome::failed(&claimed, err.to_string()),
+                None => Outcome::Interrupted,
+            };
+
+            (kind, outcome)
+        }
+        // Left by another version of grimoire, retrying won't help.
+        Err(err) => (
+            "unknown",
+            Outcome::Failed {
+                error: format!("invalid {} job: {err}", claimed.kind),
+                retry_at: None,
+            },
+        ),
+    };
+
+    let metrics_outcome = match &outcome {
+        Outcome::Succeeded => {
+            info!(job = claimed.id, kind, "job succeeded");
+            "succeeded"
+        }
+        Outcome::Failed {
+            error,
+            retry_at: Some(retry_at),
+        } => {
+            warn!(job = claimed.id, kind, err = error, %retry_at, "job failed, will retry");
+            "retried"
+        }
+        Outcome::Failed {
+            error,
+            retry_at: None,
+        } => {
+            warn!(job = claimed.id, kind, err = error, "job failed");
+            "failed"
+        }
+        Outcome::Interrupted => {
+            info!(job = claimed.id, kind, "job interrupted by shutdown");
+            "interrupted"
+        }
+    };
+    state.metrics.record_job_run(kind, metrics_outcome);
+
+    // Otherwise the job stays running until the next startup.
+    if let Err(err) = record_outcome(state, claimed.id, &outcome).await {
+        warn!(
+            job = claimed.id,
+            err = format!("{err:#}"),
+            "failed to record job outcome"
+        );
+    }
+}
+
+async fn execute(
+    state: Arc<AppState>,
+    job: Job,
+    user_id: Option<i32>,
+    data: Option<Vec<u8>>,
+    cancel: CancellationToken,
+) -> anyhow::Result<()> {
+    let mut conn = state.pool.get().await?;
+
+    match job {
+        Job::FetchMetadata => {
+            let count =
+                library::fetch_missing_metadata(&mut conn, &state.tmdb_api, &cancel).await?;
+            if count > 0 {
+                info!("Fetched the metadata of {count} movies and shows");
+            }
+        }
+        Job::AddCollection { tmdb_id } => {
+            library::add_collection_from_tmdb(&mut conn, &state.tmdb_api, &TmdbId(tmdb_id)).await?;
+        }
+        Job::Import { format } => {
+            let (Some(user_id), Some(data)) = (user_id, data) else {
+                anyhow::bail!("import job without a user or file");
+            };
+
+            // All or nothing, so that interrupted or failed imports can run
+            // again from the start.
+            let mut tx = conn.transaction().await?;
+            crate::import_into(
+                &mut tx,
+                &state.tmdb_api,
+                user_id,
+                format,
+                &mut Cursor::new(data),
+            )
+            .await?;
+            tx.commit().await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn record_outcome(state: &AppState, id: i32, outcome: &Outcome) -> anyhow::Result<()> {
+    let conn = state.pool.get().await?;
+
+    match outcome {
+        Outcome::Succeeded => db::complete_job(&conn, id).await?,
+        Outcome::Failed { error, retry_at } => {
+            db::fail_job(&conn, id, error, retry_at.as_ref()).await?
+        }
+        Outcome::Interrupted => db::interrupt_job(&conn, id).await?,
+    }
+
+    Ok(())
+}
+
+fn retry_delay(attempts: i32) -> Duration {
+    let doublings = attempts.saturating_sub(1).clamp(0, 16) as u32;
+
+    RETRY_DELAY
+        .saturating_mul(1 << doublings)
+        .min(MAX_RETRY_DELAY)
+}
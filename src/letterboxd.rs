@@ -0,0 +1,16 @@
+use jiff::{Timestamp, civil::Date, tz::TimeZone};
+
+pub mod export;
+pub mod import;
+
+/// Letterboxd only keeps dates, which are read and written as days in UTC so
+/// that imports and exports don't depend on the server's time zone and a
+/// play keeps its date through a round trip.
+fn date_to_timestamp(date: Date) -> anyhow::Result<Timestamp> {
+    Ok(date.to_zoned(TimeZone::UTC)?.timestamp())
+}
+
+/// See [`date_to_timestamp`].
+fn timestamp_to_date(timestamp: Timestamp) -> Date {
+    timestamp.to_zoned(TimeZone::UTC).date()
+}
@@ -0,0 +1,100 @@
+use deadpool_postgres::GenericClient;
+use serde::Serialize;
+
+use crate::{
+    db::{MoviePlay, get_movie_plays},
+    letterboxd::timestamp_to_date,
+};
+
+/// Row of Letterboxd's import CSV format.
+///
+/// See <https://letterboxd.com/about/importing-data/>.
+#[derive(Debug, Serialize)]
+struct LetterboxdEntry {
+    #[serde(rename = "Title")]
+    title: String,
+    #[serde(rename = "Year")]
+    year: Option<i32>,
+    #[serde(rename = "imdbID")]
+    imdb_id: Option<String>,
+    #[serde(rename = "tmdbID")]
+    tmdb_id: Option<i32>,
+    #[serde(rename = "WatchedDate")]
+    watched_date: String,
+    /// From half a star to five stars.
+    #[serde(rename = "Rating")]
+    rating: Option<f32>,
+    #[serde(rename = "Rewatch")]
+    rewatch: bool,
+}
+
+// TODO: error handling
+/// Writes every movie play of a user as a CSV that can be imported into
+/// Letterboxd.
+pub async fn export_csv<C: GenericClient, W: std::io::Write>(
+    conn: &C,
+    user_id: i32,
+    writer: W,
+) -> anyhow::Result<()> {
+    write_csv(get_movie_plays(conn, user_id).await?, writer)
+}
+
+fn write_csv<W: std::io::Write>(plays: Vec<MoviePlay>, writer: W) -> anyhow::Result<()> {
+    let mut csv_writer = csv::Writer::from_writer(writer);
+
+    for play in plays {
+        csv_writer.serialize(LetterboxdEntry {
+            title: play.title,
+            year: play.release_year,
+            imdb_id: play.imdb_id,
+            tmdb_id: play.tmdb_id,
+            watched_date: timestamp_to_date(play.watched_at).to_string(),
+            rating: play.rating.map(|rating| rating as f32 / 2.0),
+            rewatch: play.rewatch,
+        })?;
+    }
+
+    csv_writer.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::letterboxd::import::{DiaryEntry, read_csv};
+
+    #[test]
+    fn watched_dates_survive_a_round_trip() {
+        let diary = "\
+Date,Name,Year,Letterboxd URI,Rating,Rewatch,Tags,Watched Date
+2024-03-02,Heat,1995,https://boxd.it/29rc,4.5,,,2024-03-01
+2024-01-01,Alien,1979,https://boxd.it/2b0k,,Yes,,
+";
+        let plays = read_csv::<DiaryEntry>(diary)
+            .unwrap()
+            .into_iter()
+            .map(|entry| MoviePlay {
+                watched_at: entry.watched_at().unwrap(),
+                title: entry.name,
+                release_year: entry.year,
+                imdb_id: None,
+                tmdb_id: None,
+                rating: None,
+                rewatch: false,
+            })
+            .collect();
+
+        let mut csv = Vec::new();
+        write_csv(plays, &mut csv).unwrap();
+
+        let exported = read_csv::<HashMap<String, String>>(str::from_utf8(&csv).unwrap()).unwrap();
+        let watched_dates = exported
+            .iter()
+            .map(|entry| entry["WatchedDate"].as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(watched_dates, ["2024-03-01", "2024-01-01"]);
+    }
+}
@@ -0,0 +1,441 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::Read,
+};
+
+use deadpool_postgres::GenericClient;
+use jiff::{Timestamp, civil::Date};
+use serde::{Deserialize, de::DeserializeOwned};
+
+use crate::{
+    db::{
+        ListKind, Media, MediaKind, NewImportReviewItem, NewList, WatchHistory,
+        get_list_id_by_kind, get_list_id_by_name, get_watch_history_id, insert_import_review_item,
+        insert_list, insert_list_item, insert_watch_history, upsert_rating,
+    },
+    letterboxd::date_to_timestamp,
+    library::get_or_add_from_tmdb,
+    tmdb::{TmdbApi, TmdbId},
+};
+
+/// Value of `import_review_item.source` for entries coming from Letterboxd.
+pub const SOURCE: &str = "letterboxd";
+
+#[derive(Debug, Deserialize)]
+pub(super) struct DiaryEntry {
+    #[serde(rename = "Date")]
+    date: Date,
+    #[serde(rename = "Name")]
+    pub(super) name: String,
+    #[serde(rename = "Year")]
+    pub(super) year: Option<i32>,
+    #[serde(rename = "Letterboxd URI")]
+    uri: Option<String>,
+    #[serde(rename = "Watched Date")]
+    watched_date: Option<Date>,
+    // unused fields:
+    // Rating: Option<f32>,
+    // Rewatch: Option<String>,
+    // Tags: Option<String>,
+}
+
+impl DiaryEntry {
+    /// Entries logged without a watched date were watched on the day they
+    /// were logged.
+    pub(super) fn watched_at(&self) -> anyhow::Result<Timestamp> {
+        date_to_timestamp(self.watched_date.unwrap_or(self.date))
+    }
+}
+
+/// Entry of `watched.csv` and `watchlist.csv`.
+#[derive(Debug, Deserialize)]
+struct FilmEntry {
+    #[serde(rename = "Date")]
+    date: Date,
+    #[serde(rename = "Name")]
+    name: String,
+    #[serde(rename = "Year")]
+    year: Option<i32>,
+    #[serde(rename = "Letterboxd URI")]
+    uri: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RatingEntry {
+    #[serde(rename = "Date")]
+    date: Date,
+    #[serde(rename = "Name")]
+    name: String,
+    #[serde(rename = "Year")]
+    year: Option<i32>,
+    #[serde(rename = "Letterboxd URI")]
+    uri: Option<String>,
+    #[serde(rename = "Rating")]
+    rating: f32,
+}
+
+#[derive(Debug, Deserialize)]
+struct ListInfo {
+    #[serde(rename = "Date")]
+    date: Date,
+    #[serde(rename = "Name")]
+    name: String,
+    #[serde(rename = "Description")]
+    description: Option<String>,
+    // unused fields:
+    // Tags: Option<String>,
+    // URL: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ListEntry {
+    #[serde(rename = "Name")]
+    name: String,
+    #[serde(rename = "Year")]
+    year: Option<i32>,
+    #[serde(rename = "URL")]
+    uri: Option<String>,
+    // unused fields:
+    // Position: i32,
+    // Description: Option<String>,
+}
+
+// TODO: error handling
+/// Imports a Letterboxd data export into a user's history, ratings and lists.
+///
+/// Films are matched against TMDB by title and year and added to the library
+/// when missing. Entries that can't be matched are stored as import review
+/// items so they can be resolved by hand later.
+///
+/// Importing the same export again doesn't duplicate anything: known plays
+/// and review items are skipped and lists with the same name are reused.
+/// Letterboxd only has dates, which are taken as midnight UTC.
+pub async fn import_zip<C: GenericClient, R: std::io::Read + std::io::Seek>(
+    conn: &mut C,
+    tmdb_api: &TmdbApi,
+    user_id: i32,
+    zip_file: &mut R,
+) -> anyhow::Result<()> {
+    let mut zip = zip::ZipArchive::new(zip_file)?;
+
+    let mut files: HashMap<String, String> = HashMap::new();
+    let mut lists: Vec<String> = vec![];
+
+    // Files are read upfront since they need to be imported in a specific
+    // order, e.g. watched.csv depends on what was found in diary.csv.
+    for i in 0..zip.len() {
+        let mut file = zip.by_index(i)?;
+        let file_name = file.name().to_string();
+
+        if !file_name.ends_with(".csv")
+            || file_name.starts_with("deleted/")
+            || file_name.starts_with("orphaned/")
+        {
+            continue;
+        }
+
+        let mut content = String::new();
+        file.read_to_string(&mut content)?;
+
+        if file_name.starts_with("lists/") {
+            lists.push(content);
+        } else {
+            files.insert(file_name, content);
+        }
+    }
+
+    let mut importer = Importer {
+        conn,
+        tmdb_api,
+        user_id,
+        films: HashMap::new(),
+    };
+
+    let mut diary_films = HashSet::new();
+    if let Some(diary) = files.get("diary.csv") {
+        diary_films = importer.import_diary(diary).await?;
+    }
+
+    if let Some(watched) = files.get("watched.csv") {
+        importer.import_watched(watched, &diary_films).await?;
+    }
+
+    if let Some(ratings) = files.get("ratings.csv") {
+        importer.import_ratings(ratings).await?;
+    }
+
+    if let Some(watchlist) = files.get("watchlist.csv") {
+        importer.import_watchlist(watchlist).await?;
+    }
+
+    for list in lists {
+        importer.import_list(&list).await?;
+    }
+
+    Ok(())
+}
+
+type FilmKey = (String, Option<i32>);
+
+struct Importer<'a, C: GenericClient> {
+    conn: &'a mut C,
+    tmdb_api: &'a TmdbApi,
+    user_id: i32,
+    /// Movie ids already resolved during this import, `None` when the film
+    /// couldn't be matched.
+    films: HashMap<FilmKey, Option<i32>>,
+}
+
+impl<C: GenericClient> Importer<'_, C> {
+    /// Imports each diary entry as a play, returning the films found.
+    async fn import_diary(&mut self, content: &str) -> anyhow::Result<HashSet<FilmKey>> {
+        let mut films = HashSet::new();
+
+        for entry in read_csv::<DiaryEntry>(content)? {
+            let watched_at = entry.watched_at()?;
+
+            match self.get_or_add_film(&entry.name, entry.year).await? {
+                Some(media) => self.add_play(watched_at, media).await?,
+                None => {
+                    self.queue(NewImportReviewItem {
+                        watched_at: Some(watched_at),
+                        ..review_item(&entry.name, entry.year, entry.uri)
+                    })
+                    .await?
+                }
+            }
+
+            films.insert((entry.name, entry.year));
+        }
+
+        Ok(films)
+    }
+
+    /// Imports films marked as watched that have no diary entry. Letterboxd
+    /// doesn't know when those were watched, so the date they were marked is
+    /// used instead.
+    async fn import_watched(
+        &mut self,
+        content: &str,
+        diary_films: &HashSet<FilmKey>,
+    ) -> anyhow::Result<()> {
+        for entry in read_csv::<FilmEntry>(content)? {
+            if diary_films.contains(&(entry.name.clone(), entry.year)) {
+                continue;
+            }
+
+            let watched_at = date_to_timestamp(entry.date)?;
+
+            match self.get_or_add_film(&entry.name, entry.year).await? {
+                Some(media) => self.add_play(watched_at, media).await?,
+                None => {
+                    self.queue(NewImportReviewItem {
+                        watched_at: Some(watched_at),
+                        ..review_item(&entry.name, entry.year, entry.uri)
+                    })
+                    .await?
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn import_ratings(&mut self, content: &str) -> anyhow::Result<()> {
+        for entry in read_csv::<RatingEntry>(content)? {
+            // Letterboxd ratings go from half a star to five stars.
+            let rating = ((entry.rating * 2.0).round() as i32).clamp(1, 10);
+            let rated_at = date_to_timestamp(entry.date)?;
+
+            match self.get_or_add_film(&entry.name, entry.year).await? {
+                Some(media) => {
+                    upsert_rating(self.conn, self.user_id, &media, rating, &rated_at).await?
+                }
+                None => {
+                    self.queue(NewImportReviewItem {
+                        rating: Some(rating),
+                        rated_at: Some(rated_at),
+                        ..review_item(&entry.name, entry.year, entry.uri)
+                    })
+                    .await?
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn import_watchlist(&mut self, content: &str) -> anyhow::Result<()> {
+        let Some(watchlist_id) =
+            get_list_id_by_kind(self.conn, self.user_id, ListKind::Watchlist).await?
+        else {
+            anyhow::bail!("watchlist not found");
+        };
+
+        for entry in read_csv::<FilmEntry>(content)? {
+            let listed_at = date_to_timestamp(entry.date)?;
+            self.add_list_item(watchlist_id, &listed_at, entry.name, entry.year, entry.uri)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Imports a file from the `lists` directory as a new personal list.
+    async fn import_list(&mut self, content: &str) -> anyhow::Result<()> {
+        // List exports have two CSV sections: the list details, preceded by
+        // a format version line, and the list entries.
+        let Some(entries_start) = content.find("\nPosition,") else {
+            anyhow::bail!("invalid list file");
+        };
+        let (info, entries) = content.split_at(entries_start + 1);
+        let info = info.split_once('\n').map_or("", |(_, rest)| rest);
+
+        let Some(info) = read_csv::<ListInfo>(info)?.into_iter().next() else {
+            anyhow::bail!("missing list details");
+        };
+        let created_at = date_to_timestamp(info.date)?;
+
+        // Lists imported before are reused, their items are only added once.
+        let list_id =
+            match get_list_id_by_name(self.conn, self.user_id, ListKind::Personal, &info.name)
+                .await?
+            {
+                Some(list_id) => list_id,
+                None => {
+                    insert_list(
+                        self.conn,
+                        self.user_id,
+                        &NewList {
+                            kind: ListKind::Personal,
+                            name: info.name,
+                            description: info
+                                .description
+                                .filter(|description| !description.is_empty()),
+                            created_at: Some(created_at),
+                        },
+                    )
+                    .await?
+                }
+            };
+
+        for entry in read_csv::<ListEntry>(entries)? {
+            self.add_list_item(list_id, &created_at, entry.name, entry.year, entry.uri)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Adds a play unless it was imported already.
+    async fn add_play(&mut self, watched_at: Timestamp, media: Media) -> anyhow::Result<()> {
+        let watch_history = WatchHistory { watched_at, media };
+
+        if get_watch_history_id(self.conn, self.user_id, &watch_history)
+            .await?
+            .is_none()
+        {
+            insert_watch_history(self.conn, self.user_id, &watch_history).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn add_list_item(
+        &mut self,
+        list_id: i32,
+        listed_at: &Timestamp,
+        name: String,
+        year: Option<i32>,
+        uri: Option<String>,
+    ) -> anyhow::Result<()> {
+        match self.get_or_add_film(&name, year).await? {
+            Some(media) => insert_list_item(self.conn, &list_id, &media, Some(listed_at)).await?,
+            None => {
+                self.queue(NewImportReviewItem {
+                    list_id: Some(list_id),
+                    listed_at: Some(*listed_at),
+                    ..review_item(&name, year, uri)
+                })
+                .await?
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn get_or_add_film(
+        &mut self,
+        name: &str,
+        year: Option<i32>,
+    ) -> anyhow::Result<Option<Media>> {
+        let key = (name.to_string(), year);
+
+        let movie_id = match self.films.get(&key) {
+            Some(movie_id) => *movie_id,
+            None => {
+                let movie_id = match self.search_tmdb(name, year).await? {
+                    Some(tmdb_id) => Some(
+                        get_or_add_from_tmdb(self.conn, self.tmdb_api, &tmdb_id, MediaKind::Movie)
+                            .await?
+                            .id,
+                    ),
+                    None => None,
+                };
+                self.films.insert(key, movie_id);
+                movie_id
+            }
+        };
+
+        Ok(movie_id.map(|id| Media {
+            id,
+            kind: MediaKind::Movie,
+        }))
+    }
+
+    /// Looks for a TMDB movie with the exact title, falling back to the only
+    /// result when the search isn't ambiguous.
+    async fn search_tmdb(&self, name: &str, year: Option<i32>) -> anyhow::Result<Option<TmdbId>> {
+        let results = self.tmdb_api.search_movie(name, year).await?.results;
+
+        let name = name.to_lowercase();
+        let exact_match = results.iter().find(|result| {
+            result.title.to_lowercase() == name || result.original_title.to_lowercase() == name
+        });
+
+        let result = match exact_match {
+            Some(result) => Some(result),
+            None if results.len() == 1 => results.first(),
+            None => None,
+        };
+
+        Ok(result.map(|result| result.id.clone()))
+    }
+
+    async fn queue(&mut self, item: NewImportReviewItem) -> anyhow::Result<()> {
+        insert_import_review_item(self.conn, self.user_id, &item).await?;
+        Ok(())
+    }
+}
+
+fn review_item(name: &str, year: Option<i32>, uri: Option<String>) -> NewImportReviewItem {
+    NewImportReviewItem {
+        source: SOURCE.to_string(),
+        title: name.to_string(),
+        release_year: year,
+        url: uri,
+        watched_at: None,
+        rating: None,
+        rated_at: None,
+        list_id: None,
+        listed_at: None,
+    }
+}
+
+pub(super) fn read_csv<T: DeserializeOwned>(content: &str) -> anyhow::Result<Vec<T>> {
+    let entries = csv::Reader::from_reader(content.as_bytes())
+        .deserialize()
+        .collect::<Result<Vec<T>, _>>()?;
+
+    Ok(entries)
+}
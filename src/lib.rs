@@ -1,33 +1,60 @@
-use std::sync::Arc;
+use std::{future::IntoFuture, net::SocketAddr, sync::Arc, time::Duration};
 
 use axum::{
     Router,
     extract::{MatchedPath, Request},
+    middleware,
 };
-use config::AppConfig;
-use deadpool_postgres::Pool;
+use config::{AppConfig, LogFormat};
+use deadpool_postgres::{GenericClient, Pool};
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
+use tokio_util::sync::CancellationToken;
 use tower_http::trace::TraceLayer;
-use tracing::{info, level_filters::LevelFilter};
+use tracing::{debug, info, level_filters::LevelFilter, warn};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
+pub mod auth;
+pub mod backup;
 pub mod config;
 mod db;
 mod filters;
+mod jobs;
+pub mod letterboxd;
+mod library;
+mod metrics;
+mod request_id;
 mod response;
 mod routes;
+mod slug;
+mod tasks;
 pub mod tmdb;
 pub mod trakt;
 
 struct AppState {
     pub pool: Pool,
     pub tmdb_api: tmdb::TmdbApi,
+    pub metrics: metrics::Metrics,
+    pub cookie_secure: bool,
+    pub play_threshold_percent: u8,
+    pub login_limiter: auth::LoginLimiter,
 }
 
 #[derive(Error, Debug)]
 pub enum StartServerError {
+    #[error("failed to set up trace export")]
+    InitTracing(#[source] opentelemetry_otlp::ExporterBuildError),
     #[error("failed to create database connection pool")]
-    CreateDbPool(#[source] deadpool_postgres::CreatePoolError),
+    CreateDbPool(#[source] db::CreatePoolError),
+    #[error("failed to connect to the database")]
+    ConnectDb(#[source] anyhow::Error),
+    #[error("failed to resume interrupted jobs")]
+    ResumeJobs(#[source] anyhow::Error),
+    #[error("failed to create the admin account")]
+    CreateAdmin(#[source] anyhow::Error),
     #[error("failed to bind port")]
     Bind(#[source] std::io::Error),
     #[error("failed to listen on port")]
@@ -35,22 +62,53 @@ pub enum StartServerError {
 }
 
 pub async fn start_server(config: AppConfig) -> Result<(), StartServerError> {
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::builder()
-                .with_default_directive(LevelFilter::INFO.into())
-                .from_env_lossy(),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
+    let tracer_provider = init_tracing(&config).map_err(StartServerError::InitTracing)?;
 
     let pool = db::create_pool(&config).map_err(StartServerError::CreateDbPool)?;
-    let tmdb_api = tmdb::TmdbApi::new(&config.tmdb_api_key);
+    check_connection(&pool, STARTUP_DB_TIMEOUT)
+        .await
+        .map_err(StartServerError::ConnectDb)?;
 
-    let state = Arc::new(AppState { pool, tmdb_api });
+    resume_interrupted_jobs(&pool)
+        .await
+        .map_err(StartServerError::ResumeJobs)?;
+
+    if let Some(password_hash) = &config.admin_password_hash {
+        create_initial_admin(&pool, password_hash)
+            .await
+            .map_err(StartServerError::CreateAdmin)?;
+    }
+
+    let tmdb_api = tmdb::TmdbApi::new(&config.tmdb_api_key, &config.tmdb_language);
+
+    // Not worth failing to start over, the metadata refresh or the
+    // `fetch-metadata` command can do it later.
+    if let Err(err) = enqueue_outdated_metadata_fetch(&pool, tmdb_api.language()).await {
+        warn!(
+            err = format!("{err:#}"),
+            "failed to enqueue fetching outdated metadata"
+        );
+    }
+
+    let metrics = metrics::Metrics::new(&tmdb_api);
+    let state = Arc::new(AppState {
+        pool,
+        tmdb_api,
+        metrics,
+        cookie_secure: config.cookie_secure,
+        play_threshold_percent: config.play_threshold_percent,
+        login_limiter: auth::LoginLimiter::default(),
+    });
 
     let app = Router::new()
-        .merge(routes::main::build_router())
+        .merge(routes::main::build_router(state.clone()))
+        .merge(routes::webhooks::build_router())
+        .merge(routes::api::build_router(state.clone()))
+        .merge(routes::health::build_router())
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            metrics::track_requests,
+        ))
         .layer(
             TraceLayer::new_for_http()
                 .make_span_with(|req: &Request| {
@@ -62,20 +120,495 @@ pub async fn start_server(config: AppConfig) -> Result<(), StartServerError> {
                         .get::<MatchedPath>()
                         .map(|matched_path| matched_path.as_str());
 
-                    tracing::debug_span!("request", %method, %uri, matched_path)
+                    let request_id = req
+                        .extensions()
+                        .get::<request_id::RequestId>()
+                        .map(|request_id| request_id.0.as_str());
+
+                    tracing::info_span!("request", %method, %uri, matched_path, request_id)
                 })
                 .on_failure(()),
         )
-        .with_state(state);
+        .layer(middleware::from_fn(request_id::set_request_id))
+        .with_state(state.clone());
+
+    let tasks = tasks::TaskSupervisor::new();
+    for _ in 0..config.job_workers {
+        let state = state.clone();
+        tasks.spawn("job worker", move |cancel| jobs::run_worker(state, cancel));
+    }
+    if let Some(interval) = config.metadata_refresh_interval {
+        let state = state.clone();
+        tasks.spawn("metadata refresh", move |cancel| {
+            refresh_metadata(state, interval, cancel)
+        });
+    }
+    tasks.spawn("webhook session pruning", move |cancel| {
+        prune_webhook_sessions(state, cancel)
+    });
 
     info!("Running server on http://{}", config.addr);
 
     let listener = tokio::net::TcpListener::bind(config.addr)
         .await
         .map_err(StartServerError::Listen)?;
-    axum::serve(listener, app)
-        .await
-        .map_err(StartServerError::Bind)?;
+    // Client addresses are used to limit failed logins.
+    let server = axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(tasks.cancel_token().cancelled_owned())
+    .into_future();
+    let mut server = std::pin::pin!(server);
+
+    tokio::select! {
+        // Doesn't end before shutdown, except on errors.
+        result = &mut server => return result.map_err(StartServerError::Bind),
+        _ = tasks::shutdown_signal() => {}
+    }
+
+    info!(
+        "Shutting down, waiting up to {} seconds for requests and background tasks",
+        config.shutdown_timeout.as_secs()
+    );
+    let (server_result, tasks_ended) = tokio::join!(
+        tokio::time::timeout(config.shutdown_timeout, server),
+        tasks.shutdown(config.shutdown_timeout),
+    );
+    match server_result {
+        Ok(result) => result.map_err(StartServerError::Bind)?,
+        Err(_) => warn!("Requests still running after the shutdown timeout were dropped"),
+    }
+    if !tasks_ended {
+        warn!("Background tasks still running after the shutdown timeout were dropped");
+    }
+
+    if let Some(tracer_provider) = tracer_provider {
+        // Flushes the spans left, blocking until they're sent.
+        let result = tokio::task::spawn_blocking(move || tracer_provider.shutdown()).await;
+        if let Ok(Err(err)) = result {
+            warn!(%err, "failed to export the last traces");
+        }
+    }
+
+    Ok(())
+}
+
+/// Sets up logging in the configured format, and exporting spans to the
+/// OTLP endpoint if set. The returned provider should be shut down on exit.
+fn init_tracing(
+    config: &AppConfig,
+) -> Result<Option<SdkTracerProvider>, opentelemetry_otlp::ExporterBuildError> {
+    let tracer_provider = match &config.otlp_endpoint {
+        Some(endpoint) => {
+            let exporter = opentelemetry_otlp::SpanExporter::builder()
+                .with_http()
+                .with_endpoint(endpoint)
+                .build()?;
+
+            Some(
+                SdkTracerProvider::builder()
+                    .with_batch_exporter(exporter)
+                    .with_resource(
+                        opentelemetry_sdk::Resource::builder()
+                            .with_service_name("grimoire")
+                            .build(),
+                    )
+                    .build(),
+            )
+        }
+        None => None,
+    };
+
+    let (pretty_layer, json_layer) = match config.log_format {
+        LogFormat::Pretty => (Some(tracing_subscriber::fmt::layer()), None),
+        LogFormat::Json => (
+            None,
+            Some(
+                tracing_subscriber::fmt::layer()
+                    .json()
+                    .flatten_event(true)
+                    .with_current_span(true)
+                    .with_span_list(false),
+            ),
+        ),
+    };
+
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::EnvFilter::builder()
+                .with_default_directive(LevelFilter::INFO.into())
+                .from_env_lossy(),
+        )
+        .with(pretty_layer)
+        .with(json_layer)
+        .with(tracer_provider.as_ref().map(|tracer_provider| {
+            tracing_opentelemetry::layer().with_tracer(tracer_provider.tracer("grimoire"))
+        }))
+        .init();
+
+    Ok(tracer_provider)
+}
+
+/// Enqueues a job fetching the metadata of media added without it, e.g.
+/// imported ones, every interval. See [`library::fetch_missing_metadata`].
+async fn refresh_metadata(
+    state: Arc<AppState>,
+    interval: Duration,
+    cancel: CancellationToken,
+) -> anyhow::Result<()> {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            _ = cancel.cancelled() => return Ok(()),
+            _ = ticker.tick() => {}
+        }
+
+        let result = async {
+            let conn = state.pool.get().await?;
+            // Still to run after a restart or a slow refresh.
+            if !db::has_unfinished_job(&conn, jobs::Job::FETCH_METADATA).await? {
+                jobs::enqueue(&conn, &jobs::Job::FetchMetadata, None, None).await?;
+            }
+            anyhow::Ok(())
+        }
+        .await;
+
+        // Tried again next time, the database may just be unreachable for now.
+        if let Err(err) = result {
+            warn!(
+                err = format!("{err:#}"),
+                "failed to enqueue metadata refresh"
+            );
+        }
+    }
+}
+
+/// Fetches in the background the metadata that's missing or in another
+/// language than the configured one, e.g. after `TMDB_LANGUAGE` changed.
+async fn enqueue_outdated_metadata_fetch(pool: &Pool, language: &str) -> anyhow::Result<()> {
+    let conn = pool.get().await?;
+
+    let outdated = db::get_media_without_metadata(&conn, language).await?;
+    if !outdated.is_empty() && !db::has_unfinished_job(&conn, jobs::Job::FETCH_METADATA).await? {
+        info!(
+            "Fetching the metadata of {} movies and shows in the background",
+            outdated.len()
+        );
+        jobs::enqueue(&conn, &jobs::Job::FetchMetadata, None, None).await?;
+    }
+
+    Ok(())
+}
+
+/// How often webhook sessions that expired are deleted.
+const WEBHOOK_SESSION_PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+async fn prune_webhook_sessions(
+    state: Arc<AppState>,
+    cancel: CancellationToken,
+) -> anyhow::Result<()> {
+    let mut ticker = tokio::time::interval(WEBHOOK_SESSION_PRUNE_INTERVAL);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            _ = cancel.cancelled() => return Ok(()),
+            _ = ticker.tick() => {}
+        }
+
+        let result = async {
+            let conn = state.pool.get().await?;
+            anyhow::Ok(
+                db::delete_expired_webhook_sessions(&conn, routes::webhooks::SESSION_WINDOW_HOURS)
+                    .await?,
+            )
+        }
+        .await;
+
+        match result {
+            Ok(count) if count > 0 => debug!("Deleted {count} expired webhook sessions"),
+            Ok(_) => {}
+            // Tried again next time, the database may just be unreachable for now.
+            Err(err) => warn!(
+                err = format!("{err:#}"),
+                "failed to delete expired webhook sessions"
+            ),
+        }
+    }
+}
+
+/// Puts back in the queue the jobs left running by a server that was killed
+/// before it could do it, which assumes it's the only one using the database.
+async fn resume_interrupted_jobs(pool: &Pool) -> anyhow::Result<()> {
+    let conn = pool.get().await?;
+
+    let count = db::interrupt_running_jobs(&conn).await?;
+    if count > 0 {
+        info!("Recovered {count} jobs interrupted by a server stop");
+    }
+
+    Ok(())
+}
+
+/// Creates an `admin` account with the given password hash, unless there are
+/// users already.
+async fn create_initial_admin(pool: &Pool, password_hash: &str) -> anyhow::Result<()> {
+    let mut conn = pool.get().await?;
+
+    if db::get_users(&conn).await?.is_empty() {
+        db::insert_user(&mut conn, "admin", password_hash, true).await?;
+        info!("Created the admin account");
+    }
+
+    Ok(())
+}
+
+/// Data formats supported by [`export_data`] and [`import_data`].
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DataFormat {
+    /// Grimoire's own JSON backup, see [`backup`].
+    Native,
+    /// ZIP laid out like a Trakt data export.
+    Trakt,
+    /// Letterboxd CSV files. Exports are a single CSV in Letterboxd's import
+    /// format while imports expect Letterboxd's data export ZIP.
+    Letterboxd,
+}
+
+/// Resolves the user the CLI acts as. Without a username, the only user is
+/// used when there's just one.
+async fn resolve_user_id<C: GenericClient>(
+    conn: &C,
+    username: Option<&str>,
+) -> anyhow::Result<i32> {
+    if let Some(username) = username {
+        let Some(user) = db::get_user_by_username(conn, username).await? else {
+            anyhow::bail!("no user named {username}");
+        };
+
+        return Ok(user.id);
+    }
+
+    match db::get_users(conn).await?.as_slice() {
+        [user] => Ok(user.id),
+        [] => anyhow::bail!("there are no users yet, create one with `grimoire user create`"),
+        _ => anyhow::bail!("there are several users, pick one with --user"),
+    }
+}
+
+pub async fn export_data<W: std::io::Write + std::io::Seek>(
+    config: &AppConfig,
+    username: Option<&str>,
+    format: DataFormat,
+    writer: &mut W,
+) -> anyhow::Result<()> {
+    let pool = db::create_pool(config)?;
+    let mut conn = pool.get().await?;
+    let user_id = resolve_user_id(&conn, username).await?;
+
+    match format {
+        DataFormat::Native => backup::export::export_json(&mut conn, user_id, writer).await,
+        DataFormat::Trakt => trakt::export::export_zip(&conn, user_id, writer).await,
+        DataFormat::Letterboxd => letterboxd::export::export_csv(&conn, user_id, writer).await,
+    }
+}
+
+pub async fn import_data<R: std::io::Read + std::io::Seek>(
+    config: &AppConfig,
+    username: Option<&str>,
+    format: DataFormat,
+    reader: &mut R,
+) -> anyhow::Result<()> {
+    let pool = db::create_pool(config)?;
+    let mut conn = pool.get().await?;
+    let user_id = resolve_user_id(&conn, username).await?;
+    let tmdb_api = tmdb::TmdbApi::new(&config.tmdb_api_key, &config.tmdb_language);
+
+    import_into(&mut conn, &tmdb_api, user_id, format, reader).await
+}
+
+/// Imports data into the user's library, from the CLI or a job.
+async fn import_into<C: GenericClient, R: std::io::Read + std::io::Seek>(
+    conn: &mut C,
+    tmdb_api: &tmdb::TmdbApi,
+    user_id: i32,
+    format: DataFormat,
+    reader: &mut R,
+) -> anyhow::Result<()> {
+    match format {
+        DataFormat::Native => backup::import::import_json(conn, user_id, reader).await,
+        DataFormat::Trakt => trakt::import::import_zip(conn, user_id, reader).await,
+        DataFormat::Letterboxd => {
+            letterboxd::import::import_zip(conn, tmdb_api, user_id, reader).await
+        }
+    }
+}
+
+/// Fetches the metadata, credits and collections of media added without
+/// them, see [`library::fetch_missing_metadata`].
+pub async fn fetch_metadata(config: &AppConfig) -> anyhow::Result<usize> {
+    let pool = db::create_pool(config)?;
+    let mut conn = pool.get().await?;
+    let tmdb_api = tmdb::TmdbApi::new(&config.tmdb_api_key, &config.tmdb_language);
+
+    Ok(library::fetch_missing_metadata(&mut conn, &tmdb_api, &CancellationToken::new()).await?)
+}
+
+fn create_trakt_api(config: &AppConfig) -> anyhow::Result<trakt::api::TraktApi> {
+    let (Some(client_id), Some(client_secret)) =
+        (&config.trakt_client_id, &config.trakt_client_secret)
+    else {
+        anyhow::bail!("TRAKT_CLIENT_ID and TRAKT_CLIENT_SECRET must be set to use the Trakt API");
+    };
+
+    Ok(trakt::api::TraktApi::new(
+        &config.trakt_api_url,
+        client_id,
+        client_secret,
+    ))
+}
+
+/// Logs into Trakt, see [`trakt::sync::login`].
+pub async fn trakt_login(
+    config: &AppConfig,
+    username: Option<&str>,
+    show_code: impl FnOnce(&str, &str),
+) -> anyhow::Result<()> {
+    let api = create_trakt_api(config)?;
+    let pool = db::create_pool(config)?;
+    let conn = pool.get().await?;
+    let user_id = resolve_user_id(&conn, username).await?;
+
+    trakt::sync::login(&conn, user_id, &api, show_code).await
+}
+
+/// Syncs with Trakt, see [`trakt::sync::sync`].
+pub async fn trakt_sync(
+    config: &AppConfig,
+    username: Option<&str>,
+    push: bool,
+) -> anyhow::Result<trakt::sync::SyncReport> {
+    let api = create_trakt_api(config)?;
+    let pool = db::create_pool(config)?;
+    let mut conn = pool.get().await?;
+    let user_id = resolve_user_id(&conn, username).await?;
+
+    trakt::sync::sync(&mut conn, user_id, &api, push).await
+}
+
+/// Connects to the database, to check the config.
+pub async fn check_database(config: &AppConfig) -> anyhow::Result<()> {
+    let pool = db::create_pool(config)?;
+
+    check_connection(&pool, STARTUP_DB_TIMEOUT).await
+}
+
+/// How long to wait for the database on startup before giving up.
+const STARTUP_DB_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Runs a query, giving up after the timeout in case the database is
+/// unreachable.
+async fn check_connection(pool: &Pool, timeout: Duration) -> anyhow::Result<()> {
+    tokio::time::timeout(timeout, async {
+        let conn = pool.get().await?;
+        conn.simple_query("SELECT 1").await?;
+
+        Ok(())
+    })
+    .await
+    .map_err(|_| anyhow::anyhow!("timed out after {} seconds", timeout.as_secs()))?
+}
+
+/// Creates a user who can log into the web UI.
+pub async fn create_user(
+    config: &AppConfig,
+    username: &str,
+    password: &str,
+    is_admin: bool,
+) -> anyhow::Result<()> {
+    let password_hash = auth::hash_password(password)?;
+    let pool = db::create_pool(config)?;
+    let mut conn = pool.get().await?;
+
+    if db::insert_user(&mut conn, username, &password_hash, is_admin)
+        .await?
+        .is_none()
+    {
+        anyhow::bail!("a user named {username} already exists");
+    }
+
+    Ok(())
+}
+
+pub async fn list_users(config: &AppConfig) -> anyhow::Result<Vec<auth::User>> {
+    let pool = db::create_pool(config)?;
+    let conn = pool.get().await?;
+
+    Ok(db::get_users(&conn).await?)
+}
+
+/// Sets a user's password, logging out all their sessions.
+pub async fn set_password(
+    config: &AppConfig,
+    username: &str,
+    password: &str,
+) -> anyhow::Result<()> {
+    let password_hash = auth::hash_password(password)?;
+    let pool = db::create_pool(config)?;
+    let mut conn = pool.get().await?;
+    let user_id = resolve_user_id(&conn, Some(username)).await?;
+
+    db::set_user_password_hash(&mut conn, user_id, &password_hash).await?;
+
+    Ok(())
+}
+
+/// Creates an API token for a user and returns it. It can't be retrieved
+/// later, only its hash is stored.
+pub async fn create_api_token(
+    config: &AppConfig,
+    username: Option<&str>,
+    name: &str,
+) -> anyhow::Result<String> {
+    let pool = db::create_pool(config)?;
+    let conn = pool.get().await?;
+    let user_id = resolve_user_id(&conn, username).await?;
+
+    let token = auth::generate_token();
+    if !db::insert_api_token(&conn, user_id, name, &auth::hash_token(&token)).await? {
+        anyhow::bail!("an API token named {name} already exists");
+    }
+
+    Ok(token)
+}
+
+pub async fn list_api_tokens(
+    config: &AppConfig,
+    username: Option<&str>,
+) -> anyhow::Result<Vec<auth::ApiToken>> {
+    let pool = db::create_pool(config)?;
+    let conn = pool.get().await?;
+    let user_id = resolve_user_id(&conn, username).await?;
+
+    Ok(db::get_api_tokens(&conn, user_id).await?)
+}
+
+pub async fn revoke_api_token(
+    config: &AppConfig,
+    username: Option<&str>,
+    name: &str,
+) -> anyhow::Result<()> {
+    let pool = db::create_pool(config)?;
+    let conn = pool.get().await?;
+    let user_id = resolve_user_id(&conn, username).await?;
+
+    if !db::delete_api_token(&conn, user_id, name).await? {
+        anyhow::bail!("no API token named {name}");
+    }
 
     Ok(())
 }
@@ -0,0 +1,776 @@
+use deadpool_postgres::{GenericClient, tokio_postgres};
+use thiserror::Error;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    db::{
+        CreditKind, GetMediaIdError, ImportReviewItemError, InsertCreditsError,
+        InsertListItemError, InsertMovieError, InsertShowError, InsertWatchHistoryError, JobError,
+        Media, MediaExternalId, MediaKind, MediaMetadata, NewCollection, NewCollectionPart,
+        NewCredit, NewEpisode, NewMovie, NewSeason, NewShow, NewTag, PersonError, SetMetadataError,
+        UpsertCollectionError, UpsertRatingError, WatchHistory, delete_import_review_items,
+        get_episode_by_season_and_number, get_media_by_external_ids, get_media_by_tmdb_id,
+        get_media_without_metadata, get_person_id_by_tmdb_id, get_related_import_review_items,
+        get_season_by_show_and_number, insert_list_item, insert_movie, insert_show,
+        insert_watch_history, replace_credits, set_media_metadata, set_media_title,
+        upsert_collection, upsert_person, upsert_rating,
+    },
+    jobs::{self, Job},
+    tmdb::{
+        self, CastCredit, CrewCredit, ExternalSource, FindResults, FullMovie, FullShow, TmdbApi,
+        TmdbId,
+    },
+};
+
+#[derive(Debug, Error)]
+pub enum AddMediaError {
+    #[error("failed to query media")]
+    GetMedia(#[source] GetMediaIdError),
+    #[error("failed to fetch media from tmdb")]
+    Tmdb(#[source] tmdb::ApiError),
+    #[error("failed to insert movie")]
+    InsertMovie(#[source] InsertMovieError),
+    #[error("failed to insert show")]
+    InsertShow(#[source] InsertShowError),
+    #[error("failed to enqueue collection job")]
+    EnqueueCollection(#[source] JobError),
+    #[error("unsupported media kind {0:?}")]
+    UnsupportedKind(MediaKind),
+}
+
+/// Returns the library media for the given TMDB id, fetching and inserting it
+/// from TMDB first if it isn't in the library yet.
+pub async fn get_or_add_from_tmdb<C: GenericClient>(
+    conn: &mut C,
+    tmdb_api: &TmdbApi,
+    tmdb_id: &TmdbId,
+    media_kind: MediaKind,
+) -> Result<Media, AddMediaError> {
+    if let Some(media) = get_media_by_tmdb_id(conn, tmdb_id, &media_kind)
+        .await
+        .map_err(AddMediaError::GetMedia)?
+    {
+        return Ok(media);
+    }
+
+    match media_kind {
+        MediaKind::Movie => add_movie_from_tmdb(conn, tmdb_api, tmdb_id).await,
+        MediaKind::Show => add_show_from_tmdb(conn, tmdb_api, tmdb_id).await,
+        kind => Err(AddMediaError::UnsupportedKind(kind)),
+    }
+}
+
+pub async fn add_movie_from_tmdb<C: GenericClient>(
+    conn: &mut C,
+    tmdb_api: &TmdbApi,
+    tmdb_id: &TmdbId,
+) -> Result<Media, AddMediaError> {
+    let full_movie = tmdb_api
+        .fetch_full_movie(tmdb_id)
+        .await
+        .map_err(AddMediaError::Tmdb)?;
+
+    // Collections are only linked to movies by TMDB id, so they can be
+    // fetched later without holding up the request.
+    if let Some(collection) = &full_movie.belongs_to_collection {
+        jobs::enqueue(
+            conn,
+            &Job::AddCollection {
+                tmdb_id: collection.id.0,
+            },
+            None,
+            None,
+        )
+        .await
+        .map_err(AddMediaError::EnqueueCollection)?;
+    }
+
+    let metadata = movie_metadata(&full_movie, tmdb_api.language());
+
+    insert_movie(
+        conn,
+        &NewMovie {
+            slug: None,
+            title: full_movie.title,
+            release_year: full_movie.release_date.map(|date| date.year() as i32),
+            overview: Some(full_movie.overview),
+            tagline: Some(full_movie.tagline),
+            runtime: Some(full_movie.runtime),
+            external_ids: Some(MediaExternalId {
+                trakt_id: None,
+                trakt_slug: None,
+                tmdb_id: Some(full_movie.id.0),
+                imdb_id: Some(full_movie.imdb_id.to_string()),
+                tvdb_id: None,
+            }),
+            metadata,
+            credits: full_movie
+                .credits
+                .map(|credits| new_credits(&credits.cast, &credits.crew))
+                .unwrap_or_default(),
+        },
+    )
+    .await
+    .map_err(AddMediaError::InsertMovie)
+}
+
+pub async fn add_show_from_tmdb<C: GenericClient>(
+    conn: &mut C,
+    tmdb_api: &TmdbApi,
+    tmdb_id: &TmdbId,
+) -> Result<Media, AddMediaError> {
+    let full_show = tmdb_api
+        .fetch_full_show(tmdb_id)
+        .await
+        .map_err(AddMediaError::Tmdb)?;
+
+    let mut seasons: Vec<NewSeason> = vec![];
+
+    for season in full_show.seasons.iter() {
+        // FIXME: this should get rate limited by TMDB with 50+ seasons.
+        let episodes = tmdb_api
+            .fetch_full_season(&full_show.id, season.season_number)
+            .await
+            .map_err(AddMediaError::Tmdb)?
+            .episodes
+            .iter()
+            .map(|episode| NewEpisode {
+                title: episode.name.to_owned(),
+                number: episode.episode_number,
+                overview: Some(episode.overview.to_owned()),
+                runtime: episode.runtime,
+                external_ids: Some(MediaExternalId {
+                    trakt_id: None,
+                    trakt_slug: None,
+                    tvdb_id: None,
+                    imdb_id: None,
+                    tmdb_id: Some(episode.id.0),
+                }),
+                credits: new_credits(&episode.guest_stars, &episode.crew),
+            })
+            .collect();
+
+        seasons.push(NewSeason {
+            title: season.name.clone(),
+            number: season.season_number,
+            overview: Some(season.overview.clone()),
+            external_ids: Some(MediaExternalId {
+                trakt_id: None,
+                trakt_slug: None,
+                tvdb_id: None,
+                imdb_id: None,
+                tmdb_id: Some(season.id.0),
+            }),
+            episodes: Some(episodes),
+        });
+    }
+
+    let metadata = show_metadata(&full_show, tmdb_api.language());
+
+    insert_show(
+        conn,
+        &NewShow {
+            slug: None,
+            title: full_show.title,
+            release_year: full_show.release_date.map(|date| date.year() as i32),
+            overview: Some(full_show.overview),
+            tagline: Some(full_show.tagline),
+            episode_runtime: full_show.episode_runtimes.first().copied(),
+            seasons: Some(seasons),
+            external_ids: Some(MediaExternalId {
+                trakt_id: None,
+                trakt_slug: None,
+                tmdb_id: Some(full_show.id.0),
+                imdb_id: None,
+                tvdb_id: None,
+            }),
+            metadata,
+            credits: full_show
+                .credits
+                .map(|credits| new_credits(&credits.cast, &credits.crew))
+                .unwrap_or_default(),
+        },
+    )
+    .await
+    .map_err(AddMediaError::InsertShow)
+}
+
+fn movie_metadata(full_movie: &FullMovie, language: &str) -> MediaMetadata {
+    MediaMetadata {
+        language: Some(language.to_string()),
+        original_title: Some(full_movie.original_title.clone()),
+        original_language: Some(full_movie.original_language.clone()),
+        status: Some(full_movie.status.clone()),
+        production_countries: full_movie
+            .production_countries
+            .iter()
+            .map(|country| country.iso_3166_1.clone())
+            .collect(),
+        genres: new_tags(
+            full_movie
+                .genres
+                .iter()
+                .map(|genre| (&genre.id, &genre.name)),
+        ),
+        keywords: new_tags(
+            full_movie
+                .keywords
+                .iter()
+                .flat_map(|keywords| &keywords.keywords)
+                .map(|keyword| (&keyword.id, &keyword.name)),
+        ),
+    }
+}
+
+fn show_metadata(full_show: &FullShow, language: &str) -> MediaMetadata {
+    MediaMetadata {
+        language: Some(language.to_string()),
+        original_title: Some(full_show.original_title.clone()),
+        original_language: Some(full_show.original_language.clone()),
+        status: Some(full_show.status.clone()),
+        production_countries: full_show
+            .production_countries
+            .iter()
+            .map(|country| country.iso_3166_1.clone())
+            .collect(),
+        genres: new_tags(
+            full_show
+                .genres
+                .iter()
+                .map(|genre| (&genre.id, &genre.name)),
+        ),
+        keywords: new_tags(
+            full_show
+                .keywords
+                .iter()
+                .flat_map(|keywords| &keywords.results)
+                .map(|keyword| (&keyword.id, &keyword.name)),
+        ),
+    }
+}
+
+fn new_tags<'a>(tags: impl Iterator<Item = (&'a TmdbId, &'a String)>) -> Vec<NewTag> {
+    tags.map(|(tmdb_id, name)| NewTag {
+        tmdb_id: tmdb_id.0,
+        name: name.clone(),
+    })
+    .collect()
+}
+
+#[derive(Debug, Error)]
+pub enum AddCollectionError {
+    #[error("failed to fetch collection from tmdb")]
+    Tmdb(#[source] tmdb::ApiError),
+    #[error("failed to upsert collection")]
+    UpsertCollection(#[source] UpsertCollectionError),
+}
+
+/// Fetches a collection with all its movies from TMDB and stores it, or
+/// refreshes it if it's known already. Returns its id.
+pub async fn add_collection_from_tmdb<C: GenericClient>(
+    conn: &mut C,
+    tmdb_api: &TmdbApi,
+    tmdb_id: &TmdbId,
+) -> Result<i32, AddCollectionError> {
+    let full_collection = tmdb_api
+        .fetch_collection(tmdb_id)
+        .await
+        .map_err(AddCollectionError::Tmdb)?;
+
+    upsert_collection(
+        conn,
+        &NewCollection {
+            tmdb_id: full_collection.id.0,
+            name: full_collection.name,
+            overview: Some(full_collection.overview).filter(|overview| !overview.is_empty()),
+            parts: full_collection
+                .parts
+                .into_iter()
+                .map(|part| NewCollectionPart {
+                    tmdb_id: part.id.0,
+                    title: part.title,
+                    release_date: part.release_date,
+                })
+                .collect(),
+        },
+    )
+    .await
+    .map_err(AddCollectionError::UpsertCollection)
+}
+
+/// Credits to store from TMDB's, cast first.
+fn new_credits(cast: &[CastCredit], crew: &[CrewCredit]) -> Vec<NewCredit> {
+    let cast = cast.iter().map(|credit| NewCredit {
+        person_tmdb_id: credit.id.0,
+        person_name: credit.name.clone(),
+        kind: CreditKind::Cast,
+        role: credit.character.clone().unwrap_or_default(),
+        department: None,
+    });
+    let crew = crew.iter().map(|credit| NewCredit {
+        person_tmdb_id: credit.id.0,
+        person_name: credit.name.clone(),
+        kind: CreditKind::Crew,
+        role: credit.job.clone(),
+        department: Some(credit.department.clone()),
+    });
+
+    cast.chain(crew).collect()
+}
+
+#[derive(Debug, Error)]
+pub enum FetchMetadataError {
+    #[error("failed to query media")]
+    GetMedia(#[source] GetMediaIdError),
+    #[error("failed to fetch credits from tmdb")]
+    Tmdb(#[source] tmdb::ApiError),
+    #[error("failed to set metadata")]
+    SetMetadata(#[source] SetMetadataError),
+    #[error("failed to insert credits")]
+    InsertCredits(#[source] InsertCreditsError),
+    #[error("failed to add collection")]
+    AddCollection(#[source] AddCollectionError),
+    #[error("failed to start transaction")]
+    StartTransaction(#[source] tokio_postgres::Error),
+    #[error("failed to commit transaction")]
+    CommitTransaction(#[source] tokio_postgres::Error),
+}
+
+/// Fetches the metadata and credits of the movies and shows, and of their
+/// episodes, that have none yet, e.g. because they were imported from a
+/// backup, or whose metadata was fetched in another language than the
+/// configured one, along with the collections of the movies. Their titles are
+/// replaced by the ones in the configured language. Media TMDB doesn't know
+/// anymore is skipped.
+///
+/// Each movie or show is updated in its own transaction. Once `cancel` is
+/// cancelled, the remaining ones are left for the next time.
+///
+/// Returns the number of movies and shows updated.
+pub async fn fetch_missing_metadata<C: GenericClient>(
+    conn: &mut C,
+    tmdb_api: &TmdbApi,
+    cancel: &CancellationToken,
+) -> Result<usize, FetchMetadataError> {
+    let mut updated = 0;
+
+    for (media, tmdb_id) in get_media_without_metadata(conn, tmdb_api.language())
+        .await
+        .map_err(FetchMetadataError::GetMedia)?
+    {
+        if cancel.is_cancelled() {
+            break;
+        }
+
+        let mut tx = conn
+            .transaction()
+            .await
+            .map_err(FetchMetadataError::StartTransaction)?;
+
+        let result = match media.kind {
+            MediaKind::Movie => fetch_movie_metadata(&mut tx, tmdb_api, &media, &tmdb_id).await,
+            _ => fetch_show_metadata(&tx, tmdb_api, &media, &tmdb_id).await,
+        };
+
+        match result {
+            Ok(()) => {
+                tx.commit()
+                    .await
+                    .map_err(FetchMetadataError::CommitTransaction)?;
+                updated += 1;
+            }
+            Err(FetchMetadataError::Tmdb(tmdb::ApiError::NotFound)) => {}
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(updated)
+}
+
+async fn fetch_movie_metadata<C: GenericClient>(
+    conn: &mut C,
+    tmdb_api: &TmdbApi,
+    movie: &Media,
+    tmdb_id: &TmdbId,
+) -> Result<(), FetchMetadataError> {
+    let full_movie = tmdb_api
+        .fetch_full_movie(tmdb_id)
+        .await
+        .map_err(FetchMetadataError::Tmdb)?;
+
+    if let Some(collection) = &full_movie.belongs_to_collection {
+        add_collection_from_tmdb(conn, tmdb_api, &collection.id)
+            .await
+            .map_err(FetchMetadataError::AddCollection)?;
+    }
+
+    set_media_title(conn, movie, &full_movie.title)
+        .await
+        .map_err(FetchMetadataError::SetMetadata)?;
+    set_media_metadata(
+        conn,
+        movie,
+        &movie_metadata(&full_movie, tmdb_api.language()),
+    )
+    .await
+    .map_err(FetchMetadataError::SetMetadata)?;
+
+    let credits = full_movie
+        .credits
+        .map(|credits| new_credits(&credits.cast, &credits.crew))
+        .unwrap_or_default();
+
+    replace_credits(conn, movie, &credits)
+        .await
+        .map_err(FetchMetadataError::InsertCredits)
+}
+
+async fn fetch_show_metadata<C: GenericClient>(
+    conn: &C,
+    tmdb_api: &TmdbApi,
+    show: &Media,
+    tmdb_id: &TmdbId,
+) -> Result<(), FetchMetadataError> {
+    let full_show = tmdb_api
+        .fetch_full_show(tmdb_id)
+        .await
+        .map_err(FetchMetadataError::Tmdb)?;
+
+    set_media_title(conn, show, &full_show.title)
+        .await
+        .map_err(FetchMetadataError::SetMetadata)?;
+    set_media_metadata(conn, show, &show_metadata(&full_show, tmdb_api.language()))
+        .await
+        .map_err(FetchMetadataError::SetMetadata)?;
+
+    let credits = full_show
+        .credits
+        .map(|credits| new_credits(&credits.cast, &credits.crew))
+        .unwrap_or_default();
+
+    replace_credits(conn, show, &credits)
+        .await
+        .map_err(FetchMetadataError::InsertCredits)?;
+
+    for season in full_show.seasons.iter() {
+        let Some(season_media) = get_season_by_show_and_number(conn, show, season.season_number)
+            .await
+            .map_err(FetchMetadataError::GetMedia)?
+        else {
+            continue;
+        };
+
+        // FIXME: this should get rate limited by TMDB with 50+ seasons.
+        let full_season = tmdb_api
+            .fetch_full_season(&full_show.id, season.season_number)
+            .await
+            .map_err(FetchMetadataError::Tmdb)?;
+
+        for episode in full_season.episodes.iter() {
+            let Some(episode_media) =
+                get_episode_by_season_and_number(conn, &season_media, episode.episode_number)
+                    .await
+                    .map_err(FetchMetadataError::GetMedia)?
+            else {
+                continue;
+            };
+
+            replace_credits(
+                conn,
+                &episode_media,
+                &new_credits(&episode.guest_stars, &episode.crew),
+            )
+            .await
+            .map_err(FetchMetadataError::InsertCredits)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum AddPersonError {
+    #[error("failed to query person")]
+    GetPerson(#[source] PersonError),
+    #[error("failed to fetch person from tmdb")]
+    Tmdb(#[source] tmdb::ApiError),
+    #[error("failed to insert person")]
+    InsertPerson(#[source] PersonError),
+}
+
+/// Returns the id of the person with the given TMDB id, fetching and
+/// inserting them from TMDB first if they aren't known yet.
+pub async fn get_or_add_person_from_tmdb<C: GenericClient>(
+    conn: &C,
+    tmdb_api: &TmdbApi,
+    tmdb_id: &TmdbId,
+) -> Result<i32, AddPersonError> {
+    if let Some(person_id) = get_person_id_by_tmdb_id(conn, tmdb_id)
+        .await
+        .map_err(AddPersonError::GetPerson)?
+    {
+        return Ok(person_id);
+    }
+
+    let full_person = tmdb_api
+        .fetch_person(tmdb_id)
+        .await
+        .map_err(AddPersonError::Tmdb)?;
+
+    upsert_person(conn, &full_person.id, &full_person.name)
+        .await
+        .map_err(AddPersonError::InsertPerson)
+}
+
+#[derive(Debug, Error)]
+pub enum ResolveImportReviewItemError {
+    #[error("failed to query import review items")]
+    GetItems(#[source] ImportReviewItemError),
+    #[error("failed to add media")]
+    AddMedia(#[source] AddMediaError),
+    #[error("failed to insert watch history")]
+    InsertWatchHistory(#[source] InsertWatchHistoryError),
+    #[error("failed to upsert rating")]
+    UpsertRating(#[source] UpsertRatingError),
+    #[error("failed to insert list item")]
+    InsertListItem(#[source] InsertListItemError),
+    #[error("failed to delete import review items")]
+    DeleteItems(#[source] ImportReviewItemError),
+    #[error("failed to start transaction")]
+    StartTransaction(#[source] tokio_postgres::Error),
+    #[error("failed to commit transaction")]
+    CommitTransaction(#[source] tokio_postgres::Error),
+}
+
+/// Matches a user's import review item, and every other pending item of
+/// theirs for the same title, to the given TMDB movie. The plays, ratings and
+/// list entries they hold are applied to the movie and the items are removed
+/// from the queue.
+///
+/// Returns `None` when the user has no review item with the given id.
+pub async fn resolve_import_review_item<C: GenericClient>(
+    conn: &mut C,
+    tmdb_api: &TmdbApi,
+    user_id: i32,
+    id: i32,
+    tmdb_id: &TmdbId,
+) -> Result<Option<Media>, ResolveImportReviewItemError> {
+    let mut tx = conn
+        .transaction()
+        .await
+        .map_err(ResolveImportReviewItemError::StartTransaction)?;
+
+    let items = get_related_import_review_items(&tx, user_id, id)
+        .await
+        .map_err(ResolveImportReviewItemError::GetItems)?;
+
+    if items.is_empty() {
+        return Ok(None);
+    }
+
+    let media = get_or_add_from_tmdb(&mut tx, tmdb_api, tmdb_id, MediaKind::Movie)
+        .await
+        .map_err(ResolveImportReviewItemError::AddMedia)?;
+
+    for item in items.iter() {
+        if let Some(watched_at) = item.watched_at {
+            insert_watch_history(
+                &tx,
+                user_id,
+                &WatchHistory {
+                    watched_at,
+                    media: Media {
+                        id: media.id,
+                        kind: MediaKind::Movie,
+                    },
+                },
+            )
+            .await
+            .map_err(ResolveImportReviewItemError::InsertWatchHistory)?;
+        }
+
+        if let Some(rating) = item.rating {
+            let rated_at = item.rated_at.unwrap_or_else(jiff::Timestamp::now);
+            upsert_rating(&tx, user_id, &media, rating, &rated_at)
+                .await
+                .map_err(ResolveImportReviewItemError::UpsertRating)?;
+        }
+
+        if let Some(list_id) = item.list_id {
+            insert_list_item(&mut tx, &list_id, &media, item.listed_at.as_ref())
+                .await
+                .map_err(ResolveImportReviewItemError::InsertListItem)?;
+        }
+    }
+
+    let ids: Vec<i32> = items.iter().map(|item| item.id).collect();
+    delete_import_review_items(&tx, &ids)
+        .await
+        .map_err(ResolveImportReviewItemError::DeleteItems)?;
+
+    tx.commit()
+        .await
+        .map_err(ResolveImportReviewItemError::CommitTransaction)?;
+
+    Ok(Some(media))
+}
+
+/// A movie or episode reported as played by a media server or player,
+/// identified by whatever external ids it knows about.
+#[derive(Debug)]
+pub enum PlayedMedia {
+    Movie {
+        ids: MediaExternalId,
+    },
+    Episode {
+        ids: MediaExternalId,
+        show_ids: MediaExternalId,
+        season_number: Option<i32>,
+        episode_number: Option<i32>,
+    },
+}
+
+#[derive(Debug, Error)]
+pub enum ResolvePlayedMediaError {
+    #[error("failed to query media")]
+    GetMedia(#[source] GetMediaIdError),
+    #[error("failed to look up media on tmdb")]
+    Tmdb(#[source] tmdb::ApiError),
+    #[error("failed to add media")]
+    AddMedia(#[source] AddMediaError),
+}
+
+/// Returns the library media for a played movie or episode, adding the movie
+/// or show from TMDB first if it isn't in the library yet.
+///
+/// Episodes are matched by their own ids, then by number within their show.
+/// When the show isn't known, TMDB is asked which show the episode belongs
+/// to. Returns `None` when nothing matches the ids.
+pub async fn get_or_add_played_media<C: GenericClient>(
+    conn: &mut C,
+    tmdb_api: &TmdbApi,
+    played: &PlayedMedia,
+) -> Result<Option<Media>, ResolvePlayedMediaError> {
+    match played {
+        PlayedMedia::Movie { ids } => {
+            if let Some(media) = get_media_by_external_ids(conn, ids, MediaKind::Movie)
+                .await
+                .map_err(ResolvePlayedMediaError::GetMedia)?
+            {
+                return Ok(Some(media));
+            }
+
+            let tmdb_id = match ids.tmdb_id {
+                Some(tmdb_id) => Some(TmdbId(tmdb_id)),
+                None => find_on_tmdb(tmdb_api, ids)
+                    .await?
+                    .and_then(|results| results.movie_results.into_iter().next())
+                    .map(|result| result.id),
+            };
+
+            let Some(tmdb_id) = tmdb_id else {
+                return Ok(None);
+            };
+
+            get_or_add_from_tmdb(conn, tmdb_api, &tmdb_id, MediaKind::Movie)
+                .await
+                .map(Some)
+                .map_err(ResolvePlayedMediaError::AddMedia)
+        }
+        PlayedMedia::Episode {
+            ids,
+            show_ids,
+            season_number,
+            episode_number,
+        } => {
+            if let Some(media) = get_media_by_external_ids(conn, ids, MediaKind::Episode)
+                .await
+                .map_err(ResolvePlayedMediaError::GetMedia)?
+            {
+                return Ok(Some(media));
+            }
+
+            let show = get_or_add_played_show(conn, tmdb_api, show_ids).await?;
+            let (show, season_number, episode_number) = match (show, season_number, episode_number)
+            {
+                (Some(show), Some(season_number), Some(episode_number)) => {
+                    (show, *season_number, *episode_number)
+                }
+                _ => {
+                    let Some(result) = find_on_tmdb(tmdb_api, ids)
+                        .await?
+                        .and_then(|results| results.tv_episode_results.into_iter().next())
+                    else {
+                        return Ok(None);
+                    };
+
+                    let show =
+                        get_or_add_from_tmdb(conn, tmdb_api, &result.show_id, MediaKind::Show)
+                            .await
+                            .map_err(ResolvePlayedMediaError::AddMedia)?;
+
+                    (show, result.season_number, result.episode_number)
+                }
+            };
+
+            let Some(season) = get_season_by_show_and_number(conn, &show, season_number)
+                .await
+                .map_err(ResolvePlayedMediaError::GetMedia)?
+            else {
+                return Ok(None);
+            };
+
+            get_episode_by_season_and_number(conn, &season, episode_number)
+                .await
+                .map_err(ResolvePlayedMediaError::GetMedia)
+        }
+    }
+}
+
+async fn get_or_add_played_show<C: GenericClient>(
+    conn: &mut C,
+    tmdb_api: &TmdbApi,
+    ids: &MediaExternalId,
+) -> Result<Option<Media>, ResolvePlayedMediaError> {
+    if let Some(media) = get_media_by_external_ids(conn, ids, MediaKind::Show)
+        .await
+        .map_err(ResolvePlayedMediaError::GetMedia)?
+    {
+        return Ok(Some(media));
+    }
+
+    let tmdb_id = match ids.tmdb_id {
+        Some(tmdb_id) => Some(TmdbId(tmdb_id)),
+        None => find_on_tmdb(tmdb_api, ids)
+            .await?
+            .and_then(|results| results.tv_results.into_iter().next())
+            .map(|result| result.id),
+    };
+
+    let Some(tmdb_id) = tmdb_id else {
+        return Ok(None);
+    };
+
+    get_or_add_from_tmdb(conn, tmdb_api, &tmdb_id, MediaKind::Show)
+        .await
+        .map(Some)
+        .map_err(ResolvePlayedMediaError::AddMedia)
+}
+
+/// Looks up media on TMDB by its IMDb id, or its TVDB id if it has none.
+async fn find_on_tmdb(
+    tmdb_api: &TmdbApi,
+    ids: &MediaExternalId,
+) -> Result<Option<FindResults>, ResolvePlayedMediaError> {
+    let (external_id, source) = match (&ids.imdb_id, ids.tvdb_id) {
+        (Some(imdb_id), _) => (imdb_id.clone(), ExternalSource::Imdb),
+        (None, Some(tvdb_id)) => (tvdb_id.to_string(), ExternalSource::Tvdb),
+        (None, None) => return Ok(None),
+    };
+
+    match tmdb_api.find_by_external_id(&external_id, source).await {
+        Ok(results) => Ok(Some(results)),
+        Err(tmdb::ApiError::NotFound) => Ok(None),
+        Err(err) => Err(ResolvePlayedMediaError::Tmdb(err)),
+    }
+}
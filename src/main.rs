@@ -1,13 +1,298 @@
-use std::process;
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Cursor, Write},
+    path::PathBuf,
+    process,
+};
 
-use grimoire::{StartServerError, config::AppConfig, start_server};
+use clap::{Parser, Subcommand, ValueEnum};
+use grimoire::{
+    DataFormat, auth, check_database,
+    config::{AppConfig, ConfigLayer},
+    create_api_token, create_user, export_data, fetch_metadata, import_data, list_api_tokens,
+    list_users, revoke_api_token, set_password, start_server, trakt_login, trakt_sync,
+};
+
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// User to act as, only needed when there are several
+    #[arg(long, global = true)]
+    user: Option<String>,
+    /// TOML config file, overridden by environment variables and flags
+    #[arg(long, global = true, env = "GRIMOIRE_CONFIG")]
+    config: Option<PathBuf>,
+    /// Address to listen on, overrides ADDRESS
+    #[arg(long, global = true)]
+    address: Option<String>,
+    /// PostgreSQL connection URL, overrides DATABASE_URL
+    #[arg(long, global = true)]
+    database_url: Option<String>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the web server (default)
+    Serve,
+    /// Export all data
+    Export {
+        #[arg(long, value_enum, default_value_t = Format::Native)]
+        format: Format,
+        /// File to write the export to, stdout when omitted
+        output: Option<PathBuf>,
+    },
+    /// Import data from a file
+    Import {
+        #[arg(long, value_enum, default_value_t = Format::Native)]
+        format: Format,
+        /// File to read
+        input: PathBuf,
+    },
+    /// Fetch metadata, cast, crew and collections from TMDB for media imported
+    /// without them
+    FetchMetadata,
+    /// Sync with a Trakt account
+    Trakt {
+        #[command(subcommand)]
+        command: TraktCommand,
+    },
+    /// Manage users
+    User {
+        #[command(subcommand)]
+        command: UserCommand,
+    },
+    /// Manage API tokens, used by scripts and webhooks
+    Token {
+        #[command(subcommand)]
+        command: TokenCommand,
+    },
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Validate the configuration, print it without secrets and connect to
+    /// the database
+    Check,
+}
+
+#[derive(Subcommand)]
+enum UserCommand {
+    /// Create a user
+    Create {
+        username: String,
+        /// Let the user manage other users
+        #[arg(long)]
+        admin: bool,
+    },
+    /// List users
+    List,
+    /// Set the password of a user, logging them out everywhere
+    Password { username: String },
+    /// Print a hash of a password, to use as ADMIN_PASSWORD_HASH
+    HashPassword,
+}
+
+#[derive(Subcommand)]
+enum TokenCommand {
+    /// Create a token and print it
+    Create {
+        /// Name to tell the token apart, e.g. what uses it
+        name: String,
+    },
+    /// List tokens
+    List,
+    /// Revoke a token
+    Revoke { name: String },
+}
+
+#[derive(Subcommand)]
+enum TraktCommand {
+    /// Log into Trakt
+    Login,
+    /// Pull new plays from Trakt
+    Sync {
+        /// Also push plays and watchlist items added locally
+        #[arg(long)]
+        push: bool,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    /// Full JSON backup
+    Native,
+    /// Trakt data export ZIP
+    Trakt,
+    /// Letterboxd CSV
+    Letterboxd,
+}
+
+impl From<Format> for DataFormat {
+    fn from(format: Format) -> Self {
+        match format {
+            Format::Native => DataFormat::Native,
+            Format::Trakt => DataFormat::Trakt,
+            Format::Letterboxd => DataFormat::Letterboxd,
+        }
+    }
+}
 
 #[tokio::main]
-async fn main() -> Result<(), StartServerError> {
-    let config = AppConfig::from_env().unwrap_or_else(|err| {
-        eprintln!("Couldn't parse config: {err}");
+async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
+    // Hashing a password to put in the config doesn't need one.
+    if let Some(Command::User {
+        command: UserCommand::HashPassword,
+    }) = cli.command
+    {
+        println!("{}", auth::hash_password(&prompt_password()?)?);
+        return Ok(());
+    }
+
+    let overrides = ConfigLayer {
+        address: cli.address,
+        database_url: cli.database_url,
+        ..ConfigLayer::default()
+    };
+    let config = AppConfig::load(cli.config.as_deref(), overrides).unwrap_or_else(|err| {
+        eprintln!("Couldn't load config: {:#}", anyhow::Error::from(err));
         process::exit(1);
     });
 
-    start_server(config).await
+    let user = cli.user.as_deref();
+
+    match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => start_server(config).await?,
+        Command::Export {
+            format,
+            output: Some(path),
+        } => {
+            let mut file = BufWriter::new(File::create(path)?);
+            export_data(&config, user, format.into(), &mut file).await?;
+            file.flush()?;
+        }
+        Command::Export {
+            format,
+            output: None,
+        } => {
+            // stdout can't seek, which ZIP exports need.
+            let mut buffer = Cursor::new(Vec::new());
+            export_data(&config, user, format.into(), &mut buffer).await?;
+            std::io::stdout().lock().write_all(buffer.get_ref())?;
+        }
+        Command::Import { format, input } => {
+            let mut file = BufReader::new(File::open(input)?);
+            import_data(&config, user, format.into(), &mut file).await?
+        }
+        Command::FetchMetadata => {
+            let count = fetch_metadata(&config).await?;
+            println!("Fetched the metadata of {count} movies and shows");
+        }
+        Command::Trakt {
+            command: TraktCommand::Login,
+        } => {
+            trakt_login(&config, user, |user_code, verification_url| {
+                println!("Go to {verification_url} and enter the code {user_code}");
+            })
+            .await?;
+            println!("Logged into Trakt");
+        }
+        Command::Trakt {
+            command: TraktCommand::Sync { push },
+        } => {
+            let report = trakt_sync(&config, user, push).await?;
+            println!("Pulled {} plays from Trakt", report.pulled_plays);
+            if report.removed_watchlist_items > 0 {
+                println!(
+                    "Removed {} watchlist items that were removed on Trakt",
+                    report.removed_watchlist_items
+                );
+            }
+            if push {
+                println!(
+                    "Pushed {} plays and {} watchlist items to Trakt",
+                    report.pushed_plays, report.pushed_watchlist_items
+                );
+            }
+        }
+        Command::User {
+            command: UserCommand::Create { username, admin },
+        } => {
+            create_user(&config, &username, &prompt_password()?, admin).await?;
+            println!("Created {username}");
+        }
+        Command::User {
+            command: UserCommand::List,
+        } => {
+            for user in list_users(&config).await? {
+                let role = if user.is_admin { "admin" } else { "user" };
+                println!("{}\t{role}\tcreated {}", user.username, user.created_at);
+            }
+        }
+        Command::User {
+            command: UserCommand::Password { username },
+        } => {
+            set_password(&config, &username, &prompt_password()?).await?;
+            println!("Password set, all of {username}'s sessions were logged out");
+        }
+        Command::User {
+            command: UserCommand::HashPassword,
+        } => unreachable!("handled before loading the config"),
+        Command::Token {
+            command: TokenCommand::Create { name },
+        } => {
+            let token = create_api_token(&config, user, &name).await?;
+            println!("{token}");
+        }
+        Command::Token {
+            command: TokenCommand::List,
+        } => {
+            for token in list_api_tokens(&config, user).await? {
+                let last_used_at = token
+                    .last_used_at
+                    .map(|last_used_at| last_used_at.to_string())
+                    .unwrap_or("never".to_string());
+                println!(
+                    "{}\tcreated {}\tlast used {}",
+                    token.name, token.created_at, last_used_at
+                );
+            }
+        }
+        Command::Token {
+            command: TokenCommand::Revoke { name },
+        } => {
+            revoke_api_token(&config, user, &name).await?;
+            println!("Revoked {name}");
+        }
+        Command::Config {
+            command: ConfigCommand::Check,
+        } => {
+            println!("{config}");
+            check_database(&config).await?;
+            println!("Connected to the database");
+        }
+    }
+
+    Ok(())
+}
+
+fn prompt_password() -> anyhow::Result<String> {
+    let password = rpassword::prompt_password("Password: ")?;
+    if password.is_empty() {
+        anyhow::bail!("password can't be empty");
+    }
+
+    if rpassword::prompt_password("Confirm password: ")? != password {
+        anyhow::bail!("passwords don't match");
+    }
+
+    Ok(password)
 }
@@ -0,0 +1,222 @@
+//! Prometheus metrics, served by `/metrics`.
+
+use std::{sync::Arc, time::Instant};
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::Method,
+    middleware::Next,
+    response::Response,
+};
+use deadpool_postgres::Pool;
+use prometheus_client::{
+    encoding::{EncodeLabelSet, text::encode},
+    metrics::{
+        counter::Counter,
+        family::Family,
+        gauge::Gauge,
+        histogram::{Histogram, exponential_buckets},
+    },
+    registry::Registry,
+};
+
+use tracing::warn;
+
+use crate::{AppState, db, tmdb::TmdbApi};
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct RequestLabels {
+    /// Standard methods only, others are counted as `other`.
+    method: &'static str,
+    /// Route the request matched, e.g. `/movie/{movie}`, to keep the number
+    /// of series bounded.
+    path: String,
+    status: u16,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct PoolLabels {
+    /// idle, in_use or waiting, the latter being requests waiting for a
+    /// connection.
+    state: &'static str,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct ImportReviewLabels {
+    source: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct JobLabels {
+    kind: &'static str,
+    /// succeeded, retried, failed or interrupted.
+    outcome: &'static str,
+}
+
+pub struct Metrics {
+    registry: Registry,
+    request_durations: Family<RequestLabels, Histogram>,
+    pool_connections: Family<PoolLabels, Gauge>,
+    pool_max_size: Gauge,
+    import_review_items: Family<ImportReviewLabels, Gauge>,
+    job_runs: Family<JobLabels, Counter>,
+}
+
+impl Metrics {
+    pub fn new(tmdb_api: &TmdbApi) -> Self {
+        let mut registry = Registry::with_prefix("grimoire");
+
+        let request_durations =
+            Family::<RequestLabels, Histogram>::new_with_constructor(request_histogram);
+        registry.register(
+            "http_request_duration_seconds",
+            "Time taken to respond to HTTP requests",
+            request_durations.clone(),
+        );
+
+        let pool_connections = Family::<PoolLabels, Gauge>::default();
+        registry.register(
+            "db_pool_connections",
+            "Database pool connections by state",
+            pool_connections.clone(),
+        );
+
+        let pool_max_size = Gauge::default();
+        registry.register(
+            "db_pool_max_size",
+            "Maximum number of database pool connections",
+            pool_max_size.clone(),
+        );
+
+        registry.register(
+            "tmdb_requests",
+            "Requests to the TMDB API by endpoint and outcome",
+            tmdb_api.request_counts().clone(),
+        );
+
+        let import_review_items = Family::<ImportReviewLabels, Gauge>::default();
+        registry.register(
+            "import_review_items",
+            "Imported entries waiting to be matched by hand, by source",
+            import_review_items.clone(),
+        );
+
+        let job_runs = Family::<JobLabels, Counter>::default();
+        registry.register(
+            "job_runs",
+            "Background job attempts by kind and outcome",
+            job_runs.clone(),
+        );
+
+        Self {
+            registry,
+            request_durations,
+            pool_connections,
+            pool_max_size,
+            import_review_items,
+            job_runs,
+        }
+    }
+
+    pub fn record_job_run(&self, kind: &'static str, outcome: &'static str) {
+        self.job_runs
+            .get_or_create(&JobLabels { kind, outcome })
+            .inc();
+    }
+
+    /// Updates the metrics read from the pool and the database, then
+    /// encodes all of them in the OpenMetrics text format. When the database
+    /// can't be queried, the metrics read from it keep their last values so
+    /// that the others are still scraped.
+    pub async fn encode(&self, pool: &Pool) -> anyhow::Result<String> {
+        let status = pool.status();
+        let in_use = status.size.saturating_sub(status.available);
+        for (state, count) in [
+            ("idle", status.available),
+            ("in_use", in_use),
+            ("waiting", status.waiting),
+        ] {
+            self.pool_connections
+                .get_or_create(&PoolLabels { state })
+                .set(count as i64);
+        }
+        self.pool_max_size.set(status.max_size as i64);
+
+        let counts = async {
+            let conn = pool.get().await?;
+            anyhow::Ok(db::count_import_review_items(&conn).await?)
+        }
+        .await;
+        match counts {
+            Ok(counts) => {
+                // Sources with no items left would keep their last count
+                // otherwise.
+                self.import_review_items.clear();
+                for (source, count) in counts {
+                    self.import_review_items
+                        .get_or_create(&ImportReviewLabels { source })
+                        .set(count);
+                }
+            }
+            Err(err) => warn!(
+                err = format!("{err:#}"),
+                "failed to count import review items for metrics"
+            ),
+        }
+
+        let mut buffer = String::new();
+        encode(&mut buffer, &self.registry)?;
+
+        Ok(buffer)
+    }
+}
+
+/// Clients can send any method, which would add a series each.
+fn method_label(method: &Method) -> &'static str {
+    match *method {
+        Method::GET => "GET",
+        Method::HEAD => "HEAD",
+        Method::POST => "POST",
+        Method::PUT => "PUT",
+        Method::DELETE => "DELETE",
+        Method::PATCH => "PATCH",
+        Method::OPTIONS => "OPTIONS",
+        Method::CONNECT => "CONNECT",
+        Method::TRACE => "TRACE",
+        _ => "other",
+    }
+}
+
+fn request_histogram() -> Histogram {
+    // From 5 ms to about 10 s.
+    Histogram::new(exponential_buckets(0.005, 2.0, 12))
+}
+
+/// Records how long requests take by the route they matched.
+pub async fn track_requests(
+    State(state): State<Arc<AppState>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let method = method_label(req.method());
+    let path = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|matched_path| matched_path.as_str().to_string())
+        .unwrap_or_default();
+
+    let started_at = Instant::now();
+    let response = next.run(req).await;
+
+    state
+        .metrics
+        .request_durations
+        .get_or_create(&RequestLabels {
+            method,
+            path,
+            status: response.status().as_u16(),
+        })
+        .observe(started_at.elapsed().as_secs_f64());
+
+    response
+}
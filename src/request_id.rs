@@ -0,0 +1,54 @@
+//! Request ids, taken from the `X-Request-Id` header, e.g. set by a proxy, or
+//! generated. They're logged with the request span, shown on error pages and
+//! sent back in the response.
+
+use axum::{
+    extract::Request,
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use rand::Rng;
+
+static HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest request id accepted from clients, longer ones are replaced.
+const MAX_LENGTH: usize = 128;
+
+/// Request extension holding the id.
+#[derive(Clone, Debug)]
+pub struct RequestId(pub String);
+
+tokio::task_local! {
+    static CURRENT: RequestId;
+}
+
+/// Id of the request being handled, for responses built without access to
+/// the request such as errors.
+pub fn current() -> Option<String> {
+    CURRENT.try_with(|id| id.0.clone()).ok()
+}
+
+pub async fn set_request_id(mut req: Request, next: Next) -> Response {
+    let id = req
+        .headers()
+        .get(&HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| is_valid(id))
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("{:032x}", rand::rng().random::<u128>()));
+
+    req.extensions_mut().insert(RequestId(id.clone()));
+    let mut response = CURRENT.scope(RequestId(id.clone()), next.run(req)).await;
+
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(HEADER.clone(), value);
+    }
+
+    response
+}
+
+/// Ids end up in logs, so only short printable ones are kept.
+fn is_valid(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_LENGTH && id.bytes().all(|byte| byte.is_ascii_graphic())
+}
@@ -1,16 +1,43 @@
 use askama::Template;
 use axum::{
+    Json,
     http::StatusCode,
     response::{Html, IntoResponse, Response},
 };
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::request_id;
 
 #[derive(Debug)]
 pub enum AppError {
     BadRequest,
+    Unauthorized,
+    Forbidden,
     NotFound,
+    Conflict,
     Internal(anyhow::Error),
 }
 
+impl AppError {
+    fn into_status_and_message(self) -> (StatusCode, String) {
+        match self {
+            AppError::BadRequest => (StatusCode::BAD_REQUEST, "Bad Request".to_owned()),
+            AppError::Unauthorized => (StatusCode::UNAUTHORIZED, "Unauthorized".to_owned()),
+            AppError::Forbidden => (StatusCode::FORBIDDEN, "Forbidden".to_owned()),
+            AppError::NotFound => (StatusCode::NOT_FOUND, "Not Found".to_owned()),
+            AppError::Conflict => (StatusCode::CONFLICT, "Conflict".to_owned()),
+            AppError::Internal(err) => {
+                tracing::error!(err = format!("{err:#}"), "internal server error");
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Something went wrong".to_owned(),
+                )
+            }
+        }
+    }
+}
+
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
         #[derive(Template)]
@@ -18,25 +45,52 @@ impl IntoResponse for AppError {
         struct ErrorTemplate {
             code: u16,
             message: String,
+            request_id: Option<String>,
         }
 
-        let (status, message) = match self {
-            AppError::BadRequest => (StatusCode::BAD_REQUEST, "Bad Request".to_owned()),
-            AppError::NotFound => (StatusCode::NOT_FOUND, "Not Found".to_owned()),
-            AppError::Internal(err) => {
-                tracing::error!(%err, "internal server error");
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "Something went wrong".to_owned(),
-                )
-            }
-        };
+        let (status, message) = self.into_status_and_message();
 
         (
             status,
             HtmlTemplate(ErrorTemplate {
                 code: status.as_u16(),
                 message,
+                request_id: request_id::current(),
+            }),
+        )
+            .into_response()
+    }
+}
+
+/// [`AppError`] rendered as JSON, for the API.
+#[derive(Debug)]
+pub struct JsonError(pub AppError);
+
+impl From<AppError> for JsonError {
+    fn from(err: AppError) -> Self {
+        JsonError(err)
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ErrorBody {
+    /// HTTP status code.
+    code: u16,
+    message: String,
+    /// Id of the request, to find it in the logs.
+    request_id: Option<String>,
+}
+
+impl IntoResponse for JsonError {
+    fn into_response(self) -> Response {
+        let (status, message) = self.0.into_status_and_message();
+
+        (
+            status,
+            Json(ErrorBody {
+                code: status.as_u16(),
+                message,
+                request_id: request_id::current(),
             }),
         )
             .into_response()
@@ -1 +1,4 @@
+pub mod api;
+pub mod health;
 pub mod main;
+pub mod webhooks;
@@ -0,0 +1,92 @@
+use std::sync::Arc;
+
+use axum::{
+    Router,
+    extract::{
+        FromRequest, FromRequestParts,
+        rejection::{JsonRejection, PathRejection, QueryRejection},
+    },
+    extract::{Request, State},
+    http::header,
+    middleware::{self, Next},
+    response::Response,
+    routing::post,
+};
+
+use crate::{
+    AppState,
+    response::{AppError, JsonError},
+};
+
+mod scrobble;
+mod v1;
+
+pub fn build_router(state: Arc<AppState>) -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/api/scrobble/start", post(scrobble::post_scrobble_start))
+        .route("/api/scrobble/pause", post(scrobble::post_scrobble_pause))
+        .route("/api/scrobble/stop", post(scrobble::post_scrobble_stop))
+        .nest("/api/v1", v1::build_router())
+        .route_layer(middleware::from_fn_with_state(state, require_bearer_token))
+        .route("/api/v1/openapi.json", axum::routing::get(v1::get_openapi))
+}
+
+/// Like [`axum::Json`], but rejects invalid bodies with a [`JsonError`].
+#[derive(FromRequest)]
+#[from_request(via(axum::Json), rejection(JsonError))]
+pub struct ApiJson<T>(pub T);
+
+/// Like [`axum::extract::Query`], but rejects invalid queries with a
+/// [`JsonError`].
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Query), rejection(JsonError))]
+pub struct ApiQuery<T>(pub T);
+
+/// Like [`axum::extract::Path`], but rejects invalid paths with a
+/// [`JsonError`].
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Path), rejection(JsonError))]
+pub struct ApiPath<T>(pub T);
+
+impl From<JsonRejection> for JsonError {
+    fn from(_: JsonRejection) -> Self {
+        JsonError(AppError::BadRequest)
+    }
+}
+
+impl From<QueryRejection> for JsonError {
+    fn from(_: QueryRejection) -> Self {
+        JsonError(AppError::BadRequest)
+    }
+}
+
+impl From<PathRejection> for JsonError {
+    fn from(_: PathRejection) -> Self {
+        JsonError(AppError::BadRequest)
+    }
+}
+
+/// The user whose API token authenticated the request, added to requests by
+/// [`require_bearer_token`].
+#[derive(Clone, Copy)]
+pub struct ApiUser(pub i32);
+
+/// Rejects requests without an API token in the `Authorization: Bearer`
+/// header.
+async fn require_bearer_token(
+    State(state): State<Arc<AppState>>,
+    mut req: Request,
+    next: Next,
+) -> Result<Response, JsonError> {
+    let token = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or(AppError::Unauthorized)?;
+
+    let user_id = super::webhooks::check_api_token(&state, token).await?;
+    req.extensions_mut().insert(ApiUser(user_id));
+
+    Ok(next.run(req).await)
+}
@@ -0,0 +1,318 @@
+use std::sync::Arc;
+
+use axum::{Extension, Json, extract::State, http::StatusCode};
+use deadpool_postgres::GenericClient;
+use jiff::ToSpan;
+use serde::{Deserialize, Serialize};
+
+use super::{ApiJson, ApiUser};
+use crate::{
+    AppState,
+    db::{
+        Media, ScrobbleState, WatchHistory, delete_scrobble_session, has_play_since,
+        insert_watch_history, upsert_scrobble_session,
+    },
+    library::{PlayedMedia, get_or_add_played_media},
+    response::{AppError, JsonError},
+    trakt::TraktExternalIds,
+};
+
+/// Body of the scrobble endpoints, shaped like Trakt's: either a movie, or a
+/// show with an episode given by number or by its own ids.
+#[derive(Deserialize, Debug)]
+pub struct ScrobbleRequest {
+    movie: Option<ScrobbleItem>,
+    show: Option<ScrobbleItem>,
+    episode: Option<ScrobbleEpisode>,
+    /// Percent watched, from 0 to 100.
+    progress: f32,
+}
+
+#[derive(Deserialize, Debug)]
+struct ScrobbleItem {
+    #[serde(default)]
+    ids: TraktExternalIds,
+}
+
+#[derive(Deserialize, Debug)]
+struct ScrobbleEpisode {
+    season: Option<i32>,
+    number: Option<i32>,
+    #[serde(default)]
+    ids: TraktExternalIds,
+}
+
+#[derive(Serialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum ScrobbleAction {
+    Start,
+    Pause,
+    /// Playback stopped past the play threshold and a play was recorded.
+    Scrobble,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ScrobbleResponse {
+    action: ScrobbleAction,
+    progress: f32,
+    /// Id of the recorded play.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<i32>,
+}
+
+pub async fn post_scrobble_start(
+    State(state): State<Arc<AppState>>,
+    Extension(ApiUser(user_id)): Extension<ApiUser>,
+    ApiJson(request): ApiJson<ScrobbleRequest>,
+) -> Result<(StatusCode, Json<ScrobbleResponse>), JsonError> {
+    scrobble(&state, user_id, request, ScrobbleAction::Start).await
+}
+
+pub async fn post_scrobble_pause(
+    State(state): State<Arc<AppState>>,
+    Extension(ApiUser(user_id)): Extension<ApiUser>,
+    ApiJson(request): ApiJson<ScrobbleRequest>,
+) -> Result<(StatusCode, Json<ScrobbleResponse>), JsonError> {
+    scrobble(&state, user_id, request, ScrobbleAction::Pause).await
+}
+
+/// Ends the session. Like on Trakt, stopping before the play threshold is
+/// treated as a pause, so the session stays around with its progress, and
+/// duplicate stops are answered with 409 Conflict, see [`end_session`].
+pub async fn post_scrobble_stop(
+    State(state): State<Arc<AppState>>,
+    Extension(ApiUser(user_id)): Extension<ApiUser>,
+    ApiJson(request): ApiJson<ScrobbleRequest>,
+) -> Result<(StatusCode, Json<ScrobbleResponse>), JsonError> {
+    let action = if request.progress >= f32::from(state.play_threshold_percent) {
+        ScrobbleAction::Scrobble
+    } else {
+        ScrobbleAction::Pause
+    };
+
+    scrobble(&state, user_id, request, action).await
+}
+
+async fn scrobble(
+    state: &AppState,
+    user_id: i32,
+    request: ScrobbleRequest,
+    action: ScrobbleAction,
+) -> Result<(StatusCode, Json<ScrobbleResponse>), JsonError> {
+    if !(0.0..=100.0).contains(&request.progress) {
+        return Err(AppError::BadRequest.into());
+    }
+
+    let played = match (request.movie, request.show, request.episode) {
+        (Some(movie), None, None) => PlayedMedia::Movie {
+            ids: movie.ids.into(),
+        },
+        (None, show, Some(episode)) => PlayedMedia::Episode {
+            ids: episode.ids.into(),
+            show_ids: show.map(|show| show.ids).unwrap_or_default().into(),
+            season_number: episode.season,
+            episode_number: episode.number,
+        },
+        _ => return Err(AppError::BadRequest.into()),
+    };
+
+    let mut conn = state
+        .pool
+        .get()
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    let Some(media) = get_or_add_played_media(&mut conn, &state.tmdb_api, &played)
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?
+    else {
+        return Err(AppError::NotFound.into());
+    };
+
+    let id = match action {
+        ScrobbleAction::Start | ScrobbleAction::Pause => {
+            let scrobble_state = match action {
+                ScrobbleAction::Start => ScrobbleState::Playing,
+                _ => ScrobbleState::Paused,
+            };
+
+            upsert_scrobble_session(&conn, user_id, &media, scrobble_state, request.progress)
+                .await
+                .map_err(|err| AppError::Internal(err.into()))?;
+
+            None
+        }
+        ScrobbleAction::Scrobble => {
+            let id = end_session(&mut conn, user_id, media)
+                .await
+                .map_err(AppError::Internal)?
+                .ok_or(AppError::Conflict)?;
+
+            Some(id)
+        }
+    };
+
+    // Created only when there's a new play, otherwise the session was just
+    // updated.
+    let status = match id {
+        Some(_) => StatusCode::CREATED,
+        None => StatusCode::OK,
+    };
+
+    Ok((
+        status,
+        Json(ScrobbleResponse {
+            action,
+            progress: request.progress,
+            id,
+        }),
+    ))
+}
+
+/// Plays of the media this recent make a stop a duplicate, e.g. one retried
+/// by the client after a start sent by mistake.
+const DUPLICATE_PLAY_MINUTES: i64 = 10;
+
+/// Ends the user's session for the media with a play and returns the play's
+/// id. `None` is returned for duplicate stops, when there's no session to end
+/// or the media was just played, which happens when clients retry.
+///
+/// Both happen in one transaction, so that the session isn't lost when the
+/// play fails to be recorded. Concurrent stops wait on the deleted session
+/// until this commits, then find nothing left to end.
+async fn end_session<C: GenericClient>(
+    conn: &mut C,
+    user_id: i32,
+    media: Media,
+) -> anyhow::Result<Option<i32>> {
+    let tx = conn.transaction().await?;
+
+    if !delete_scrobble_session(&tx, user_id, &media).await? {
+        return Ok(None);
+    }
+
+    let now = jiff::Timestamp::now();
+    let since = now - DUPLICATE_PLAY_MINUTES.minutes();
+    if has_play_since(&tx, user_id, &media, &since).await? {
+        // The session still ends, only without a second play.
+        tx.commit().await?;
+        return Ok(None);
+    }
+
+    let id = insert_watch_history(
+        &tx,
+        user_id,
+        &WatchHistory {
+            media,
+            watched_at: now,
+        },
+    )
+    .await?;
+    tx.commit().await?;
+
+    Ok(Some(id))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use tokio_postgres::NoTls;
+
+    use super::*;
+    use crate::db::MediaKind;
+
+    /// Connects to `TEST_DATABASE_URL` with the schema created in a schema of
+    /// its own. `None` when the variable isn't set, to skip tests that need a
+    /// database.
+    async fn test_db(name: &str) -> Option<deadpool_postgres::Object> {
+        let url = std::env::var("TEST_DATABASE_URL").ok()?;
+        let schema = format!("test_{name}_{}", std::process::id());
+
+        let mut config = tokio_postgres::Config::from_str(&url).unwrap();
+        config.options(format!("-c search_path={schema}"));
+        let pool = deadpool_postgres::Pool::builder(deadpool_postgres::Manager::new(config, NoTls))
+            .max_size(1)
+            .build()
+            .unwrap();
+
+        let conn = pool.get().await.unwrap();
+        conn.batch_execute(&format!(
+            "DROP SCHEMA IF EXISTS {schema} CASCADE;
+            CREATE SCHEMA {schema};
+            {}",
+            include_str!("../../../db/schema.sql")
+        ))
+        .await
+        .unwrap();
+
+        Some(conn)
+    }
+
+    async fn drop_test_db(conn: deadpool_postgres::Object) {
+        conn.batch_execute(
+            "DO $$ BEGIN EXECUTE 'DROP SCHEMA ' || current_schema() || ' CASCADE'; END $$",
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn stopping_twice_records_a_single_play() {
+        let Some(mut conn) = test_db("scrobble").await else {
+            eprintln!("TEST_DATABASE_URL is not set, skipping");
+            return;
+        };
+
+        let user_id: i32 = conn
+            .query_one(
+                "INSERT INTO users (username, password_hash) VALUES ('alice', '') RETURNING id",
+                &[],
+            )
+            .await
+            .unwrap()
+            .get(0);
+        let media_id: i32 = conn
+            .query_one(
+                "INSERT INTO media (kind) VALUES ('MOVIE') RETURNING id",
+                &[],
+            )
+            .await
+            .unwrap()
+            .get(0);
+        let media = || Media {
+            id: media_id,
+            kind: MediaKind::Movie,
+        };
+
+        upsert_scrobble_session(&conn, user_id, &media(), ScrobbleState::Playing, 95.0)
+            .await
+            .unwrap();
+        let first = end_session(&mut conn, user_id, media()).await.unwrap();
+        let second = end_session(&mut conn, user_id, media()).await.unwrap();
+        assert!(first.is_some());
+        assert_eq!(second, None);
+
+        // Even with a new session, e.g. started by mistake, right after.
+        upsert_scrobble_session(&conn, user_id, &media(), ScrobbleState::Playing, 95.0)
+            .await
+            .unwrap();
+        let third = end_session(&mut conn, user_id, media()).await.unwrap();
+        assert_eq!(third, None);
+        let sessions: i64 = conn
+            .query_one("SELECT COUNT(*) FROM scrobble_session", &[])
+            .await
+            .unwrap()
+            .get(0);
+        assert_eq!(sessions, 0);
+
+        let plays: i64 = conn
+            .query_one("SELECT COUNT(*) FROM watch_history", &[])
+            .await
+            .unwrap()
+            .get(0);
+        assert_eq!(plays, 1);
+
+        drop_test_db(conn).await;
+    }
+}
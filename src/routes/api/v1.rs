@@ -0,0 +1,75 @@
+//! Versioned JSON API mirroring the HTML routes.
+
+use std::sync::Arc;
+
+use axum::{
+    Json, Router,
+    routing::{get, post},
+};
+use utoipa::{
+    Modify, OpenApi,
+    openapi::security::{Http, HttpAuthScheme, SecurityScheme},
+};
+
+use crate::{AppState, response::ErrorBody};
+
+mod history;
+mod media;
+mod search;
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Grimoire API"),
+    paths(
+        media::get_movie,
+        media::get_show,
+        media::get_season,
+        media::get_episode,
+        media::post_media,
+        history::get_history,
+        history::post_history,
+        search::get_search,
+    ),
+    components(schemas(ErrorBody)),
+    modifiers(&BearerAuth),
+    security(("api_token" = []))
+)]
+struct ApiDoc;
+
+/// Documents the API tokens created with `grimoire token create`.
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme(
+                "api_token",
+                SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+            );
+        }
+    }
+}
+
+pub fn build_router() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/movies/{movie_id}", get(media::get_movie))
+        .route("/shows/{show_id}", get(media::get_show))
+        .route(
+            "/shows/{show_id}/seasons/{season_number}",
+            get(media::get_season),
+        )
+        .route(
+            "/shows/{show_id}/seasons/{season_number}/episodes/{episode_number}",
+            get(media::get_episode),
+        )
+        .route("/media", post(media::post_media))
+        .route(
+            "/history",
+            get(history::get_history).post(history::post_history),
+        )
+        .route("/search", get(search::get_search))
+}
+
+pub(super) async fn get_openapi() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
@@ -0,0 +1,217 @@
+use std::sync::Arc;
+
+use axum::{Extension, Json, extract::State, http::StatusCode};
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    AppState,
+    db::{
+        MediaKind, WatchHistory, WatchHistoryCursor, WatchHistoryEntry, WatchHistoryEntryMedia,
+        WatchHistoryFilter, WatchHistoryPage, get_media_by_id, get_watch_history,
+        insert_watch_history,
+    },
+    response::{AppError, ErrorBody, JsonError},
+    routes::api::{ApiJson, ApiQuery, ApiUser},
+};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct HistoryParams {
+    /// Number of plays to return, at most 200.
+    limit: Option<i64>,
+    /// `next_cursor` of the previous page.
+    cursor: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct HistoryPage {
+    items: Vec<HistoryItem>,
+    /// Cursor for the next page, missing on the last page.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_cursor: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct HistoryItem {
+    id: i32,
+    #[schema(value_type = String, format = DateTime)]
+    watched_at: Timestamp,
+    media: HistoryMedia,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum HistoryMedia {
+    Movie {
+        id: i32,
+        title: String,
+    },
+    Episode {
+        id: i32,
+        title: String,
+        number: i32,
+        season_number: i32,
+        show_id: i32,
+        show_title: String,
+    },
+}
+
+impl From<WatchHistoryEntry> for HistoryItem {
+    fn from(entry: WatchHistoryEntry) -> Self {
+        let media = match entry.media {
+            WatchHistoryEntryMedia::Movie { id, title, .. } => HistoryMedia::Movie { id, title },
+            WatchHistoryEntryMedia::Episode {
+                episode_id,
+                episode_title,
+                episode_number,
+                season_number,
+                show_id,
+                show_title,
+                ..
+            } => HistoryMedia::Episode {
+                id: episode_id,
+                title: episode_title,
+                number: episode_number,
+                season_number,
+                show_id,
+                show_title,
+            },
+        };
+
+        HistoryItem {
+            id: entry.id,
+            watched_at: entry.watched_at,
+            media,
+        }
+    }
+}
+
+/// Lists plays, most recent first.
+#[utoipa::path(
+    get,
+    path = "/api/v1/history",
+    params(HistoryParams),
+    responses(
+        (status = 200, body = HistoryPage),
+        (status = 400, body = ErrorBody),
+    )
+)]
+pub async fn get_history(
+    State(state): State<Arc<AppState>>,
+    Extension(ApiUser(user_id)): Extension<ApiUser>,
+    ApiQuery(params): ApiQuery<HistoryParams>,
+) -> Result<Json<HistoryPage>, JsonError> {
+    let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(AppError::BadRequest.into());
+    }
+
+    let cursor = match params.cursor {
+        Some(cursor) => Some(WatchHistoryPage::After(
+            WatchHistoryCursor::decode(&cursor).ok_or(AppError::BadRequest)?,
+        )),
+        None => None,
+    };
+
+    let conn = state
+        .pool
+        .get()
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    // Fetch one extra play to know whether there's a next page.
+    let mut entries = get_watch_history(
+        &conn,
+        user_id,
+        limit + 1,
+        &WatchHistoryFilter::default(),
+        cursor,
+    )
+    .await
+    .map_err(|err| AppError::Internal(err.into()))?;
+
+    let next_cursor = if entries.len() as i64 > limit {
+        entries.truncate(limit as usize);
+        entries.last().map(|entry| entry.cursor().encode())
+    } else {
+        None
+    };
+
+    Ok(Json(HistoryPage {
+        items: entries.into_iter().map(Into::into).collect(),
+        next_cursor,
+    }))
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum PlayableKind {
+    Movie,
+    Episode,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct AddPlayRequest {
+    #[serde(rename = "type")]
+    kind: PlayableKind,
+    /// Id of the movie or episode.
+    id: i32,
+    /// Defaults to now.
+    #[schema(value_type = Option<String>, format = DateTime)]
+    watched_at: Option<Timestamp>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AddPlayResponse {
+    id: i32,
+}
+
+/// Records a play of a movie or episode.
+#[utoipa::path(
+    post,
+    path = "/api/v1/history",
+    request_body = AddPlayRequest,
+    responses(
+        (status = 201, body = AddPlayResponse),
+        (status = 404, body = ErrorBody),
+    )
+)]
+pub async fn post_history(
+    State(state): State<Arc<AppState>>,
+    Extension(ApiUser(user_id)): Extension<ApiUser>,
+    ApiJson(request): ApiJson<AddPlayRequest>,
+) -> Result<(StatusCode, Json<AddPlayResponse>), JsonError> {
+    let media_kind = match request.kind {
+        PlayableKind::Movie => MediaKind::Movie,
+        PlayableKind::Episode => MediaKind::Episode,
+    };
+
+    let conn = state
+        .pool
+        .get()
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    let media = get_media_by_id(&conn, request.id, Some(media_kind))
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?
+        .ok_or(AppError::NotFound)?;
+
+    let id = insert_watch_history(
+        &conn,
+        user_id,
+        &WatchHistory {
+            media,
+            watched_at: request.watched_at.unwrap_or_else(Timestamp::now),
+        },
+    )
+    .await
+    .map_err(|err| AppError::Internal(err.into()))?;
+
+    Ok((StatusCode::CREATED, Json(AddPlayResponse { id })))
+}
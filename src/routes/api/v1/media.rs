@@ -0,0 +1,375 @@
+use std::sync::Arc;
+
+use axum::{Extension, Json, extract::State};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    AppState,
+    db::{
+        EpisodeDetails, MediaKind, MovieDetails, SeasonDetails, SeasonEpisodeSummary, ShowDetails,
+        ShowSeasonSummary, get_episode_details, get_movie_details, get_season_details,
+        get_show_details,
+    },
+    library::{AddMediaError, get_or_add_from_tmdb},
+    response::{AppError, ErrorBody, JsonError},
+    routes::api::{ApiJson, ApiPath, ApiUser},
+    tmdb::{self, TmdbId},
+};
+
+#[derive(Serialize, ToSchema)]
+pub struct Movie {
+    id: i32,
+    /// Used in web UI URLs, e.g. `/movie/the-matrix-1999`.
+    slug: String,
+    title: String,
+    release_year: Option<i32>,
+    overview: Option<String>,
+    tagline: Option<String>,
+    /// Runtime in minutes.
+    runtime: Option<i32>,
+    play_count: i64,
+}
+
+impl From<MovieDetails> for Movie {
+    fn from(movie: MovieDetails) -> Self {
+        Movie {
+            id: movie.id,
+            slug: movie.slug,
+            title: movie.title,
+            release_year: movie.release_year,
+            overview: movie.overview,
+            tagline: movie.tagline,
+            runtime: movie.runtime,
+            play_count: movie.play_count,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Show {
+    id: i32,
+    /// Used in web UI URLs, e.g. `/show/the-office-2005`.
+    slug: String,
+    title: String,
+    release_year: Option<i32>,
+    overview: Option<String>,
+    tagline: Option<String>,
+    /// Typical episode runtime in minutes.
+    episode_runtime: Option<i32>,
+    episodes_count: i64,
+    episodes_watched: i64,
+    play_count: i64,
+    seasons: Vec<SeasonSummary>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SeasonSummary {
+    title: String,
+    number: i32,
+    episodes_count: i64,
+    episodes_watched: i64,
+    play_count: i64,
+}
+
+impl From<ShowDetails> for Show {
+    fn from(show: ShowDetails) -> Self {
+        Show {
+            id: show.id,
+            slug: show.slug,
+            title: show.title,
+            release_year: show.release_year,
+            overview: show.overview,
+            tagline: show.tagline,
+            episode_runtime: show.episode_runtime,
+            episodes_count: show.episodes_count,
+            episodes_watched: show.episodes_watched,
+            play_count: show.play_count,
+            seasons: show.seasons.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl From<ShowSeasonSummary> for SeasonSummary {
+    fn from(season: ShowSeasonSummary) -> Self {
+        SeasonSummary {
+            title: season.title,
+            number: season.number,
+            episodes_count: season.episodes_count,
+            episodes_watched: season.episodes_watched,
+            play_count: season.play_count,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Season {
+    show_id: i32,
+    show_title: String,
+    title: String,
+    number: i32,
+    overview: Option<String>,
+    episodes_count: i64,
+    episodes_watched: i64,
+    play_count: i64,
+    episodes: Vec<EpisodeSummary>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct EpisodeSummary {
+    id: i32,
+    title: String,
+    number: i32,
+    overview: Option<String>,
+    play_count: i64,
+}
+
+impl From<SeasonDetails> for Season {
+    fn from(season: SeasonDetails) -> Self {
+        Season {
+            show_id: season.show_id,
+            show_title: season.show_title,
+            title: season.title,
+            number: season.number,
+            overview: season.overview,
+            episodes_count: season.episodes_count,
+            episodes_watched: season.episodes_watched,
+            play_count: season.play_count,
+            episodes: season.episodes.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl From<SeasonEpisodeSummary> for EpisodeSummary {
+    fn from(episode: SeasonEpisodeSummary) -> Self {
+        EpisodeSummary {
+            id: episode.id,
+            title: episode.title,
+            number: episode.number,
+            overview: episode.overview,
+            play_count: episode.play_count,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Episode {
+    id: i32,
+    title: String,
+    number: i32,
+    overview: Option<String>,
+    show_id: i32,
+    show_title: String,
+    season_title: String,
+    season_number: i32,
+    play_count: i64,
+}
+
+impl From<EpisodeDetails> for Episode {
+    fn from(episode: EpisodeDetails) -> Self {
+        Episode {
+            id: episode.id,
+            title: episode.title,
+            number: episode.number,
+            overview: episode.overview,
+            show_id: episode.show_id,
+            show_title: episode.show_title,
+            season_title: episode.season_title,
+            season_number: episode.season_number,
+            play_count: episode.play_count,
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/movies/{movie_id}",
+    params(("movie_id" = i32, Path)),
+    responses(
+        (status = 200, body = Movie),
+        (status = 404, body = ErrorBody),
+    )
+)]
+pub async fn get_movie(
+    State(state): State<Arc<AppState>>,
+    Extension(ApiUser(user_id)): Extension<ApiUser>,
+    ApiPath(movie_id): ApiPath<i32>,
+) -> Result<Json<Movie>, JsonError> {
+    let conn = state
+        .pool
+        .get()
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    let movie = get_movie_details(&conn, user_id, movie_id)
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?
+        .ok_or(AppError::NotFound)?;
+
+    Ok(Json(movie.into()))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/shows/{show_id}",
+    params(("show_id" = i32, Path)),
+    responses(
+        (status = 200, body = Show),
+        (status = 404, body = ErrorBody),
+    )
+)]
+pub async fn get_show(
+    State(state): State<Arc<AppState>>,
+    Extension(ApiUser(user_id)): Extension<ApiUser>,
+    ApiPath(show_id): ApiPath<i32>,
+) -> Result<Json<Show>, JsonError> {
+    let conn = state
+        .pool
+        .get()
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    let show = get_show_details(&conn, user_id, show_id)
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?
+        .ok_or(AppError::NotFound)?;
+
+    Ok(Json(show.into()))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/shows/{show_id}/seasons/{season_number}",
+    params(("show_id" = i32, Path), ("season_number" = i32, Path)),
+    responses(
+        (status = 200, body = Season),
+        (status = 404, body = ErrorBody),
+    )
+)]
+pub async fn get_season(
+    State(state): State<Arc<AppState>>,
+    Extension(ApiUser(user_id)): Extension<ApiUser>,
+    ApiPath((show_id, season_number)): ApiPath<(i32, i32)>,
+) -> Result<Json<Season>, JsonError> {
+    let conn = state
+        .pool
+        .get()
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    let season = get_season_details(&conn, user_id, show_id, season_number)
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?
+        .ok_or(AppError::NotFound)?;
+
+    Ok(Json(season.into()))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/shows/{show_id}/seasons/{season_number}/episodes/{episode_number}",
+    params(
+        ("show_id" = i32, Path),
+        ("season_number" = i32, Path),
+        ("episode_number" = i32, Path),
+    ),
+    responses(
+        (status = 200, body = Episode),
+        (status = 404, body = ErrorBody),
+    )
+)]
+pub async fn get_episode(
+    State(state): State<Arc<AppState>>,
+    Extension(ApiUser(user_id)): Extension<ApiUser>,
+    ApiPath((show_id, season_number, episode_number)): ApiPath<(i32, i32, i32)>,
+) -> Result<Json<Episode>, JsonError> {
+    let conn = state
+        .pool
+        .get()
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    let episode = get_episode_details(&conn, user_id, show_id, season_number, episode_number)
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?
+        .ok_or(AppError::NotFound)?;
+
+    Ok(Json(episode.into()))
+}
+
+#[derive(Deserialize, Serialize, ToSchema, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum AddableKind {
+    Movie,
+    Show,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct AddMediaRequest {
+    tmdb_id: i32,
+    #[serde(rename = "type")]
+    kind: AddableKind,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AddMediaResponse {
+    id: i32,
+    #[serde(rename = "type")]
+    kind: AddableKind,
+}
+
+/// Adds a movie or show from TMDB to the library, unless it's already there.
+/// Answers 404 when TMDB has no such movie or show.
+#[utoipa::path(
+    post,
+    path = "/api/v1/media",
+    request_body = AddMediaRequest,
+    responses(
+        (status = 200, body = AddMediaResponse),
+        (status = 400, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    )
+)]
+pub async fn post_media(
+    State(state): State<Arc<AppState>>,
+    ApiJson(request): ApiJson<AddMediaRequest>,
+) -> Result<Json<AddMediaResponse>, JsonError> {
+    let media_kind = match request.kind {
+        AddableKind::Movie => MediaKind::Movie,
+        AddableKind::Show => MediaKind::Show,
+    };
+
+    let mut conn = state
+        .pool
+        .get()
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    // All or nothing, so that a failure can't leave part of a show behind for
+    // the next call to find.
+    let mut tx = conn
+        .transaction()
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    let media = get_or_add_from_tmdb(
+        &mut tx,
+        &state.tmdb_api,
+        &TmdbId(request.tmdb_id),
+        media_kind,
+    )
+    .await
+    .map_err(|err| match err {
+        AddMediaError::Tmdb(tmdb::ApiError::NotFound) => AppError::NotFound,
+        err => AppError::Internal(err.into()),
+    })?;
+
+    tx.commit()
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    Ok(Json(AddMediaResponse {
+        id: media.id,
+        kind: request.kind,
+    }))
+}
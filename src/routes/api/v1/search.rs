@@ -0,0 +1,117 @@
+use std::sync::Arc;
+
+use axum::{Json, extract::State};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    AppState,
+    response::{AppError, ErrorBody, JsonError},
+    routes::api::ApiQuery,
+    tmdb::SearchResultMedia,
+};
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchParams {
+    query: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SearchResults {
+    results: Vec<SearchResult>,
+}
+
+/// A TMDB movie or show, which can be added with `POST /api/v1/media`.
+#[derive(Serialize, ToSchema)]
+pub struct SearchResult {
+    tmdb_id: i32,
+    #[serde(rename = "type")]
+    kind: SearchResultKind,
+    title: String,
+    original_title: String,
+    overview: String,
+    release_year: Option<i16>,
+    poster_path: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchResultKind {
+    Movie,
+    Show,
+}
+
+/// Searches TMDB for movies and shows.
+#[utoipa::path(
+    get,
+    path = "/api/v1/search",
+    params(SearchParams),
+    responses(
+        (status = 200, body = SearchResults),
+        (status = 400, body = ErrorBody),
+    )
+)]
+pub async fn get_search(
+    State(state): State<Arc<AppState>>,
+    ApiQuery(params): ApiQuery<SearchParams>,
+) -> Result<Json<SearchResults>, JsonError> {
+    let search_response = state
+        .tmdb_api
+        .multi_search(&params.query)
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    let results = search_response
+        .results
+        .into_iter()
+        .filter_map(|entry| {
+            let (kind, title, original_title, overview, release_date, poster_path) =
+                match entry.media {
+                    SearchResultMedia::Movie {
+                        title,
+                        original_title,
+                        overview,
+                        release_date,
+                        poster_path,
+                        ..
+                    } => (
+                        SearchResultKind::Movie,
+                        title,
+                        original_title,
+                        overview,
+                        release_date,
+                        poster_path,
+                    ),
+                    SearchResultMedia::Show {
+                        title,
+                        original_title,
+                        overview,
+                        release_date,
+                        poster_path,
+                        ..
+                    } => (
+                        SearchResultKind::Show,
+                        title,
+                        original_title,
+                        overview,
+                        release_date,
+                        poster_path,
+                    ),
+                    SearchResultMedia::Person { .. } => return None,
+                };
+
+            Some(SearchResult {
+                tmdb_id: entry.id.0,
+                kind,
+                title,
+                original_title,
+                overview,
+                release_year: release_date.map(|date| date.year()),
+                poster_path,
+            })
+        })
+        .collect();
+
+    Ok(Json(SearchResults { results }))
+}
@@ -0,0 +1,69 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    Router,
+    extract::{Query, State},
+    http::{StatusCode, header},
+    response::IntoResponse,
+    routing::get,
+};
+use serde::Deserialize;
+
+use crate::{AppState, check_connection, response::AppError};
+
+/// How long the readiness checks wait, shorter than usual probe timeouts.
+const READY_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub fn build_router() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/healthz", get(get_healthz))
+        .route("/readyz", get(get_readyz))
+        .route("/metrics", get(get_metrics))
+}
+
+async fn get_healthz() -> &'static str {
+    "ok"
+}
+
+#[derive(Deserialize)]
+pub struct ReadyParams {
+    /// Also check that TMDB is reachable.
+    #[serde(default)]
+    tmdb: bool,
+}
+
+async fn get_readyz(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<ReadyParams>,
+) -> impl IntoResponse {
+    if let Err(err) = check_connection(&state.pool, READY_TIMEOUT).await {
+        tracing::warn!(err = format!("{err:#}"), "database is not ready");
+        return (StatusCode::SERVICE_UNAVAILABLE, "database unreachable");
+    }
+
+    if params.tmdb {
+        let result = tokio::time::timeout(READY_TIMEOUT, state.tmdb_api.fetch_config()).await;
+        if !matches!(result, Ok(Ok(_))) {
+            tracing::warn!("tmdb is not reachable");
+            return (StatusCode::SERVICE_UNAVAILABLE, "tmdb unreachable");
+        }
+    }
+
+    (StatusCode::OK, "ready")
+}
+
+async fn get_metrics(State(state): State<Arc<AppState>>) -> Result<impl IntoResponse, AppError> {
+    let metrics = state
+        .metrics
+        .encode(&state.pool)
+        .await
+        .map_err(AppError::Internal)?;
+
+    Ok((
+        [(
+            header::CONTENT_TYPE,
+            "application/openmetrics-text; version=1.0.0; charset=utf-8",
+        )],
+        metrics,
+    ))
+}
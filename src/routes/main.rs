@@ -2,41 +2,158 @@ use std::sync::Arc;
 
 use axum::{
     Router,
-    response::IntoResponse,
+    extract::DefaultBodyLimit,
+    middleware,
+    response::{IntoResponse, Redirect},
     routing::{get, post},
 };
+use deadpool_postgres::GenericClient;
 
-use crate::{AppState, response::AppError};
+use crate::{
+    AppState,
+    db::{
+        Credit, CreditKind, MediaKind, WatchHistoryEntryMedia, get_credits, get_media_id_by_slug,
+        get_media_slug,
+    },
+    response::AppError,
+};
 
 mod add_media;
 mod add_watch;
+mod admin_users;
+mod auth;
+mod collection;
+mod export;
+mod history;
+mod import_review;
 mod index;
+mod jobs;
+mod library;
 mod movie;
+mod person;
 mod search;
 mod show;
 mod show_episode;
 mod show_season;
+mod stats;
+
+/// Number of plays listed on the movie and show pages. The full history is
+/// on the history page, linked when there's more.
+const RECENT_PLAYS: i64 = 10;
 
-pub fn build_router() -> Router<Arc<AppState>> {
+pub fn build_router(state: Arc<AppState>) -> Router<Arc<AppState>> {
     Router::new()
         .route("/", get(index::get_index))
-        // TODO: accept slugs for movie and show
-        .route("/movie/{movie_id}", get(movie::get_movie))
-        .route("/show/{show_id}", get(show::get_show))
+        .route("/movies", get(library::get_movies_page))
+        .route("/shows", get(library::get_shows_page))
+        .route("/movie/{movie}", get(movie::get_movie))
+        .route("/show/{show}", get(show::get_show))
         .route(
-            "/show/{show_id}/season/{season_number}",
+            "/show/{show}/season/{season_number}",
             get(show_season::get_show_season),
         )
         .route(
-            "/show/{show_id}/season/{season_number}/episode/{episode_number}",
+            "/show/{show}/season/{season_number}/episode/{episode_number}",
             get(show_episode::get_show_episode),
         )
+        .route(
+            "/collection/{collection_id}",
+            get(collection::get_collection_page),
+        )
+        .route("/person/{person_id}", get(person::get_person_page))
+        .route("/history", get(history::get_history))
+        .route("/stats", get(stats::get_stats))
         .route("/add-watch", post(add_watch::post_add_watch))
         .route("/search", get(search::get_search))
         .route("/add-media", post(add_media::post_add_media))
+        .route("/add-person", post(person::post_add_person))
+        .route("/export", get(export::get_backup_export))
+        .route("/export/letterboxd", get(export::get_letterboxd_export))
+        .route("/export/trakt", get(export::get_trakt_export))
+        .route(
+            "/import",
+            post(import_review::post_import)
+                .layer(DefaultBodyLimit::max(import_review::MAX_IMPORT_SIZE)),
+        )
+        .route("/import/review", get(import_review::get_import_review))
+        .route(
+            "/import/review/{item_id}/resolve",
+            post(import_review::post_resolve_import_review),
+        )
+        .route(
+            "/admin/users",
+            get(admin_users::get_admin_users).post(admin_users::post_admin_users),
+        )
+        .route("/jobs", get(jobs::get_jobs_page))
+        .route("/logout", get(auth::get_logout).post(auth::post_logout))
+        .route_layer(middleware::from_fn_with_state(state, auth::require_session))
+        .route("/login", get(auth::get_login).post(auth::post_login))
         .fallback(fallback_handler)
 }
 
 async fn fallback_handler() -> impl IntoResponse {
     AppError::NotFound
 }
+
+/// URL of the page of a played movie or episode.
+fn media_url(media: &WatchHistoryEntryMedia) -> String {
+    match media {
+        WatchHistoryEntryMedia::Movie { slug, .. } => format!("/movie/{}", slug),
+        WatchHistoryEntryMedia::Episode {
+            show_slug,
+            season_number,
+            episode_number,
+            ..
+        } => format!(
+            "/show/{}/season/{}/episode/{}",
+            show_slug, season_number, episode_number
+        ),
+    }
+}
+
+/// Returns the cast and the crew of a movie, show or episode.
+async fn get_cast_and_crew<C: GenericClient>(
+    conn: &C,
+    media_id: i32,
+) -> Result<(Vec<Credit>, Vec<Credit>), AppError> {
+    Ok(get_credits(conn, media_id)
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?
+        .into_iter()
+        .partition(|credit| credit.kind == CreditKind::Cast))
+}
+
+/// A movie or show given in a URL, see [`resolve_slug`].
+enum Resolved {
+    Id(i32),
+    /// It was given by id, redirect to the URL with its slug.
+    Redirect(Redirect),
+}
+
+/// Resolves the slug of a movie or show given in a URL to its id. Ids are
+/// accepted too, so old links keep working, but redirect to the URL made by
+/// `canonical_url` from the slug.
+async fn resolve_slug<C: GenericClient>(
+    conn: &C,
+    kind: MediaKind,
+    slug_or_id: &str,
+    canonical_url: impl FnOnce(&str) -> String,
+) -> Result<Resolved, AppError> {
+    // Slugs are never all digits, see `crate::slug::media_slug`.
+    if let Ok(id) = slug_or_id.parse::<i32>() {
+        let slug = get_media_slug(conn, kind, id)
+            .await
+            .map_err(|err| AppError::Internal(err.into()))?
+            .ok_or(AppError::NotFound)?;
+
+        return Ok(Resolved::Redirect(Redirect::permanent(&canonical_url(
+            &slug,
+        ))));
+    }
+
+    get_media_id_by_slug(conn, kind, slug_or_id)
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?
+        .map(Resolved::Id)
+        .ok_or(AppError::NotFound)
+}
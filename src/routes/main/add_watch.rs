@@ -1,11 +1,13 @@
 use std::sync::Arc;
 
 use axum::{
+    Extension, Form,
     extract::{Query, State},
     response::Redirect,
 };
 use serde::Deserialize;
 
+use super::auth::{CsrfForm, Session};
 use crate::{
     AppState,
     db::{MediaKind, WatchHistory, get_media_by_id, insert_watch_history},
@@ -20,8 +22,12 @@ pub struct AddWatchParams {
 
 pub async fn post_add_watch(
     State(state): State<Arc<AppState>>,
+    Extension(session): Extension<Session>,
     Query(params): Query<AddWatchParams>,
+    Form(csrf): Form<CsrfForm>,
 ) -> Result<Redirect, AppError> {
+    session.verify_csrf(&csrf.csrf_token)?;
+
     let conn = state
         .pool
         .get()
@@ -37,6 +43,7 @@ pub async fn post_add_watch(
 
     insert_watch_history(
         &conn,
+        session.user_id,
         &WatchHistory {
             media,
             watched_at: jiff::Timestamp::now(),
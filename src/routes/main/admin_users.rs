@@ -0,0 +1,125 @@
+use std::sync::Arc;
+
+use askama::Template;
+use axum::{
+    Extension, Form,
+    extract::State,
+    response::{IntoResponse, Redirect, Response},
+};
+use serde::Deserialize;
+
+use super::auth::Session;
+use crate::{
+    AppState,
+    auth::hash_password,
+    db::{User, get_users, insert_user},
+    filters,
+    response::{AppError, HtmlTemplate},
+};
+
+#[derive(Template)]
+#[template(path = "admin_users.html")]
+pub struct AdminUsersTemplate {
+    title: String,
+    users: Vec<User>,
+    error: Option<String>,
+    csrf_token: String,
+}
+
+pub async fn get_admin_users(
+    State(state): State<Arc<AppState>>,
+    Extension(session): Extension<Session>,
+) -> Result<impl IntoResponse, AppError> {
+    render_admin_users(&state, session, None).await
+}
+
+#[derive(Deserialize)]
+pub struct CreateUserForm {
+    username: String,
+    password: String,
+    /// Checkbox, only sent when checked.
+    is_admin: Option<String>,
+    csrf_token: String,
+}
+
+pub async fn post_admin_users(
+    State(state): State<Arc<AppState>>,
+    Extension(session): Extension<Session>,
+    Form(form): Form<CreateUserForm>,
+) -> Result<Response, AppError> {
+    session.verify_csrf(&form.csrf_token)?;
+    if !session.is_admin {
+        return Err(AppError::Forbidden);
+    }
+
+    let username = form.username.trim().to_string();
+    if username.is_empty() || form.password.is_empty() {
+        return Ok(render_admin_users(
+            &state,
+            session,
+            Some("Username and password can't be empty.".to_string()),
+        )
+        .await?
+        .into_response());
+    }
+
+    // Hashing is slow on purpose, keep it off the async workers.
+    let password_hash = tokio::task::spawn_blocking(move || hash_password(&form.password))
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    let mut conn = state
+        .pool
+        .get()
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    let Some(_) = insert_user(
+        &mut conn,
+        &username,
+        &password_hash,
+        form.is_admin.is_some(),
+    )
+    .await
+    .map_err(|err| AppError::Internal(err.into()))?
+    else {
+        drop(conn);
+        return Ok(render_admin_users(
+            &state,
+            session,
+            Some(format!("The username {username} is taken.")),
+        )
+        .await?
+        .into_response());
+    };
+
+    Ok(Redirect::to("/admin/users").into_response())
+}
+
+async fn render_admin_users(
+    state: &AppState,
+    session: Session,
+    error: Option<String>,
+) -> Result<HtmlTemplate<AdminUsersTemplate>, AppError> {
+    if !session.is_admin {
+        return Err(AppError::Forbidden);
+    }
+
+    let conn = state
+        .pool
+        .get()
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    let users = get_users(&conn)
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    Ok(HtmlTemplate(AdminUsersTemplate {
+        title: "Users".to_string(),
+        users,
+        error,
+        csrf_token: session.csrf_token,
+    }))
+}
@@ -0,0 +1,282 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use askama::Template;
+use axum::{
+    Extension, Form,
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Redirect, Response},
+};
+use jiff::ToSpan;
+use serde::Deserialize;
+
+use crate::{
+    AppState,
+    auth::{constant_time_eq, generate_token, hash_token, verify_password},
+    db::{delete_auth_session, get_auth_session, get_user_by_username, insert_auth_session},
+    response::{AppError, HtmlTemplate},
+};
+
+const SESSION_COOKIE: &str = "grimoire_session";
+/// Holds the CSRF token of the login form, which is sent before there's a
+/// session to tie it to.
+const LOGIN_CSRF_COOKIE: &str = "grimoire_login_csrf";
+const SESSION_DAYS: i64 = 30;
+/// Made up password hash with the same parameters as the ones made by
+/// [`crate::auth::hash_password`], checked when the user doesn't exist.
+const DUMMY_PASSWORD_HASH: &str = "$argon2id$v=19$m=19456,t=2,p=1$c29tZXNhbHRzb21lc2FsdA$6fSsuDbI1uLJmkmuGjuwwaFRwjb3t1Bi8IVSj7QF2Tw";
+
+/// The logged in session, added to requests by [`require_session`].
+#[derive(Clone)]
+pub struct Session {
+    token_hash: String,
+    pub user_id: i32,
+    pub is_admin: bool,
+    pub csrf_token: String,
+}
+
+impl Session {
+    /// Checks the CSRF token sent with a form against the session's.
+    pub fn verify_csrf(&self, csrf_token: &str) -> Result<(), AppError> {
+        if !constant_time_eq(&self.csrf_token, csrf_token) {
+            return Err(AppError::BadRequest);
+        }
+
+        Ok(())
+    }
+}
+
+/// Form of POST forms that have no fields besides the CSRF token, see
+/// [`Session::verify_csrf`].
+#[derive(Deserialize)]
+pub struct CsrfForm {
+    pub csrf_token: String,
+}
+
+/// Redirects to the login page unless the request has a valid session cookie.
+pub async fn require_session(
+    State(state): State<Arc<AppState>>,
+    mut req: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let Some(token) = cookie(req.headers(), SESSION_COOKIE) else {
+        return Ok(Redirect::to("/login").into_response());
+    };
+
+    let conn = state
+        .pool
+        .get()
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    let token_hash = hash_token(token);
+    let Some(session) = get_auth_session(&conn, &token_hash)
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?
+    else {
+        return Ok(Redirect::to("/login").into_response());
+    };
+    drop(conn);
+
+    req.extensions_mut().insert(Session {
+        token_hash,
+        user_id: session.user_id,
+        is_admin: session.is_admin,
+        csrf_token: session.csrf_token,
+    });
+
+    Ok(next.run(req).await)
+}
+
+fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .find_map(|cookie| {
+            let (cookie_name, value) = cookie.trim().split_once('=')?;
+            (cookie_name == name).then_some(value)
+        })
+}
+
+#[derive(Template)]
+#[template(path = "login.html")]
+pub struct LoginTemplate {
+    title: String,
+    error: Option<String>,
+    csrf_token: String,
+}
+
+impl LoginTemplate {
+    fn new(csrf_token: String, error: Option<&str>) -> Self {
+        LoginTemplate {
+            title: "Log in".to_string(),
+            error: error.map(str::to_string),
+            csrf_token,
+        }
+    }
+}
+
+/// Shows the login form, along with a new CSRF token that [`post_login`]
+/// checks against the login CSRF cookie.
+pub async fn get_login(State(state): State<Arc<AppState>>) -> Result<Response, AppError> {
+    let csrf_token = generate_token();
+    let cookie = format!(
+        "{LOGIN_CSRF_COOKIE}={csrf_token}; Path=/login; HttpOnly; SameSite=Strict{}",
+        if state.cookie_secure { "; Secure" } else { "" },
+    );
+
+    Ok((
+        [(
+            header::SET_COOKIE,
+            HeaderValue::from_str(&cookie).map_err(|err| AppError::Internal(err.into()))?,
+        )],
+        HtmlTemplate(LoginTemplate::new(csrf_token, None)),
+    )
+        .into_response())
+}
+
+#[derive(Deserialize)]
+pub struct LoginForm {
+    username: String,
+    password: String,
+    csrf_token: String,
+}
+
+pub async fn post_login(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Form(form): Form<LoginForm>,
+) -> Result<Response, AppError> {
+    if !cookie(&headers, LOGIN_CSRF_COOKIE)
+        .is_some_and(|csrf_token| constant_time_eq(csrf_token, &form.csrf_token))
+    {
+        return Err(AppError::BadRequest);
+    }
+
+    let client_ip = client_addr.ip();
+    if !state.login_limiter.is_allowed(client_ip, &form.username) {
+        tracing::warn!(
+            username = form.username,
+            %client_ip,
+            "login refused after too many failed attempts"
+        );
+        return Ok((
+            StatusCode::TOO_MANY_REQUESTS,
+            HtmlTemplate(LoginTemplate::new(
+                form.csrf_token,
+                Some("Too many failed login attempts, try again later."),
+            )),
+        )
+            .into_response());
+    }
+
+    let conn = state
+        .pool
+        .get()
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    let user = get_user_by_username(&conn, &form.username)
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    // Unknown users are checked against a dummy hash, so they take as long
+    // as wrong passwords and usernames can't be probed.
+    let (user_id, password_hash) = match user {
+        Some(user) => (Some(user.id), user.password_hash),
+        None => (None, DUMMY_PASSWORD_HASH.to_string()),
+    };
+
+    // Hashing is slow on purpose, keep it off the async workers.
+    let password = form.password;
+    let password_matches =
+        tokio::task::spawn_blocking(move || verify_password(&password, &password_hash))
+            .await
+            .map_err(|err| AppError::Internal(err.into()))?;
+
+    let (Some(user_id), true) = (user_id, password_matches) else {
+        tracing::warn!(username = form.username, %client_ip, "failed login attempt");
+        state
+            .login_limiter
+            .record_failure(client_ip, &form.username);
+        return Ok(HtmlTemplate(LoginTemplate::new(
+            form.csrf_token,
+            Some("Wrong username or password."),
+        ))
+        .into_response());
+    };
+    state.login_limiter.record_success(&form.username);
+
+    let token = generate_token();
+    insert_auth_session(
+        &conn,
+        user_id,
+        &hash_token(&token),
+        &generate_token(),
+        &(jiff::Timestamp::now() + (SESSION_DAYS * 24).hours()),
+    )
+    .await
+    .map_err(|err| AppError::Internal(err.into()))?;
+
+    let cookie = format!(
+        "{SESSION_COOKIE}={token}; Path=/; Max-Age={}; HttpOnly; SameSite=Lax{}",
+        SESSION_DAYS * 24 * 60 * 60,
+        if state.cookie_secure { "; Secure" } else { "" },
+    );
+
+    Ok((
+        [(
+            header::SET_COOKIE,
+            HeaderValue::from_str(&cookie).map_err(|err| AppError::Internal(err.into()))?,
+        )],
+        Redirect::to("/"),
+    )
+        .into_response())
+}
+
+#[derive(Template)]
+#[template(path = "logout.html")]
+pub struct LogoutTemplate {
+    title: String,
+    csrf_token: String,
+}
+
+pub async fn get_logout(Extension(session): Extension<Session>) -> impl IntoResponse {
+    HtmlTemplate(LogoutTemplate {
+        title: "Log out".to_string(),
+        csrf_token: session.csrf_token,
+    })
+}
+
+pub async fn post_logout(
+    State(state): State<Arc<AppState>>,
+    Extension(session): Extension<Session>,
+    Form(form): Form<CsrfForm>,
+) -> Result<Response, AppError> {
+    session.verify_csrf(&form.csrf_token)?;
+
+    let conn = state
+        .pool
+        .get()
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    delete_auth_session(&conn, &session.token_hash)
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    Ok((
+        [(
+            header::SET_COOKIE,
+            HeaderValue::from_static(
+                "grimoire_session=; Path=/; Max-Age=0; HttpOnly; SameSite=Lax",
+            ),
+        )],
+        Redirect::to("/login"),
+    )
+        .into_response())
+}
@@ -0,0 +1,68 @@
+use std::sync::Arc;
+
+use askama::Template;
+use axum::{
+    Extension,
+    extract::{Path, State},
+    response::IntoResponse,
+};
+use jiff::{Zoned, civil::Date};
+
+use super::auth::Session;
+use crate::{
+    AppState,
+    db::{CollectionPart, get_collection},
+    filters,
+    response::{AppError, HtmlTemplate},
+};
+
+#[derive(Template)]
+#[template(path = "collection.html")]
+pub struct CollectionTemplate {
+    title: String,
+    tmdb_id: i32,
+    overview: Option<String>,
+    parts: Vec<CollectionPart>,
+    watched_count: usize,
+    next: Option<CollectionPart>,
+    today: Date,
+    csrf_token: String,
+}
+
+pub async fn get_collection_page(
+    State(state): State<Arc<AppState>>,
+    Extension(session): Extension<Session>,
+    Path(collection_id): Path<i32>,
+) -> Result<impl IntoResponse, AppError> {
+    let conn = state
+        .pool
+        .get()
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    let Some(collection) = get_collection(&conn, session.user_id, collection_id)
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?
+    else {
+        return Err(AppError::NotFound);
+    };
+
+    let today = Zoned::now().date();
+    let next = collection.next_unwatched(today, None).cloned();
+    let watched_count = collection
+        .parts
+        .iter()
+        .filter(|part| part.play_count > 0)
+        .count();
+
+    Ok(HtmlTemplate(CollectionTemplate {
+        title: collection.name,
+        tmdb_id: collection.tmdb_id,
+        overview: collection.overview,
+        parts: collection.parts,
+        watched_count,
+        next,
+        today,
+        csrf_token: session.csrf_token,
+    }))
+}
@@ -0,0 +1,166 @@
+use std::{io::Cursor, sync::Arc};
+
+use axum::{Extension, body::Body, extract::State, http::header, response::IntoResponse};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::{Instrument, warn};
+
+use super::auth::Session;
+use crate::{AppState, backup, letterboxd, response::AppError, trakt};
+
+pub async fn get_letterboxd_export(
+    State(state): State<Arc<AppState>>,
+    Extension(session): Extension<Session>,
+) -> Result<impl IntoResponse, AppError> {
+    let conn = state
+        .pool
+        .get()
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    let body = stream_export(move |writer| async move {
+        letterboxd::export::export_csv(&conn, session.user_id, writer).await
+    });
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"grimoire-letterboxd.csv\"",
+            ),
+        ],
+        body,
+    ))
+}
+
+pub async fn get_backup_export(
+    State(state): State<Arc<AppState>>,
+    Extension(session): Extension<Session>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut conn = state
+        .pool
+        .get()
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    let body = stream_export(move |writer| async move {
+        backup::export::export_json(&mut conn, session.user_id, writer).await
+    });
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/json"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"grimoire-backup.json\"",
+            ),
+        ],
+        body,
+    ))
+}
+
+pub async fn get_trakt_export(
+    State(state): State<Arc<AppState>>,
+    Extension(session): Extension<Session>,
+) -> Result<impl IntoResponse, AppError> {
+    let conn = state
+        .pool
+        .get()
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    // The zip crate seeks back to write the headers of each file, so unlike
+    // the other exports the archive is built in memory before being sent.
+    let mut zip = Cursor::new(Vec::new());
+    trakt::export::export_zip(&conn, session.user_id, &mut zip)
+        .await
+        .map_err(AppError::Internal)?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/zip"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"grimoire-trakt.zip\"",
+            ),
+        ],
+        zip.into_inner(),
+    ))
+}
+
+/// Size of the chunks streamed exports are sent in.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Chunks written but not sent yet, past which the export waits for the
+/// client.
+const PENDING_CHUNKS: usize = 4;
+
+type Chunk = Result<Vec<u8>, std::io::Error>;
+
+/// Runs the export in its own task and sends what it writes as the response
+/// body while it's written, so that neither holds the whole export. The
+/// response has started by the time the export could fail, so failures
+/// abort it rather than turn it into an error page.
+fn stream_export<F, Fut>(export: F) -> Body
+where
+    F: FnOnce(ChannelWriter) -> Fut,
+    Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel(PENDING_CHUNKS);
+    let export = export(ChannelWriter {
+        sender: sender.clone(),
+        buffer: Vec::with_capacity(CHUNK_SIZE),
+    });
+
+    tokio::spawn(
+        async move {
+            if let Err(err) = export.await {
+                warn!(err = format!("{err:#}"), "export failed");
+                let _ = sender
+                    .send(Err(std::io::Error::other("export failed")))
+                    .await;
+            }
+        }
+        .in_current_span(),
+    );
+
+    Body::from_stream(ReceiverStream::new(receiver))
+}
+
+/// Sends what's written to the response body of [`stream_export`], for the
+/// exports written by synchronous code such as serde's. Blocks while the
+/// client is behind, which is fine on the server's multi-threaded runtime.
+struct ChannelWriter {
+    sender: mpsc::Sender<Chunk>,
+    buffer: Vec<u8>,
+}
+
+impl ChannelWriter {
+    fn send_buffer(&mut self) -> std::io::Result<()> {
+        let chunk = std::mem::replace(&mut self.buffer, Vec::with_capacity(CHUNK_SIZE));
+
+        tokio::task::block_in_place(|| self.sender.blocking_send(Ok(chunk))).map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::BrokenPipe, "the client went away")
+        })
+    }
+}
+
+impl std::io::Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= CHUNK_SIZE {
+            self.send_buffer()?;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        self.send_buffer()
+    }
+}
@@ -0,0 +1,220 @@
+use std::sync::Arc;
+
+use askama::Template;
+use axum::{
+    Extension,
+    extract::{Query, State},
+    response::IntoResponse,
+};
+use jiff::{civil::Date, tz::TimeZone};
+use serde::{Deserialize, Serialize};
+
+use super::{auth::Session, media_url};
+use crate::{
+    AppState,
+    db::{
+        MediaKind, WatchHistoryCursor, WatchHistoryEntry, WatchHistoryEntryMedia,
+        WatchHistoryFilter, WatchHistoryPage, WatchedShow, get_media_id_by_slug, get_watch_history,
+        get_watched_shows,
+    },
+    filters,
+    response::{AppError, HtmlTemplate},
+};
+
+const PAGE_SIZE: i64 = 50;
+
+/// Query of the history page. Blank form fields are sent as empty strings,
+/// which mean the same as missing ones.
+#[derive(Deserialize, Serialize, Default, Clone)]
+pub struct HistoryParams {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    kind: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    show: String,
+    /// Season number, along with `show`.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    season: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    movie: String,
+    /// First day to include, as `YYYY-MM-DD`.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    from: String,
+    /// Last day to include, as `YYYY-MM-DD`.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    to: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    q: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    after: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    before: String,
+}
+
+#[derive(Template)]
+#[template(path = "history.html")]
+pub struct HistoryTemplate {
+    title: String,
+    params: HistoryParams,
+    shows: Vec<WatchedShow>,
+    entries: Vec<HistoryEntry>,
+    previous_url: Option<String>,
+    next_url: Option<String>,
+}
+
+/// A play in the history lists of the history, show, season and episode
+/// pages.
+pub(super) struct HistoryEntry {
+    pub watched_at: jiff::Timestamp,
+    pub url: String,
+    pub media: WatchHistoryEntryMedia,
+}
+
+pub async fn get_history(
+    State(state): State<Arc<AppState>>,
+    Extension(session): Extension<Session>,
+    Query(params): Query<HistoryParams>,
+) -> Result<impl IntoResponse, AppError> {
+    let page = match (params.after.as_str(), params.before.as_str()) {
+        ("", "") => None,
+        (after, "") => Some(WatchHistoryPage::After(
+            WatchHistoryCursor::decode(after).ok_or(AppError::BadRequest)?,
+        )),
+        ("", before) => Some(WatchHistoryPage::Before(
+            WatchHistoryCursor::decode(before).ok_or(AppError::BadRequest)?,
+        )),
+        _ => return Err(AppError::BadRequest),
+    };
+
+    let conn = state
+        .pool
+        .get()
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    let mut filter = WatchHistoryFilter {
+        kind: match params.kind.as_str() {
+            "" => None,
+            "movie" => Some(MediaKind::Movie),
+            "episode" => Some(MediaKind::Episode),
+            _ => return Err(AppError::BadRequest),
+        },
+        watched_from: parse_day(&params.from, false)?,
+        watched_until: parse_day(&params.to, true)?,
+        season_number: match params.season.as_str() {
+            "" => None,
+            season => Some(season.parse().map_err(|_| AppError::BadRequest)?),
+        },
+        text: Some(params.q.trim().to_string()).filter(|text| !text.is_empty()),
+        ..Default::default()
+    };
+
+    if !params.show.is_empty() {
+        filter.show_id = Some(
+            get_media_id_by_slug(&conn, MediaKind::Show, &params.show)
+                .await
+                .map_err(|err| AppError::Internal(err.into()))?
+                .ok_or(AppError::NotFound)?,
+        );
+    }
+    if !params.movie.is_empty() {
+        filter.movie_id = Some(
+            get_media_id_by_slug(&conn, MediaKind::Movie, &params.movie)
+                .await
+                .map_err(|err| AppError::Internal(err.into()))?
+                .ok_or(AppError::NotFound)?,
+        );
+    }
+
+    // Fetch one extra play to know whether there's more past this page.
+    let mut entries = get_watch_history(&conn, session.user_id, PAGE_SIZE + 1, &filter, page)
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    let has_more = entries.len() as i64 > PAGE_SIZE;
+    let (has_previous, has_next) = match page {
+        None => (false, has_more),
+        Some(WatchHistoryPage::After(_)) => (true, has_more),
+        Some(WatchHistoryPage::Before(_)) => (has_more, true),
+    };
+    if has_more {
+        // The extra play is the furthest from the cursor.
+        match page {
+            Some(WatchHistoryPage::Before(_)) => {
+                entries.remove(0);
+            }
+            _ => entries.truncate(PAGE_SIZE as usize),
+        }
+    }
+
+    let previous_url = entries
+        .first()
+        .filter(|_| has_previous)
+        .map(|entry| page_url(&params, None, Some(entry)));
+    let next_url = entries
+        .last()
+        .filter(|_| has_next)
+        .map(|entry| page_url(&params, Some(entry), None));
+
+    let shows = get_watched_shows(&conn, session.user_id)
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    Ok(HtmlTemplate(HistoryTemplate {
+        title: "History".to_string(),
+        params,
+        shows,
+        entries: entries.into_iter().map(HistoryEntry::from).collect(),
+        previous_url,
+        next_url,
+    }))
+}
+
+impl From<WatchHistoryEntry> for HistoryEntry {
+    fn from(entry: WatchHistoryEntry) -> Self {
+        HistoryEntry {
+            watched_at: entry.watched_at,
+            url: media_url(&entry.media),
+            media: entry.media,
+        }
+    }
+}
+
+/// Parses a day from a date input as the time it starts, or ends when `end`
+/// is set, in the server's time zone.
+fn parse_day(day: &str, end: bool) -> Result<Option<jiff::Timestamp>, AppError> {
+    if day.is_empty() {
+        return Ok(None);
+    }
+
+    let mut date: Date = day.parse().map_err(|_| AppError::BadRequest)?;
+    if end {
+        date = date.tomorrow().map_err(|_| AppError::BadRequest)?;
+    }
+
+    date.to_zoned(TimeZone::system())
+        .map(|zoned| Some(zoned.timestamp()))
+        .map_err(|_| AppError::BadRequest)
+}
+
+/// URL of the page after or before an entry, with the same filters.
+fn page_url(
+    params: &HistoryParams,
+    after: Option<&WatchHistoryEntry>,
+    before: Option<&WatchHistoryEntry>,
+) -> String {
+    let params = HistoryParams {
+        after: after
+            .map(|entry| entry.cursor().encode())
+            .unwrap_or_default(),
+        before: before
+            .map(|entry| entry.cursor().encode())
+            .unwrap_or_default(),
+        ..params.clone()
+    };
+
+    // Serializing a struct of strings can't fail.
+    format!(
+        "/history?{}",
+        serde_urlencoded::to_string(&params).unwrap_or_default()
+    )
+}
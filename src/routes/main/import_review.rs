@@ -0,0 +1,157 @@
+use std::sync::Arc;
+
+use askama::Template;
+use axum::{
+    Extension, Form,
+    extract::{Multipart, Path, State},
+    response::{IntoResponse, Redirect},
+};
+use serde::Deserialize;
+
+use super::auth::Session;
+use crate::{
+    AppState, DataFormat,
+    db::{ImportReviewItem, JobStatus, JobSummary, get_import_review_items, get_jobs},
+    filters,
+    jobs::{self, Job},
+    library::resolve_import_review_item,
+    response::{AppError, HtmlTemplate},
+    tmdb::TmdbId,
+};
+
+/// Number of the user's imports listed on the import page.
+const RECENT_IMPORTS: i64 = 10;
+
+/// Largest file that can be imported from the web UI.
+pub const MAX_IMPORT_SIZE: usize = 64 * 1024 * 1024;
+
+#[derive(Template)]
+#[template(path = "import_review.html")]
+pub struct ImportReviewTemplate {
+    /// The user's recent imports.
+    imports: Vec<JobSummary>,
+    items: Vec<ImportReviewItem>,
+    csrf_token: String,
+}
+
+pub async fn get_import_review(
+    State(state): State<Arc<AppState>>,
+    Extension(session): Extension<Session>,
+) -> Result<impl IntoResponse, AppError> {
+    let conn = state
+        .pool
+        .get()
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    let imports = get_jobs(&conn, Some(session.user_id), RECENT_IMPORTS)
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+    let items = get_import_review_items(&conn, session.user_id)
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    Ok(HtmlTemplate(ImportReviewTemplate {
+        imports,
+        items,
+        csrf_token: session.csrf_token,
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct ResolveImportReviewForm {
+    tmdb_id: TmdbId,
+    csrf_token: String,
+}
+
+pub async fn post_resolve_import_review(
+    State(state): State<Arc<AppState>>,
+    Extension(session): Extension<Session>,
+    Path(item_id): Path<i32>,
+    Form(form): Form<ResolveImportReviewForm>,
+) -> Result<Redirect, AppError> {
+    session.verify_csrf(&form.csrf_token)?;
+
+    let mut conn = state
+        .pool
+        .get()
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    let Some(_) = resolve_import_review_item(
+        &mut conn,
+        &state.tmdb_api,
+        session.user_id,
+        item_id,
+        &form.tmdb_id,
+    )
+    .await
+    .map_err(|err| AppError::Internal(err.into()))?
+    else {
+        return Err(AppError::NotFound);
+    };
+
+    Ok(Redirect::to("/import/review"))
+}
+
+/// Enqueues a job importing the uploaded file, with `csrf_token`, `format`
+/// and `file` fields.
+pub async fn post_import(
+    State(state): State<Arc<AppState>>,
+    Extension(session): Extension<Session>,
+    mut multipart: Multipart,
+) -> Result<Redirect, AppError> {
+    let mut csrf_token = None;
+    let mut format = None;
+    let mut file = None;
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|_| AppError::BadRequest)?
+    {
+        match field.name() {
+            Some("csrf_token") => {
+                csrf_token = Some(field.text().await.map_err(|_| AppError::BadRequest)?)
+            }
+            Some("format") => {
+                format = Some(
+                    match field
+                        .text()
+                        .await
+                        .map_err(|_| AppError::BadRequest)?
+                        .as_str()
+                    {
+                        "native" => DataFormat::Native,
+                        "trakt" => DataFormat::Trakt,
+                        "letterboxd" => DataFormat::Letterboxd,
+                        _ => return Err(AppError::BadRequest),
+                    },
+                )
+            }
+            Some("file") => file = Some(field.bytes().await.map_err(|_| AppError::BadRequest)?),
+            _ => {}
+        }
+    }
+
+    session.verify_csrf(&csrf_token.unwrap_or_default())?;
+    let (Some(format), Some(file)) = (format, file) else {
+        return Err(AppError::BadRequest);
+    };
+
+    let conn = state
+        .pool
+        .get()
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    jobs::enqueue(
+        &conn,
+        &Job::Import { format },
+        Some(session.user_id),
+        Some(&file),
+    )
+    .await
+    .map_err(|err| AppError::Internal(err.into()))?;
+
+    Ok(Redirect::to("/import/review"))
+}
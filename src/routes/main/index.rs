@@ -1,56 +1,92 @@
 use std::sync::Arc;
 
 use askama::Template;
-use axum::{extract::State, response::IntoResponse};
+use axum::{Extension, extract::State, response::IntoResponse};
+use jiff::ToSpan;
 
+use super::{auth::Session, media_url};
 use crate::{
     AppState,
-    db::{WatchHistoryEntryMedia, get_watch_history},
+    db::{
+        ScrobbleState, WatchHistoryEntryMedia, WatchHistoryFilter, get_scrobble_sessions,
+        get_watch_history,
+    },
     filters,
     response::{AppError, HtmlTemplate},
 };
 
+/// Scrobble sessions not updated for this long are considered abandoned.
+const NOW_WATCHING_HOURS: i64 = 12;
+
 struct RecentlyWatchedEntry {
     watched_at: jiff::Timestamp,
     url: String,
     media: WatchHistoryEntryMedia,
 }
 
+struct NowWatchingEntry {
+    updated_at: jiff::Timestamp,
+    paused: bool,
+    progress: f32,
+    url: String,
+    media: WatchHistoryEntryMedia,
+}
+
 #[derive(Template)]
 #[template(path = "index.html")]
 pub struct IndexTemplate {
+    now_watching: Vec<NowWatchingEntry>,
     recently_watched: Vec<RecentlyWatchedEntry>,
+    is_admin: bool,
 }
 
-pub async fn get_index(State(state): State<Arc<AppState>>) -> Result<impl IntoResponse, AppError> {
+pub async fn get_index(
+    State(state): State<Arc<AppState>>,
+    Extension(session): Extension<Session>,
+) -> Result<impl IntoResponse, AppError> {
     let conn = state
         .pool
         .get()
         .await
         .map_err(|err| AppError::Internal(err.into()))?;
 
-    let recently_watched = get_watch_history(&conn, 10, None)
-        .await
-        .inspect_err(|err| eprintln!("{:?}", err))
-        .map_err(|err| AppError::Internal(err.into()))?
-        .iter()
-        .map(|entry| RecentlyWatchedEntry {
-            watched_at: entry.watched_at,
-            media: entry.media.to_owned(),
-            url: match entry.media {
-                WatchHistoryEntryMedia::Movie { id, .. } => format!("/movie/{}", id),
-                WatchHistoryEntryMedia::Episode {
-                    show_id,
-                    season_number,
-                    episode_number,
-                    ..
-                } => format!(
-                    "/show/{}/season/{}/episode/{}",
-                    show_id, season_number, episode_number
-                ),
-            },
-        })
-        .collect();
-
-    Ok(HtmlTemplate(IndexTemplate { recently_watched }))
+    let now_watching = get_scrobble_sessions(
+        &conn,
+        session.user_id,
+        &(jiff::Timestamp::now() - NOW_WATCHING_HOURS.hours()),
+    )
+    .await
+    .map_err(|err| AppError::Internal(err.into()))?
+    .into_iter()
+    .map(|session| NowWatchingEntry {
+        updated_at: session.updated_at,
+        paused: session.state == ScrobbleState::Paused,
+        progress: session.progress,
+        url: media_url(&session.media),
+        media: session.media,
+    })
+    .collect();
+
+    let recently_watched = get_watch_history(
+        &conn,
+        session.user_id,
+        10,
+        &WatchHistoryFilter::default(),
+        None,
+    )
+    .await
+    .map_err(|err| AppError::Internal(err.into()))?
+    .iter()
+    .map(|entry| RecentlyWatchedEntry {
+        watched_at: entry.watched_at,
+        media: entry.media.to_owned(),
+        url: media_url(&entry.media),
+    })
+    .collect();
+
+    Ok(HtmlTemplate(IndexTemplate {
+        now_watching,
+        recently_watched,
+        is_admin: session.is_admin,
+    }))
 }
@@ -0,0 +1,42 @@
+use std::sync::Arc;
+
+use askama::Template;
+use axum::{Extension, extract::State, response::IntoResponse};
+
+use super::auth::Session;
+use crate::{
+    AppState,
+    db::{JobStatus, JobSummary, get_jobs},
+    filters,
+    response::{AppError, HtmlTemplate},
+};
+
+/// Number of jobs listed, most recent first.
+const JOBS_LIMIT: i64 = 100;
+
+#[derive(Template)]
+#[template(path = "jobs.html")]
+pub struct JobsTemplate {
+    jobs: Vec<JobSummary>,
+}
+
+pub async fn get_jobs_page(
+    State(state): State<Arc<AppState>>,
+    Extension(session): Extension<Session>,
+) -> Result<impl IntoResponse, AppError> {
+    if !session.is_admin {
+        return Err(AppError::Forbidden);
+    }
+
+    let conn = state
+        .pool
+        .get()
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    let jobs = get_jobs(&conn, None, JOBS_LIMIT)
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    Ok(HtmlTemplate(JobsTemplate { jobs }))
+}
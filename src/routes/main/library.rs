@@ -0,0 +1,209 @@
+use std::sync::Arc;
+
+use askama::Template;
+use axum::{
+    Extension,
+    extract::{Query, State},
+    response::IntoResponse,
+};
+use serde::{Deserialize, Serialize};
+
+use super::auth::Session;
+use crate::{
+    AppState,
+    db::{
+        LibraryFacets, LibraryQuery, LibrarySort, MediaKind, MovieSummary, ShowSummary,
+        WatchStatus, get_library_facets, get_movies, get_shows,
+    },
+    filters,
+    response::{AppError, HtmlTemplate},
+};
+
+const PAGE_SIZE: i64 = 50;
+
+/// Query of the library pages. Blank form fields are sent as empty strings,
+/// which mean the same as missing ones.
+#[derive(Deserialize, Serialize, Default, Clone)]
+pub struct LibraryParams {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    sort: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    status: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    genre: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    keyword: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    language: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    country: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    release_status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    page: Option<i64>,
+}
+
+impl LibraryParams {
+    fn to_query(&self) -> Result<LibraryQuery, AppError> {
+        let sort = match self.sort.as_str() {
+            "" | "title" => LibrarySort::Title,
+            "year" => LibrarySort::ReleaseYear,
+            "last_watched" => LibrarySort::LastWatched,
+            "plays" => LibrarySort::PlayCount,
+            "added" => LibrarySort::Added,
+            _ => return Err(AppError::BadRequest),
+        };
+        let status = match self.status.as_str() {
+            "" => None,
+            "watched" => Some(WatchStatus::Watched),
+            "unwatched" => Some(WatchStatus::Unwatched),
+            "in_progress" => Some(WatchStatus::InProgress),
+            _ => return Err(AppError::BadRequest),
+        };
+        let non_empty = |value: &str| {
+            let value = value.trim();
+            (!value.is_empty()).then(|| value.to_string())
+        };
+        let page = self.page.unwrap_or(1);
+        if page < 1 {
+            return Err(AppError::BadRequest);
+        }
+        let offset = (page - 1)
+            .checked_mul(PAGE_SIZE)
+            .ok_or(AppError::BadRequest)?;
+
+        // Fetch one extra item to know whether there's a next page.
+        Ok(LibraryQuery {
+            sort,
+            status,
+            genre: non_empty(&self.genre),
+            keyword: non_empty(&self.keyword),
+            original_language: non_empty(&self.language),
+            country: non_empty(&self.country),
+            release_status: non_empty(&self.release_status),
+            limit: PAGE_SIZE + 1,
+            offset,
+        })
+    }
+
+    /// URLs of the previous and next pages, with the same sort and filter.
+    fn page_urls(
+        &self,
+        path: &str,
+        has_next: bool,
+    ) -> Result<(Option<String>, Option<String>), AppError> {
+        let page = self.page.unwrap_or(1);
+        let page_url = |page: i64| {
+            let params = LibraryParams {
+                page: Some(page),
+                ..self.clone()
+            };
+
+            // Serializing a struct of strings and numbers can't fail.
+            format!(
+                "{path}?{}",
+                serde_urlencoded::to_string(&params).unwrap_or_default()
+            )
+        };
+
+        let next_url = if has_next {
+            Some(page_url(page.checked_add(1).ok_or(AppError::BadRequest)?))
+        } else {
+            None
+        };
+
+        Ok(((page > 1).then(|| page_url(page - 1)), next_url))
+    }
+}
+
+#[derive(Template)]
+#[template(path = "movies.html")]
+pub struct MoviesTemplate {
+    title: String,
+    params: LibraryParams,
+    facets: LibraryFacets,
+    movies: Vec<MovieSummary>,
+    previous_url: Option<String>,
+    next_url: Option<String>,
+}
+
+pub async fn get_movies_page(
+    State(state): State<Arc<AppState>>,
+    Extension(session): Extension<Session>,
+    Query(params): Query<LibraryParams>,
+) -> Result<impl IntoResponse, AppError> {
+    let query = params.to_query()?;
+
+    let conn = state
+        .pool
+        .get()
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    let mut movies = get_movies(&conn, session.user_id, &query)
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    let facets = get_library_facets(&conn, MediaKind::Movie)
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    let has_next = movies.len() as i64 > PAGE_SIZE;
+    movies.truncate(PAGE_SIZE as usize);
+    let (previous_url, next_url) = params.page_urls("/movies", has_next)?;
+
+    Ok(HtmlTemplate(MoviesTemplate {
+        title: "Movies".to_string(),
+        params,
+        facets,
+        movies,
+        previous_url,
+        next_url,
+    }))
+}
+
+#[derive(Template)]
+#[template(path = "shows.html")]
+pub struct ShowsTemplate {
+    title: String,
+    params: LibraryParams,
+    facets: LibraryFacets,
+    shows: Vec<ShowSummary>,
+    previous_url: Option<String>,
+    next_url: Option<String>,
+}
+
+pub async fn get_shows_page(
+    State(state): State<Arc<AppState>>,
+    Extension(session): Extension<Session>,
+    Query(params): Query<LibraryParams>,
+) -> Result<impl IntoResponse, AppError> {
+    let query = params.to_query()?;
+
+    let conn = state
+        .pool
+        .get()
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    let mut shows = get_shows(&conn, session.user_id, &query)
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    let facets = get_library_facets(&conn, MediaKind::Show)
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    let has_next = shows.len() as i64 > PAGE_SIZE;
+    shows.truncate(PAGE_SIZE as usize);
+    let (previous_url, next_url) = params.page_urls("/shows", has_next)?;
+
+    Ok(HtmlTemplate(ShowsTemplate {
+        title: "Shows".to_string(),
+        params,
+        facets,
+        shows,
+        previous_url,
+        next_url,
+    }))
+}
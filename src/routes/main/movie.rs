@@ -3,13 +3,20 @@ use std::sync::Arc;
 use crate::filters;
 use askama::Template;
 use axum::{
+    Extension,
     extract::{Path, State},
-    response::IntoResponse,
+    response::{IntoResponse, Response},
 };
+use jiff::{Zoned, civil::Date};
 
+use super::{RECENT_PLAYS, Resolved, auth::Session, get_cast_and_crew, resolve_slug};
 use crate::{
     AppState,
-    db::{WatchHistoryEntry, WatchHistoryEntryMedia, get_watch_history},
+    db::{
+        CollectionPart, Credit, MediaKind, MetadataDetails, WatchHistoryEntry,
+        WatchHistoryEntryMedia, WatchHistoryFilter, get_collection, get_media_metadata,
+        get_movie_collection_id, get_movie_details, get_watch_history,
+    },
     response::{AppError, HtmlTemplate},
 };
 
@@ -17,59 +24,113 @@ use crate::{
 #[template(path = "movie.html")]
 pub struct MovieTemplate {
     id: i32,
+    slug: String,
     title: String,
-    release_year: i32,
+    release_year: Option<i32>,
     play_count: i64,
     overview: Option<String>,
     tagline: Option<String>,
     runtime: Option<i32>,
+    metadata: MetadataDetails,
+    /// Where the genre, language and country links go.
+    library_url: &'static str,
     history: Vec<WatchHistoryEntry>,
+    cast: Vec<Credit>,
+    crew: Vec<Credit>,
+    collection: Option<MovieCollection>,
+    today: Date,
+    csrf_token: String,
+}
+
+/// The collection a movie is part of.
+struct MovieCollection {
+    id: i32,
+    name: String,
+    /// The first movie of the collection, besides this one, that the user
+    /// hasn't watched.
+    next: Option<CollectionPart>,
 }
 
 pub async fn get_movie(
     State(state): State<Arc<AppState>>,
-    Path(movie_id): Path<i32>,
-) -> Result<impl IntoResponse, AppError> {
+    Extension(session): Extension<Session>,
+    Path(movie): Path<String>,
+) -> Result<Response, AppError> {
     let conn = state
         .pool
         .get()
         .await
         .map_err(|err| AppError::Internal(err.into()))?;
 
-    let Some(row) = conn
-        .query_opt(
-            "
-            SELECT mo.id, mo.title, mo.release_year, COUNT(wh.watched_at) AS play_count,
-            mo.overview, mo.tagline, mo.runtime FROM movie mo
-            LEFT JOIN watch_history wh ON mo.id = wh.media_id AND wh.media_kind = 'MOVIE'
-            WHERE mo.id = $1
-            GROUP BY mo.id
-            ",
-            &[&movie_id],
-        )
+    let movie_id = match resolve_slug(&conn, MediaKind::Movie, &movie, |slug| {
+        format!("/movie/{slug}")
+    })
+    .await?
+    {
+        Resolved::Id(id) => id,
+        Resolved::Redirect(redirect) => return Ok(redirect.into_response()),
+    };
+
+    let Some(movie) = get_movie_details(&conn, session.user_id, movie_id)
         .await
         .map_err(|err| AppError::Internal(err.into()))?
     else {
         return Err(AppError::NotFound);
     };
 
-    // TODO: implement pagination...
     let movie_history = get_watch_history(
         &conn,
-        999,
-        Some(crate::db::GetWatchHistoryFilter::Movie(movie_id)),
+        session.user_id,
+        RECENT_PLAYS,
+        &WatchHistoryFilter {
+            movie_id: Some(movie_id),
+            ..Default::default()
+        },
+        None,
     )
     .await
     .map_err(|err| AppError::Internal(err.into()))?;
 
+    let metadata = get_media_metadata(&conn, MediaKind::Movie, movie_id)
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?
+        .ok_or(AppError::NotFound)?;
+
+    let (cast, crew) = get_cast_and_crew(&conn, movie_id).await?;
+
+    let today = Zoned::now().date();
+    let collection = match get_movie_collection_id(&conn, movie_id)
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?
+    {
+        Some(collection_id) => get_collection(&conn, session.user_id, collection_id)
+            .await
+            .map_err(|err| AppError::Internal(err.into()))?
+            .map(|collection| MovieCollection {
+                id: collection.id,
+                next: collection.next_unwatched(today, Some(movie_id)).cloned(),
+                name: collection.name,
+            }),
+        None => None,
+    };
+
     Ok(HtmlTemplate(MovieTemplate {
-        id: row.get(0),
-        title: row.get(1),
-        release_year: row.get(2),
-        play_count: row.get(3),
-        overview: row.get(4),
-        tagline: row.get(5),
-        runtime: row.get(6),
+        id: movie.id,
+        slug: movie.slug,
+        title: movie.title,
+        release_year: movie.release_year,
+        play_count: movie.play_count,
+        overview: movie.overview,
+        tagline: movie.tagline,
+        runtime: movie.runtime,
+        metadata,
+        library_url: "/movies",
         history: movie_history,
-    }))
+        cast,
+        crew,
+        collection,
+        today,
+        csrf_token: session.csrf_token,
+    })
+    .into_response())
 }
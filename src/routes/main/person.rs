@@ -0,0 +1,87 @@
+use std::sync::Arc;
+
+use askama::Template;
+use axum::{
+    Extension, Form,
+    extract::{Path, Query, State},
+    response::{IntoResponse, Redirect},
+};
+use serde::Deserialize;
+
+use super::auth::{CsrfForm, Session};
+use crate::{
+    AppState,
+    db::{CreditKind, PersonMovie, PersonShow, get_person, get_person_movies, get_person_shows},
+    filters,
+    library::get_or_add_person_from_tmdb,
+    response::{AppError, HtmlTemplate},
+    tmdb::TmdbId,
+};
+
+#[derive(Template)]
+#[template(path = "person.html")]
+pub struct PersonTemplate {
+    title: String,
+    tmdb_id: i32,
+    movies: Vec<PersonMovie>,
+    shows: Vec<PersonShow>,
+}
+
+pub async fn get_person_page(
+    State(state): State<Arc<AppState>>,
+    Extension(session): Extension<Session>,
+    Path(person_id): Path<i32>,
+) -> Result<impl IntoResponse, AppError> {
+    let conn = state
+        .pool
+        .get()
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    let Some(person) = get_person(&conn, person_id)
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?
+    else {
+        return Err(AppError::NotFound);
+    };
+
+    let movies = get_person_movies(&conn, session.user_id, person.id)
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+    let shows = get_person_shows(&conn, session.user_id, person.id)
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    Ok(HtmlTemplate(PersonTemplate {
+        title: person.name,
+        tmdb_id: person.tmdb_id,
+        movies,
+        shows,
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct AddPersonParams {
+    tmdb_id: TmdbId,
+}
+
+pub async fn post_add_person(
+    State(state): State<Arc<AppState>>,
+    Extension(session): Extension<Session>,
+    Query(params): Query<AddPersonParams>,
+    Form(csrf): Form<CsrfForm>,
+) -> Result<Redirect, AppError> {
+    session.verify_csrf(&csrf.csrf_token)?;
+
+    let conn = state
+        .pool
+        .get()
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    let person_id = get_or_add_person_from_tmdb(&conn, &state.tmdb_api, &params.tmdb_id)
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    Ok(Redirect::to(&format!("/person/{person_id}")))
+}
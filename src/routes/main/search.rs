@@ -2,11 +2,13 @@ use std::sync::Arc;
 
 use askama::Template;
 use axum::{
+    Extension,
     extract::{Query, State},
     response::IntoResponse,
 };
 use serde::Deserialize;
 
+use super::auth::Session;
 use crate::{
     AppState,
     response::{AppError, HtmlTemplate},
@@ -23,21 +25,23 @@ pub struct SearchParams {
 pub struct SearchResultTemplate {
     title: String,
     results: Vec<SearchResultEntry>,
+    csrf_token: String,
 }
 
 pub async fn get_search(
     State(state): State<Arc<AppState>>,
+    Extension(session): Extension<Session>,
     Query(params): Query<SearchParams>,
 ) -> Result<impl IntoResponse, AppError> {
     let search_response = state
         .tmdb_api
         .multi_search(&params.query)
         .await
-        .inspect_err(|err| eprintln!("{:?}", err))
         .map_err(|err| AppError::Internal(err.into()))?;
 
     Ok(HtmlTemplate(SearchResultTemplate {
         title: params.query,
         results: search_response.results,
+        csrf_token: session.csrf_token,
     }))
 }
@@ -2,13 +2,20 @@ use std::sync::Arc;
 
 use askama::Template;
 use axum::{
+    Extension,
     extract::{Path, State},
-    response::IntoResponse,
+    response::{IntoResponse, Response},
 };
 use serde::Deserialize;
 
+use super::{RECENT_PLAYS, Resolved, auth::Session, get_cast_and_crew, resolve_slug};
 use crate::{
     AppState,
+    db::{
+        Credit, MediaKind, WatchHistoryEntry, WatchHistoryFilter, get_episode_details,
+        get_watch_history,
+    },
+    filters,
     response::{AppError, HtmlTemplate},
 };
 
@@ -18,67 +25,90 @@ pub struct ShowEpisodeTemplate {
     episode_id: i32,
     title: String,
     episode_number: i32,
-    show_id: i32,
+    show_slug: String,
     show_title: String,
     season_title: String,
     season_number: i32,
     overview: Option<String>,
     play_count: i64,
+    history: Vec<WatchHistoryEntry>,
+    cast: Vec<Credit>,
+    crew: Vec<Credit>,
+    csrf_token: String,
 }
 
 #[derive(Deserialize)]
 pub struct GetShowEpisodeParams {
-    show_id: i32,
+    show: String,
     season_number: i32,
     episode_number: i32,
 }
 
 pub async fn get_show_episode(
     State(state): State<Arc<AppState>>,
+    Extension(session): Extension<Session>,
     Path(params): Path<GetShowEpisodeParams>,
-) -> Result<impl IntoResponse, AppError> {
+) -> Result<Response, AppError> {
     let conn = state
         .pool
         .get()
         .await
         .map_err(|err| AppError::Internal(err.into()))?;
 
-    let Some(row) = conn
-        .query_opt(
-            "
-            SELECT sh.id AS show_id, sh.title AS show_title, se.title AS season_title,
-            se.number AS season_number, ep.id AS episode_id, 
-            ep.title AS episode_title, ep.number AS episode_number, 
-            ep.overview AS episode_overview, COUNT(wh.watched_at) AS play_count FROM episode ep
-            INNER JOIN season se ON se.id = ep.season_id
-            INNER JOIN show sh ON sh.id = ep.show_id
-            LEFT JOIN watch_history wh ON wh.media_id = ep.id AND wh.media_kind = 'EPISODE'
-            WHERE sh.id = $1 AND se.number = $2 AND ep.number = $3
-            GROUP BY se.id, sh.id, ep.id
-            ",
-            &[
-                &params.show_id,
-                &params.season_number,
-                &params.episode_number,
-            ],
+    let show_id = match resolve_slug(&conn, MediaKind::Show, &params.show, |slug| {
+        format!(
+            "/show/{slug}/season/{}/episode/{}",
+            params.season_number, params.episode_number
         )
-        .await
-        .map_err(|err| AppError::Internal(err.into()))?
+    })
+    .await?
+    {
+        Resolved::Id(id) => id,
+        Resolved::Redirect(redirect) => return Ok(redirect.into_response()),
+    };
+
+    let Some(episode) = get_episode_details(
+        &conn,
+        session.user_id,
+        show_id,
+        params.season_number,
+        params.episode_number,
+    )
+    .await
+    .map_err(|err| AppError::Internal(err.into()))?
     else {
         return Err(AppError::NotFound);
     };
 
-    let template = ShowEpisodeTemplate {
-        episode_id: row.get(4),
-        title: row.get(5),
-        episode_number: row.get(6),
-        show_id: row.get(0),
-        show_title: row.get(1),
-        season_title: row.get(2),
-        season_number: row.get(3),
-        overview: row.get(7),
-        play_count: row.get(8),
-    };
+    let history = get_watch_history(
+        &conn,
+        session.user_id,
+        RECENT_PLAYS,
+        &WatchHistoryFilter {
+            episode_id: Some(episode.id),
+            ..Default::default()
+        },
+        None,
+    )
+    .await
+    .map_err(|err| AppError::Internal(err.into()))?;
+
+    let (cast, crew) = get_cast_and_crew(&conn, episode.id).await?;
 
-    Ok(HtmlTemplate(template))
+    Ok(HtmlTemplate(ShowEpisodeTemplate {
+        episode_id: episode.id,
+        title: episode.title,
+        episode_number: episode.number,
+        show_slug: episode.show_slug,
+        show_title: episode.show_title,
+        season_title: episode.season_title,
+        season_number: episode.season_number,
+        overview: episode.overview,
+        play_count: episode.play_count,
+        history,
+        cast,
+        crew,
+        csrf_token: session.csrf_token,
+    })
+    .into_response())
 }
@@ -0,0 +1,59 @@
+use std::sync::Arc;
+
+use askama::Template;
+use axum::{
+    Extension,
+    extract::{Query, State},
+    response::IntoResponse,
+};
+use serde::Deserialize;
+
+use super::auth::Session;
+use crate::{
+    AppState,
+    db::{StatsDimension, WatchStats, get_watch_stats},
+    response::{AppError, HtmlTemplate},
+};
+
+#[derive(Deserialize)]
+pub struct StatsParams {
+    #[serde(default)]
+    by: String,
+}
+
+#[derive(Template)]
+#[template(path = "stats.html")]
+pub struct StatsTemplate {
+    title: String,
+    by: &'static str,
+    stats: Vec<WatchStats>,
+}
+
+pub async fn get_stats(
+    State(state): State<Arc<AppState>>,
+    Extension(session): Extension<Session>,
+    Query(params): Query<StatsParams>,
+) -> Result<impl IntoResponse, AppError> {
+    let (by, dimension) = match params.by.as_str() {
+        "" | "genre" => ("genre", StatsDimension::Genre),
+        "language" => ("language", StatsDimension::Language),
+        "country" => ("country", StatsDimension::Country),
+        _ => return Err(AppError::BadRequest),
+    };
+
+    let conn = state
+        .pool
+        .get()
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    let stats = get_watch_stats(&conn, session.user_id, dimension)
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    Ok(HtmlTemplate(StatsTemplate {
+        title: "Stats".to_string(),
+        by,
+        stats,
+    }))
+}
@@ -0,0 +1,130 @@
+use std::sync::Arc;
+
+use axum::{Router, http::StatusCode, routing::post};
+use serde::Deserialize;
+
+use crate::{
+    AppState,
+    auth::hash_token,
+    db::{WatchHistory, claim_webhook_session, insert_watch_history, use_api_token},
+    library::{PlayedMedia, get_or_add_played_media},
+    response::AppError,
+};
+
+mod emby;
+mod jellyfin;
+mod plex;
+
+/// How long a playback session is remembered for when ignoring duplicate
+/// events. Servers that don't send a session id get one made from the device
+/// and item, so this also bounds how soon a rewatch on the same device is
+/// recorded.
+pub(crate) const SESSION_WINDOW_HOURS: i32 = 12;
+
+pub fn build_router() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/webhooks/plex", post(plex::post_plex_webhook))
+        .route("/webhooks/jellyfin", post(jellyfin::post_jellyfin_webhook))
+        .route("/webhooks/emby", post(emby::post_emby_webhook))
+}
+
+#[derive(Deserialize)]
+pub struct WebhookParams {
+    token: String,
+}
+
+/// Checks the token passed by a media server, returning the id of the user
+/// the plays are recorded for.
+async fn verify_token(state: &AppState, params: &WebhookParams) -> Result<i32, AppError> {
+    check_api_token(state, &params.token).await
+}
+
+/// Checks a token against the API tokens, returning the id of the user it
+/// belongs to.
+pub(super) async fn check_api_token(state: &AppState, token: &str) -> Result<i32, AppError> {
+    let conn = state
+        .pool
+        .get()
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    // Only hashes are stored, so looking them up doesn't leak timing about
+    // the tokens themselves.
+    use_api_token(&conn, &hash_token(token))
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?
+        .ok_or(AppError::Unauthorized)
+}
+
+/// Whether playback stopped far enough into the item to count as a play.
+fn reached_play_threshold(
+    state: &AppState,
+    position_ticks: Option<i64>,
+    runtime_ticks: Option<i64>,
+) -> bool {
+    match (position_ticks, runtime_ticks) {
+        (Some(position), Some(runtime)) if runtime > 0 => {
+            position as f64 / runtime as f64 * 100.0 >= f64::from(state.play_threshold_percent)
+        }
+        _ => false,
+    }
+}
+
+/// Resolves a played movie or episode and records the play. With a session,
+/// the play is only recorded for the first event of that session.
+async fn record_play(
+    state: &AppState,
+    user_id: i32,
+    source: &str,
+    session_id: Option<&str>,
+    title: &str,
+    played: &PlayedMedia,
+) -> Result<StatusCode, AppError> {
+    let mut conn = state
+        .pool
+        .get()
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    // The session is only claimed along with the play, so that the next
+    // event isn't ignored when this one fails to be recorded. Concurrent
+    // events of the same session wait on the claim until this commits.
+    let mut tx = conn
+        .transaction()
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    if let Some(session_id) = session_id
+        && !claim_webhook_session(&tx, user_id, source, session_id, SESSION_WINDOW_HOURS)
+            .await
+            .map_err(|err| AppError::Internal(err.into()))?
+    {
+        tracing::debug!(source, session_id, "ignoring duplicate webhook event");
+        return Ok(StatusCode::NO_CONTENT);
+    }
+
+    let Some(media) = get_or_add_played_media(&mut tx, &state.tmdb_api, played)
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?
+    else {
+        tracing::warn!(source, title, ?played, "no media found for webhook play");
+        return Ok(StatusCode::NO_CONTENT);
+    };
+
+    insert_watch_history(
+        &tx,
+        user_id,
+        &WatchHistory {
+            media,
+            watched_at: jiff::Timestamp::now(),
+        },
+    )
+    .await
+    .map_err(|err| AppError::Internal(err.into()))?;
+
+    tx.commit()
+        .await
+        .map_err(|err| AppError::Internal(err.into()))?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
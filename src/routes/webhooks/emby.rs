@@ -0,0 +1,120 @@
+use std::{collections::HashMap, sync::Arc};
+
+use axum::{
+    Json,
+    extract::{Query, State},
+    http::StatusCode,
+};
+use serde::Deserialize;
+
+use super::{WebhookParams, reached_play_threshold, record_play, verify_token};
+use crate::{AppState, db::MediaExternalId, library::PlayedMedia, response::AppError};
+
+const SOURCE: &str = "emby";
+const PLAYBACK_STOP_EVENT: &str = "playback.stop";
+
+/// Payload of Emby's webhook notifications.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct EmbyPayload {
+    event: String,
+    item: Option<EmbyItem>,
+    session: Option<EmbySession>,
+    playback_info: Option<EmbyPlaybackInfo>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct EmbyItem {
+    id: String,
+    name: String,
+    #[serde(rename = "Type")]
+    kind: String,
+    /// Season number, for episodes.
+    parent_index_number: Option<i32>,
+    /// Episode number, for episodes.
+    index_number: Option<i32>,
+    run_time_ticks: Option<i64>,
+    #[serde(default)]
+    provider_ids: HashMap<String, String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct EmbySession {
+    id: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct EmbyPlaybackInfo {
+    position_ticks: Option<i64>,
+    #[serde(default)]
+    played_to_completion: bool,
+    play_session_id: Option<String>,
+}
+
+/// Receives Emby webhook notifications and records a play when playback
+/// stops past the play threshold.
+pub async fn post_emby_webhook(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<WebhookParams>,
+    Json(payload): Json<EmbyPayload>,
+) -> Result<StatusCode, AppError> {
+    let user_id = verify_token(&state, &params).await?;
+
+    if payload.event != PLAYBACK_STOP_EVENT {
+        return Ok(StatusCode::NO_CONTENT);
+    }
+
+    let (Some(item), Some(playback_info)) = (payload.item, payload.playback_info) else {
+        return Err(AppError::BadRequest);
+    };
+
+    if !playback_info.played_to_completion
+        && !reached_play_threshold(&state, playback_info.position_ticks, item.run_time_ticks)
+    {
+        return Ok(StatusCode::NO_CONTENT);
+    }
+
+    // Provider id keys aren't consistently cased across Emby versions.
+    let provider_id = |name: &str| {
+        item.provider_ids
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.clone())
+    };
+    let ids = MediaExternalId {
+        tmdb_id: provider_id("tmdb").and_then(|id| id.parse().ok()),
+        imdb_id: provider_id("imdb"),
+        tvdb_id: provider_id("tvdb").and_then(|id| id.parse().ok()),
+        ..Default::default()
+    };
+
+    let played = match item.kind.as_str() {
+        "Movie" => PlayedMedia::Movie { ids },
+        "Episode" => PlayedMedia::Episode {
+            ids,
+            show_ids: MediaExternalId::default(),
+            season_number: item.parent_index_number,
+            episode_number: item.index_number,
+        },
+        _ => return Ok(StatusCode::NO_CONTENT),
+    };
+
+    let session_id = playback_info.play_session_id.or_else(|| {
+        payload
+            .session
+            .map(|session| format!("{}:{}", session.id, item.id))
+    });
+
+    record_play(
+        &state,
+        user_id,
+        SOURCE,
+        session_id.as_deref(),
+        &item.name,
+        &played,
+    )
+    .await
+}
@@ -0,0 +1,99 @@
+use std::sync::Arc;
+
+use axum::{
+    Json,
+    extract::{Query, State},
+    http::StatusCode,
+};
+use serde::Deserialize;
+
+use super::{WebhookParams, reached_play_threshold, record_play, verify_token};
+use crate::{AppState, db::MediaExternalId, library::PlayedMedia, response::AppError};
+
+const SOURCE: &str = "jellyfin";
+const PLAYBACK_STOP_EVENT: &str = "PlaybackStop";
+
+/// Payload of the Jellyfin Webhook plugin's default template.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct JellyfinPayload {
+    notification_type: String,
+    item_id: Option<String>,
+    item_type: Option<String>,
+    name: Option<String>,
+    season_number: Option<i32>,
+    episode_number: Option<i32>,
+    #[serde(rename = "Provider_tmdb", alias = "Provider_Tmdb")]
+    provider_tmdb: Option<String>,
+    #[serde(rename = "Provider_imdb", alias = "Provider_Imdb")]
+    provider_imdb: Option<String>,
+    #[serde(rename = "Provider_tvdb", alias = "Provider_Tvdb")]
+    provider_tvdb: Option<String>,
+    playback_position_ticks: Option<i64>,
+    run_time_ticks: Option<i64>,
+    #[serde(default)]
+    played_to_completion: bool,
+    play_session_id: Option<String>,
+    device_id: Option<String>,
+    user_id: Option<String>,
+}
+
+/// Receives Jellyfin Webhook plugin events and records a play when playback
+/// stops past the play threshold.
+pub async fn post_jellyfin_webhook(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<WebhookParams>,
+    Json(payload): Json<JellyfinPayload>,
+) -> Result<StatusCode, AppError> {
+    let user_id = verify_token(&state, &params).await?;
+
+    if payload.notification_type != PLAYBACK_STOP_EVENT {
+        return Ok(StatusCode::NO_CONTENT);
+    }
+
+    if !payload.played_to_completion
+        && !reached_play_threshold(
+            &state,
+            payload.playback_position_ticks,
+            payload.run_time_ticks,
+        )
+    {
+        return Ok(StatusCode::NO_CONTENT);
+    }
+
+    let ids = MediaExternalId {
+        tmdb_id: payload.provider_tmdb.and_then(|id| id.parse().ok()),
+        imdb_id: payload.provider_imdb,
+        tvdb_id: payload.provider_tvdb.and_then(|id| id.parse().ok()),
+        ..Default::default()
+    };
+
+    let played = match payload.item_type.as_deref() {
+        Some("Movie") => PlayedMedia::Movie { ids },
+        Some("Episode") => PlayedMedia::Episode {
+            ids,
+            show_ids: MediaExternalId::default(),
+            season_number: payload.season_number,
+            episode_number: payload.episode_number,
+        },
+        _ => return Ok(StatusCode::NO_CONTENT),
+    };
+
+    // Older versions of the plugin don't send the play session id.
+    let session_id = payload.play_session_id.or_else(|| {
+        Some(format!(
+            "{}:{}:{}",
+            payload.user_id?, payload.device_id?, payload.item_id?
+        ))
+    });
+
+    record_play(
+        &state,
+        user_id,
+        SOURCE,
+        session_id.as_deref(),
+        payload.name.as_deref().unwrap_or_default(),
+        &played,
+    )
+    .await
+}
@@ -0,0 +1,135 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Multipart, Query, State},
+    http::StatusCode,
+};
+use serde::Deserialize;
+
+use super::{WebhookParams, record_play, verify_token};
+use crate::{AppState, db::MediaExternalId, library::PlayedMedia, response::AppError};
+
+const SOURCE: &str = "plex";
+const SCROBBLE_EVENT: &str = "media.scrobble";
+
+#[derive(Deserialize, Debug)]
+struct PlexPayload {
+    event: String,
+    #[serde(rename = "Metadata")]
+    metadata: Option<PlexMetadata>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct PlexMetadata {
+    #[serde(rename = "type")]
+    kind: String,
+    title: String,
+    /// Legacy agents put the only external id here, e.g.
+    /// `com.plexapp.agents.imdb://tt0113277?lang=en`.
+    guid: Option<String>,
+    /// External ids from the new Plex agents, e.g. `tmdb://949`.
+    #[serde(rename = "Guid", default)]
+    guids: Vec<PlexGuid>,
+    /// Season number, for episodes.
+    parent_index: Option<i32>,
+    /// Episode number, for episodes.
+    index: Option<i32>,
+}
+
+#[derive(Deserialize, Debug)]
+struct PlexGuid {
+    id: String,
+}
+
+/// Receives Plex webhooks and records a play for every `media.scrobble`
+/// event, which Plex sends once a movie or episode is watched past 90%.
+/// Other events are ignored.
+pub async fn post_plex_webhook(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<WebhookParams>,
+    mut multipart: Multipart,
+) -> Result<StatusCode, AppError> {
+    let user_id = verify_token(&state, &params).await?;
+
+    let mut payload: Option<PlexPayload> = None;
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|_| AppError::BadRequest)?
+    {
+        // The other part Plex sends is the poster as `thumb`.
+        if field.name() == Some("payload") {
+            let text = field.text().await.map_err(|_| AppError::BadRequest)?;
+            payload = Some(serde_json::from_str(&text).map_err(|_| AppError::BadRequest)?);
+        }
+    }
+
+    let Some(payload) = payload else {
+        return Err(AppError::BadRequest);
+    };
+
+    if payload.event != SCROBBLE_EVENT {
+        return Ok(StatusCode::NO_CONTENT);
+    }
+
+    let Some(metadata) = payload.metadata else {
+        return Err(AppError::BadRequest);
+    };
+
+    let Some(played) = played_media(&metadata) else {
+        tracing::debug!(kind = metadata.kind, "ignoring plex scrobble");
+        return Ok(StatusCode::NO_CONTENT);
+    };
+
+    record_play(&state, user_id, SOURCE, None, &metadata.title, &played).await
+}
+
+fn played_media(metadata: &PlexMetadata) -> Option<PlayedMedia> {
+    let mut ids = MediaExternalId::default();
+    let mut show_ids = MediaExternalId::default();
+
+    for guid in &metadata.guids {
+        apply_guid(&mut ids, &guid.id);
+    }
+
+    if let Some(guid) = &metadata.guid
+        && let Some((agent, value)) = guid.split_once("://")
+    {
+        let value = value.split('?').next().unwrap_or_default();
+        match agent {
+            "com.plexapp.agents.imdb" => ids.imdb_id = Some(value.to_string()),
+            "com.plexapp.agents.themoviedb" => ids.tmdb_id = value.parse().ok(),
+            // Episode guids hold the show's id, season and episode numbers,
+            // e.g. `com.plexapp.agents.thetvdb://73244/1/1`.
+            "com.plexapp.agents.thetvdb" => {
+                show_ids.tvdb_id = value.split('/').next().and_then(|id| id.parse().ok())
+            }
+            _ => {}
+        }
+    }
+
+    match metadata.kind.as_str() {
+        "movie" => Some(PlayedMedia::Movie { ids }),
+        "episode" => Some(PlayedMedia::Episode {
+            ids,
+            show_ids,
+            season_number: metadata.parent_index,
+            episode_number: metadata.index,
+        }),
+        _ => None,
+    }
+}
+
+fn apply_guid(ids: &mut MediaExternalId, guid: &str) {
+    let Some((source, value)) = guid.split_once("://") else {
+        return;
+    };
+
+    match source {
+        "imdb" => ids.imdb_id = Some(value.to_string()),
+        "tmdb" => ids.tmdb_id = value.parse().ok(),
+        "tvdb" => ids.tvdb_id = value.parse().ok(),
+        _ => {}
+    }
+}
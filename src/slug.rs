@@ -0,0 +1,99 @@
+//! Slugs used in movie and show URLs, e.g. `/movie/the-matrix-1999`.
+//!
+//! Slugs are made once, when the movie or show is added, and never change
+//! afterwards so links keep working.
+
+/// Lowercases the text and joins its words with dashes, dropping everything
+/// but letters and digits.
+pub fn slugify(text: &str) -> String {
+    let mut slug = String::with_capacity(text.len());
+
+    for c in text.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+
+    if slug.ends_with('-') {
+        slug.pop();
+    }
+
+    slug
+}
+
+/// Makes the slug for a new movie or show from its title and release year,
+/// unless a slug to start from is given, e.g. the one from Trakt. `kind`
+/// stands in for titles without any letters or digits.
+///
+/// Slugs are never all digits, so they can't be mistaken for ids.
+pub fn media_slug(
+    kind: &str,
+    title: &str,
+    release_year: Option<i32>,
+    seed: Option<&str>,
+) -> String {
+    let mut slug = seed.map(slugify).unwrap_or_default();
+
+    if slug.is_empty() {
+        slug = match release_year {
+            Some(release_year) => slugify(&format!("{title} {release_year}")),
+            None => slugify(title),
+        };
+    }
+
+    if slug.is_empty() {
+        kind.to_string()
+    } else if slug.bytes().all(|b| b.is_ascii_digit()) {
+        format!("{slug}-{kind}")
+    } else {
+        slug
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slugify_joins_words_with_dashes() {
+        assert_eq!(slugify("The Matrix"), "the-matrix");
+        assert_eq!(
+            slugify("  Spider-Man: No Way Home! "),
+            "spider-man-no-way-home"
+        );
+        assert_eq!(slugify("Amélie"), "amélie");
+        assert_eq!(slugify("WALL·E"), "wall-e");
+        assert_eq!(slugify("?!"), "");
+    }
+
+    #[test]
+    fn media_slug_adds_the_release_year() {
+        assert_eq!(
+            media_slug("movie", "The Matrix", Some(1999), None),
+            "the-matrix-1999"
+        );
+        assert_eq!(media_slug("show", "Lost", None, None), "lost");
+    }
+
+    #[test]
+    fn media_slug_prefers_the_seed() {
+        assert_eq!(
+            media_slug("movie", "The Matrix", Some(1999), Some("the-matrix-1999-2")),
+            "the-matrix-1999-2"
+        );
+        assert_eq!(
+            media_slug("movie", "The Matrix", Some(1999), Some("!!")),
+            "the-matrix-1999"
+        );
+    }
+
+    #[test]
+    fn media_slug_is_never_empty_or_all_digits() {
+        assert_eq!(media_slug("movie", "…", None, None), "movie");
+        assert_eq!(media_slug("movie", "1917", None, None), "1917-movie");
+        assert_eq!(media_slug("movie", "1917", Some(2019), None), "1917-2019");
+        assert_eq!(media_slug("show", "24", None, Some("24")), "24-show");
+    }
+}
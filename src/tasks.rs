@@ -0,0 +1,78 @@
+//! Background tasks of the server, stopped along with it.
+
+use std::time::Duration;
+
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tracing::{debug, error};
+
+pub struct TaskSupervisor {
+    tracker: TaskTracker,
+    cancel: CancellationToken,
+}
+
+impl TaskSupervisor {
+    pub fn new() -> Self {
+        Self {
+            tracker: TaskTracker::new(),
+            cancel: CancellationToken::new(),
+        }
+    }
+
+    /// Token cancelled on shutdown.
+    pub fn cancel_token(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
+
+    /// Runs a task in the background, logging how it ends. Tasks are given
+    /// the cancel token and should stop soon once it's cancelled, at a point
+    /// where they can pick up from later.
+    pub fn spawn<F, Fut>(&self, name: &'static str, task: F)
+    where
+        F: FnOnce(CancellationToken) -> Fut,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        let task = tokio::spawn(task(self.cancel.clone()));
+        self.tracker.spawn(async move {
+            match task.await {
+                Ok(Ok(())) => debug!(task = name, "task ended"),
+                Ok(Err(err)) => error!(task = name, err = format!("{err:#}"), "task failed"),
+                Err(err) => error!(task = name, %err, "task panicked"),
+            }
+        });
+    }
+
+    /// Cancels the tasks and waits for them to end, up to the timeout.
+    /// Returns whether they all did.
+    pub async fn shutdown(&self, timeout: Duration) -> bool {
+        self.cancel.cancel();
+        self.tracker.close();
+
+        tokio::time::timeout(timeout, self.tracker.wait())
+            .await
+            .is_ok()
+    }
+}
+
+/// Resolves on SIGINT or SIGTERM.
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to listen for SIGINT");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
@@ -1,6 +1,10 @@
 use std::fmt::Display;
 
 use jiff::civil::Date;
+use prometheus_client::{
+    encoding::EncodeLabelSet,
+    metrics::{counter::Counter, family::Family},
+};
 use reqwest::StatusCode;
 use serde::{
     Deserialize,
@@ -10,7 +14,17 @@ use thiserror::Error;
 
 pub struct TmdbApi {
     api_key: String,
+    /// Sent with every request, see `AppConfig::tmdb_language`.
+    language: String,
     client: reqwest::Client,
+    request_counts: Family<TmdbRequestLabels, Counter>,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct TmdbRequestLabels {
+    endpoint: &'static str,
+    /// ok, not_found or error.
+    outcome: &'static str,
 }
 
 #[derive(Deserialize)]
@@ -93,6 +107,88 @@ pub struct SearchResultEntry {
     pub media: SearchResultMedia,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct MovieSearchResult {
+    pub id: TmdbId,
+    pub title: String,
+    pub original_title: String,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub release_date: Option<Date>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CastCredit {
+    pub id: TmdbId,
+    pub name: String,
+    pub character: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CrewCredit {
+    pub id: TmdbId,
+    pub name: String,
+    pub job: String,
+    pub department: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Credits {
+    pub cast: Vec<CastCredit>,
+    pub crew: Vec<CrewCredit>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Genre {
+    pub id: TmdbId,
+    pub name: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Keyword {
+    pub id: TmdbId,
+    pub name: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MovieKeywords {
+    pub keywords: Vec<Keyword>,
+}
+
+/// Same as [`MovieKeywords`], TMDB names the list differently for shows.
+#[derive(Deserialize, Debug)]
+pub struct ShowKeywords {
+    pub results: Vec<Keyword>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ProductionCountry {
+    /// ISO 3166-1 code.
+    pub iso_3166_1: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CollectionSummary {
+    pub id: TmdbId,
+    pub name: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CollectionPart {
+    pub id: TmdbId,
+    pub title: String,
+    pub original_title: String,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    pub release_date: Option<Date>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct FullCollection {
+    pub id: TmdbId,
+    pub name: String,
+    pub overview: String,
+    pub parts: Vec<CollectionPart>,
+}
+
 #[derive(Deserialize, Debug)]
 pub struct FullMovie {
     pub id: TmdbId,
@@ -107,7 +203,11 @@ pub struct FullMovie {
     pub runtime: i32,
     pub imdb_id: String,
     pub images: Option<Images>,
-    // TODO: fetch collections
+    pub genres: Vec<Genre>,
+    pub production_countries: Vec<ProductionCountry>,
+    pub credits: Option<Credits>,
+    pub keywords: Option<MovieKeywords>,
+    pub belongs_to_collection: Option<CollectionSummary>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -138,6 +238,10 @@ pub struct FullShow {
     pub number_of_episodes: i32,
     pub seasons: Vec<Season>,
     pub images: Option<Images>,
+    pub genres: Vec<Genre>,
+    pub production_countries: Vec<ProductionCountry>,
+    pub credits: Option<Credits>,
+    pub keywords: Option<ShowKeywords>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -148,6 +252,10 @@ pub struct Episode {
     pub name: String,
     pub overview: String,
     pub runtime: Option<i32>,
+    #[serde(default)]
+    pub guest_stars: Vec<CastCredit>,
+    #[serde(default)]
+    pub crew: Vec<CrewCredit>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -159,13 +267,55 @@ pub struct FullSeason {
     pub episodes: Vec<Episode>,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct FullPerson {
+    pub id: TmdbId,
+    pub name: String,
+}
+
+/// External id sources accepted by [`TmdbApi::find_by_external_id`].
+#[derive(Debug, Clone, Copy)]
+pub enum ExternalSource {
+    Imdb,
+    Tvdb,
+}
+
+impl ExternalSource {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ExternalSource::Imdb => "imdb_id",
+            ExternalSource::Tvdb => "tvdb_id",
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct FindResult {
+    pub id: TmdbId,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct FindEpisodeResult {
+    pub id: TmdbId,
+    pub show_id: TmdbId,
+    pub season_number: i32,
+    pub episode_number: i32,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct FindResults {
+    pub movie_results: Vec<FindResult>,
+    pub tv_results: Vec<FindResult>,
+    pub tv_episode_results: Vec<FindEpisodeResult>,
+}
+
 fn empty_string_as_none<'de, D, T>(de: D) -> Result<Option<T>, D::Error>
 where
     D: serde::Deserializer<'de>,
     T: serde::Deserialize<'de>,
 {
     let opt = Option::<String>::deserialize(de)?;
-    let opt = opt.as_ref().map(String::as_str);
+    let opt = opt.as_deref();
     match opt {
         None | Some("") => Ok(None),
         Some(s) => T::deserialize(s.into_deserializer()).map(Some),
@@ -197,83 +347,155 @@ pub enum ApiError {
 impl TmdbApi {
     const BASE_URL: &'static str = "https://api.themoviedb.org/3";
 
-    pub fn new(api_key: &str) -> TmdbApi {
+    pub fn new(api_key: &str, language: &str) -> TmdbApi {
         let client = reqwest::Client::new();
         TmdbApi {
             api_key: api_key.to_string(),
+            language: language.to_string(),
             client,
+            request_counts: Family::default(),
         }
     }
 
+    /// Language the texts are fetched in.
+    pub fn language(&self) -> &str {
+        &self.language
+    }
+
+    fn get(&self, path: &str) -> reqwest::RequestBuilder {
+        self.client
+            .get(format!("{}{}", Self::BASE_URL, path))
+            .query(&[("language", &self.language)])
+            .bearer_auth(&self.api_key)
+    }
+
     pub async fn multi_search(
         &self,
         query: &str,
     ) -> Result<ListResponse<SearchResultEntry>, ApiError> {
-        // TODO: handle person results
-        Self::json_request(
-            self.client
-                .get(format!("{}/search/multi", Self::BASE_URL))
-                .query(&[("query", query)])
-                .bearer_auth(self.api_key.to_string()),
+        self.json_request(
+            "search/multi",
+            self.get("/search/multi").query(&[("query", query)]),
         )
         .await
     }
 
-    pub async fn fetch_config(&self) -> Result<Config, ApiError> {
-        Self::json_request(
-            self.client
-                .get(format!("{}/configuration", Self::BASE_URL))
-                .bearer_auth(self.api_key.to_string()),
+    pub async fn search_movie(
+        &self,
+        query: &str,
+        year: Option<i32>,
+    ) -> Result<ListResponse<MovieSearchResult>, ApiError> {
+        let mut req = self.get("/search/movie").query(&[("query", query)]);
+
+        if let Some(year) = year {
+            req = req.query(&[("primary_release_year", year)]);
+        }
+
+        self.json_request("search/movie", req).await
+    }
+
+    /// Finds movies, shows and episodes by their IMDb or TVDB id.
+    pub async fn find_by_external_id(
+        &self,
+        external_id: &str,
+        source: ExternalSource,
+    ) -> Result<FindResults, ApiError> {
+        self.json_request(
+            "find",
+            self.get(&format!("/find/{}", external_id))
+                .query(&[("external_source", source.as_str())]),
         )
         .await
     }
 
+    pub async fn fetch_config(&self) -> Result<Config, ApiError> {
+        self.json_request("configuration", self.get("/configuration"))
+            .await
+    }
+
     pub async fn fetch_full_movie(&self, movie_id: &TmdbId) -> Result<FullMovie, ApiError> {
-        Self::json_request(
-            self.client
-                .get(format!("{}/movie/{}", Self::BASE_URL, movie_id.0))
-                .bearer_auth(self.api_key.to_string()),
+        self.json_request(
+            "movie",
+            self.get(&format!("/movie/{}", movie_id.0))
+                .query(&[("append_to_response", "credits,keywords")]),
         )
         .await
     }
 
     pub async fn fetch_full_show(&self, show_id: &TmdbId) -> Result<FullShow, ApiError> {
-        Self::json_request(
-            self.client
-                .get(format!("{}/tv/{}", Self::BASE_URL, show_id.0))
-                .bearer_auth(self.api_key.to_string()),
+        self.json_request(
+            "tv",
+            self.get(&format!("/tv/{}", show_id.0))
+                .query(&[("append_to_response", "credits,keywords")]),
         )
         .await
     }
 
+    pub async fn fetch_collection(
+        &self,
+        collection_id: &TmdbId,
+    ) -> Result<FullCollection, ApiError> {
+        self.json_request(
+            "collection",
+            self.get(&format!("/collection/{}", collection_id.0)),
+        )
+        .await
+    }
+
+    pub async fn fetch_person(&self, person_id: &TmdbId) -> Result<FullPerson, ApiError> {
+        self.json_request("person", self.get(&format!("/person/{}", person_id.0)))
+            .await
+    }
+
     pub async fn fetch_full_season(
         &self,
         show_id: &TmdbId,
         season_number: i32,
     ) -> Result<FullSeason, ApiError> {
-        Self::json_request(
-            self.client
-                .get(format!(
-                    "{}/tv/{}/season/{}",
-                    Self::BASE_URL,
-                    show_id.0,
-                    season_number
-                ))
-                .bearer_auth(self.api_key.to_string()),
+        self.json_request(
+            "tv/season",
+            self.get(&format!("/tv/{}/season/{}", show_id.0, season_number)),
         )
         .await
     }
 
     pub async fn fetch_movie_images(&self, movie_id: &TmdbId) -> Result<Images, ApiError> {
-        Self::json_request(
-            self.client
-                .get(format!("{}/movie/{}/images", Self::BASE_URL, movie_id.0))
-                .bearer_auth(self.api_key.to_string()),
+        // Images in other languages are left out otherwise, textless ones
+        // included.
+        let image_language = self.language.split('-').next().unwrap_or_default();
+        self.json_request(
+            "movie/images",
+            self.get(&format!("/movie/{}/images", movie_id.0))
+                .query(&[("include_image_language", format!("{image_language},null"))]),
         )
         .await
     }
 
+    /// Number of requests by endpoint and outcome, see [`crate::metrics`].
+    pub fn request_counts(&self) -> &Family<TmdbRequestLabels, Counter> {
+        &self.request_counts
+    }
+
     async fn json_request<T: DeserializeOwned>(
+        &self,
+        endpoint: &'static str,
+        req: reqwest::RequestBuilder,
+    ) -> Result<T, ApiError> {
+        let result = Self::send_json_request(req).await;
+
+        let outcome = match &result {
+            Ok(_) => "ok",
+            Err(ApiError::NotFound) => "not_found",
+            Err(_) => "error",
+        };
+        self.request_counts
+            .get_or_create(&TmdbRequestLabels { endpoint, outcome })
+            .inc();
+
+        result
+    }
+
+    async fn send_json_request<T: DeserializeOwned>(
         req: reqwest::RequestBuilder,
     ) -> Result<T, ApiError> {
         let res = req.send().await.map_err(map_reqwest_error)?;
@@ -1 +1,144 @@
+//! Trakt data shapes, as found in Trakt's API responses and data exports.
+
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+
+use crate::db::MediaExternalId;
+
+pub(crate) mod api;
+pub mod export;
 pub mod import;
+pub mod sync;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TraktExternalIds {
+    /// Missing for media that was never synced with Trakt, e.g. in exports
+    /// made by Grimoire.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trakt: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    slug: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tvdb: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    imdb: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tmdb: Option<i32>,
+}
+
+impl From<TraktExternalIds> for MediaExternalId {
+    fn from(ids: TraktExternalIds) -> Self {
+        MediaExternalId {
+            trakt_id: ids.trakt,
+            trakt_slug: ids.slug,
+            tvdb_id: ids.tvdb,
+            imdb_id: ids.imdb,
+            tmdb_id: ids.tmdb,
+        }
+    }
+}
+
+impl From<MediaExternalId> for TraktExternalIds {
+    fn from(ids: MediaExternalId) -> Self {
+        TraktExternalIds {
+            trakt: ids.trakt_id,
+            slug: ids.trakt_slug,
+            tvdb: ids.tvdb_id,
+            imdb: ids.imdb_id,
+            tmdb: ids.tmdb_id,
+        }
+    }
+}
+
+impl TraktExternalIds {
+    fn is_empty(&self) -> bool {
+        self.trakt.is_none()
+            && self.slug.is_none()
+            && self.tvdb.is_none()
+            && self.imdb.is_none()
+            && self.tmdb.is_none()
+    }
+
+    /// Whether both have an id in common, in which case they're for the same
+    /// media, assuming it's of the same kind.
+    fn matches(&self, other: &TraktExternalIds) -> bool {
+        fn same<T: PartialEq>(a: &Option<T>, b: &Option<T>) -> bool {
+            a.is_some() && a == b
+        }
+
+        same(&self.trakt, &other.trakt)
+            || same(&self.slug, &other.slug)
+            || same(&self.tvdb, &other.tvdb)
+            || same(&self.imdb, &other.imdb)
+            || same(&self.tmdb, &other.tmdb)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TraktMovie {
+    title: String,
+    year: Option<i32>,
+    ids: TraktExternalIds,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TraktEpisode {
+    #[serde(rename = "season")]
+    season_number: i32,
+    number: i32,
+    title: String,
+    ids: TraktExternalIds,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TraktShow {
+    title: String,
+    year: Option<i32>,
+    ids: TraktExternalIds,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TraktSeason {
+    number: i32,
+    ids: TraktExternalIds,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type")]
+enum TraktMedia {
+    #[serde(rename = "movie")]
+    Movie { movie: TraktMovie },
+    #[serde(rename = "episode")]
+    Episode {
+        episode: TraktEpisode,
+        show: TraktShow,
+    },
+    #[serde(rename = "show")]
+    Show { show: TraktShow },
+    #[serde(rename = "season")]
+    Season {
+        season: TraktSeason,
+        show: TraktShow,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WatchHistoryEntry {
+    watched_at: Timestamp,
+    #[serde(flatten)]
+    media: TraktMedia,
+    // unused fields:
+    // action: String,
+    // progress: f32,
+    // duration: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WatchlistEntry {
+    listed_at: jiff::Timestamp,
+    #[serde(flatten)]
+    media: TraktMedia,
+    // unused fields:
+    // rank: i32,
+    // notes: Option<String>,
+}
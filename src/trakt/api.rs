@@ -0,0 +1,665 @@
+use jiff::Timestamp;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use thiserror::Error;
+
+use super::{TraktExternalIds, WatchHistoryEntry, WatchlistEntry};
+
+pub struct TraktApi {
+    base_url: String,
+    client_id: String,
+    client_secret: String,
+    client: reqwest::Client,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct DeviceCode {
+    pub device_code: String,
+    pub user_code: String,
+    pub verification_url: String,
+    /// Seconds until the device code expires.
+    pub expires_in: i64,
+    /// Seconds to wait between token polls.
+    pub interval: i64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Token {
+    pub access_token: String,
+    pub refresh_token: String,
+    /// Seconds the access token is valid for, starting from `created_at`.
+    pub expires_in: i64,
+    /// Unix timestamp of when the token was created.
+    pub created_at: i64,
+}
+
+impl Token {
+    pub fn expires_at(&self) -> Timestamp {
+        Timestamp::from_second(self.created_at + self.expires_in).unwrap_or(Timestamp::MAX)
+    }
+}
+
+#[derive(Debug)]
+pub enum DeviceTokenPoll {
+    Authorized(Token),
+    /// The user hasn't approved the code yet.
+    Pending,
+    /// Polling too fast, the interval should be increased.
+    SlowDown,
+}
+
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub page_count: i32,
+}
+
+/// Items sent to the `sync` endpoints. Episodes and seasons are nested in
+/// their shows, which lets Trakt match them by number when they have no ids.
+#[derive(Serialize, Debug, Default)]
+pub struct SyncItems {
+    pub movies: Vec<SyncMovie>,
+    pub shows: Vec<SyncShow>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SyncMovie {
+    pub title: String,
+    pub year: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub watched_at: Option<Timestamp>,
+    pub ids: TraktExternalIds,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SyncShow {
+    pub title: String,
+    pub year: Option<i32>,
+    pub ids: TraktExternalIds,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub seasons: Vec<SyncSeason>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SyncSeason {
+    pub number: i32,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub episodes: Vec<SyncEpisode>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SyncEpisode {
+    pub number: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub watched_at: Option<Timestamp>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct SyncCounts {
+    #[serde(default)]
+    pub movies: i32,
+    #[serde(default)]
+    pub episodes: i32,
+    #[serde(default)]
+    pub shows: i32,
+    #[serde(default)]
+    pub seasons: i32,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SyncResponse {
+    #[serde(default)]
+    pub added: SyncCounts,
+    #[serde(default)]
+    pub not_found: SyncNotFound,
+}
+
+/// Items of a sync request Trakt couldn't match, as they were sent.
+#[derive(Deserialize, Debug, Default)]
+pub struct SyncNotFound {
+    #[serde(default)]
+    pub movies: Vec<NotFoundItem>,
+    #[serde(default)]
+    pub shows: Vec<NotFoundItem>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NotFoundItem {
+    pub title: Option<String>,
+    pub year: Option<i32>,
+    #[serde(default)]
+    pub ids: TraktExternalIds,
+    /// For shows, the seasons that weren't found. The whole show wasn't
+    /// found when empty.
+    #[serde(default)]
+    pub seasons: Vec<NotFoundSeason>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NotFoundSeason {
+    pub number: i32,
+    /// All episodes of the season weren't found when empty.
+    #[serde(default)]
+    pub episodes: Vec<NotFoundEpisode>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NotFoundEpisode {
+    pub number: i32,
+}
+
+/// When the user's data last changed on Trakt.
+#[derive(Deserialize, Debug)]
+pub struct LastActivities {
+    pub movies: ActivityTimes,
+    pub episodes: ActivityTimes,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ActivityTimes {
+    /// Last time plays were added or removed.
+    pub watched_at: Timestamp,
+}
+
+impl LastActivities {
+    pub fn history_changed_at(&self) -> Timestamp {
+        self.movies.watched_at.max(self.episodes.watched_at)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ApiError {
+    #[error("failed to connect")]
+    Connect(#[source] reqwest::Error),
+    #[error("failed to parse response")]
+    Parsing(#[source] reqwest::Error),
+    #[error("unknown http error")]
+    UnknownHttp(#[source] reqwest::Error),
+    #[error("invalid or expired access token")]
+    Unauthorized,
+    #[error("invalid device code")]
+    InvalidDeviceCode,
+    #[error("device code was already used")]
+    DeviceCodeUsed,
+    #[error("device code expired")]
+    DeviceCodeExpired,
+    #[error("user denied the device code")]
+    DeviceCodeDenied,
+    #[error("unexpected status {0}")]
+    Status(StatusCode),
+}
+
+impl TraktApi {
+    const API_VERSION: &'static str = "2";
+    const HISTORY_PAGE_LIMIT: i32 = 100;
+
+    pub fn new(base_url: &str, client_id: &str, client_secret: &str) -> TraktApi {
+        TraktApi {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            client_id: client_id.to_string(),
+            client_secret: client_secret.to_string(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub async fn request_device_code(&self) -> Result<DeviceCode, ApiError> {
+        Self::json_request(
+            self.client
+                .post(format!("{}/oauth/device/code", self.base_url))
+                .json(&serde_json::json!({ "client_id": self.client_id })),
+        )
+        .await
+    }
+
+    pub async fn poll_device_token(&self, device_code: &str) -> Result<DeviceTokenPoll, ApiError> {
+        let res = self
+            .client
+            .post(format!("{}/oauth/device/token", self.base_url))
+            .json(&serde_json::json!({
+                "code": device_code,
+                "client_id": self.client_id,
+                "client_secret": self.client_secret,
+            }))
+            .send()
+            .await
+            .map_err(map_reqwest_error)?;
+
+        match res.status() {
+            StatusCode::OK => res
+                .json()
+                .await
+                .map(DeviceTokenPoll::Authorized)
+                .map_err(map_reqwest_error),
+            StatusCode::BAD_REQUEST => Ok(DeviceTokenPoll::Pending),
+            StatusCode::TOO_MANY_REQUESTS => Ok(DeviceTokenPoll::SlowDown),
+            StatusCode::NOT_FOUND => Err(ApiError::InvalidDeviceCode),
+            StatusCode::CONFLICT => Err(ApiError::DeviceCodeUsed),
+            StatusCode::GONE => Err(ApiError::DeviceCodeExpired),
+            StatusCode::IM_A_TEAPOT => Err(ApiError::DeviceCodeDenied),
+            status => Err(ApiError::Status(status)),
+        }
+    }
+
+    pub async fn refresh_token(&self, refresh_token: &str) -> Result<Token, ApiError> {
+        Self::json_request(
+            self.client
+                .post(format!("{}/oauth/token", self.base_url))
+                .json(&serde_json::json!({
+                    "refresh_token": refresh_token,
+                    "client_id": self.client_id,
+                    "client_secret": self.client_secret,
+                    "redirect_uri": "urn:ietf:wg:oauth:2.0:oob",
+                    "grant_type": "refresh_token",
+                })),
+        )
+        .await
+    }
+
+    /// Fetches a page of the user's watch history, newest first, optionally
+    /// only with plays watched after `start_at`. Pages start at 1.
+    pub async fn fetch_history(
+        &self,
+        access_token: &str,
+        start_at: Option<&Timestamp>,
+        page: i32,
+    ) -> Result<Page<WatchHistoryEntry>, ApiError> {
+        let mut req = self
+            .authorized(
+                self.client.get(format!("{}/sync/history", self.base_url)),
+                access_token,
+            )
+            .query(&[("page", page), ("limit", Self::HISTORY_PAGE_LIMIT)]);
+
+        if let Some(start_at) = start_at {
+            req = req.query(&[("start_at", start_at.to_string())]);
+        }
+
+        let res = Self::send(req).await?;
+
+        let page_count = res
+            .headers()
+            .get("x-pagination-page-count")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(1);
+
+        let items = res.json().await.map_err(map_reqwest_error)?;
+
+        Ok(Page { items, page_count })
+    }
+
+    pub async fn fetch_last_activities(
+        &self,
+        access_token: &str,
+    ) -> Result<LastActivities, ApiError> {
+        Self::json_request(
+            self.authorized(
+                self.client
+                    .get(format!("{}/sync/last_activities", self.base_url)),
+                access_token,
+            ),
+        )
+        .await
+    }
+
+    pub async fn add_to_history(
+        &self,
+        access_token: &str,
+        items: &SyncItems,
+    ) -> Result<SyncResponse, ApiError> {
+        Self::json_request(
+            self.authorized(
+                self.client.post(format!("{}/sync/history", self.base_url)),
+                access_token,
+            )
+            .json(items),
+        )
+        .await
+    }
+
+    pub async fn add_to_watchlist(
+        &self,
+        access_token: &str,
+        items: &SyncItems,
+    ) -> Result<SyncResponse, ApiError> {
+        Self::json_request(
+            self.authorized(
+                self.client
+                    .post(format!("{}/sync/watchlist", self.base_url)),
+                access_token,
+            )
+            .json(items),
+        )
+        .await
+    }
+
+    /// Fetches the user's whole watchlist.
+    pub async fn fetch_watchlist(
+        &self,
+        access_token: &str,
+    ) -> Result<Vec<WatchlistEntry>, ApiError> {
+        Self::json_request(self.authorized(
+            self.client.get(format!("{}/sync/watchlist", self.base_url)),
+            access_token,
+        ))
+        .await
+    }
+
+    fn authorized(
+        &self,
+        req: reqwest::RequestBuilder,
+        access_token: &str,
+    ) -> reqwest::RequestBuilder {
+        req.bearer_auth(access_token)
+            .header("trakt-api-version", Self::API_VERSION)
+            .header("trakt-api-key", &self.client_id)
+    }
+
+    async fn send(req: reqwest::RequestBuilder) -> Result<reqwest::Response, ApiError> {
+        let res = req.send().await.map_err(map_reqwest_error)?;
+
+        match res.status() {
+            status if status.is_success() => Ok(res),
+            StatusCode::UNAUTHORIZED => Err(ApiError::Unauthorized),
+            status => Err(ApiError::Status(status)),
+        }
+    }
+
+    async fn json_request<T: DeserializeOwned>(
+        req: reqwest::RequestBuilder,
+    ) -> Result<T, ApiError> {
+        Self::send(req)
+            .await?
+            .json()
+            .await
+            .map_err(map_reqwest_error)
+    }
+}
+
+fn map_reqwest_error(err: reqwest::Error) -> ApiError {
+    if err.is_connect() {
+        return ApiError::Connect(err);
+    }
+    if err.is_decode() {
+        return ApiError::Parsing(err);
+    }
+
+    ApiError::UnknownHttp(err)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{
+        Json, Router,
+        extract::Query,
+        http::HeaderMap,
+        response::IntoResponse,
+        routing::{get, post},
+    };
+    use serde_json::{Value, json};
+
+    use super::*;
+    use crate::trakt::TraktMedia;
+
+    /// Serves `router` on a free port and returns a client for it.
+    async fn mock_api(router: Router) -> TraktApi {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+        TraktApi::new(&format!("http://{addr}/"), "client-id", "client-secret")
+    }
+
+    fn assert_authorized(headers: &HeaderMap) {
+        assert_eq!(headers["authorization"], "Bearer access-token");
+        assert_eq!(headers["trakt-api-key"], "client-id");
+        assert_eq!(headers["trakt-api-version"], "2");
+    }
+
+    #[tokio::test]
+    async fn device_code_flow() {
+        let polls = Arc::new(Mutex::new(0));
+        let router = Router::new()
+            .route(
+                "/oauth/device/code",
+                post(|Json(body): Json<Value>| async move {
+                    assert_eq!(body, json!({ "client_id": "client-id" }));
+                    Json(json!({
+                        "device_code": "device-code",
+                        "user_code": "ABCD1234",
+                        "verification_url": "https://trakt.tv/activate",
+                        "expires_in": 600,
+                        "interval": 5,
+                    }))
+                }),
+            )
+            .route(
+                "/oauth/device/token",
+                post(move |Json(body): Json<Value>| async move {
+                    assert_eq!(body["code"], "device-code");
+                    assert_eq!(body["client_secret"], "client-secret");
+
+                    let mut polls = polls.lock().unwrap();
+                    *polls += 1;
+                    match *polls {
+                        1 => StatusCode::BAD_REQUEST.into_response(),
+                        2 => StatusCode::TOO_MANY_REQUESTS.into_response(),
+                        3 => Json(json!({
+                            "access_token": "access-token",
+                            "refresh_token": "refresh-token",
+                            "expires_in": 7200,
+                            "created_at": 1700000000,
+                        }))
+                        .into_response(),
+                        _ => StatusCode::CONFLICT.into_response(),
+                    }
+                }),
+            );
+        let api = mock_api(router).await;
+
+        let device_code = api.request_device_code().await.unwrap();
+        assert_eq!(device_code.user_code, "ABCD1234");
+        assert_eq!(device_code.verification_url, "https://trakt.tv/activate");
+        assert_eq!(device_code.interval, 5);
+
+        let poll = || api.poll_device_token(&device_code.device_code);
+        assert!(matches!(poll().await, Ok(DeviceTokenPoll::Pending)));
+        assert!(matches!(poll().await, Ok(DeviceTokenPoll::SlowDown)));
+        let Ok(DeviceTokenPoll::Authorized(token)) = poll().await else {
+            panic!("token wasn't authorized");
+        };
+        assert_eq!(token.access_token, "access-token");
+        assert_eq!(token.refresh_token, "refresh-token");
+        assert_eq!(
+            token.expires_at(),
+            Timestamp::from_second(1700007200).unwrap()
+        );
+        assert!(matches!(poll().await, Err(ApiError::DeviceCodeUsed)));
+    }
+
+    #[tokio::test]
+    async fn fetch_history_pages() {
+        let router = Router::new().route(
+            "/sync/history",
+            get(
+                |headers: HeaderMap, Query(query): Query<HashMap<String, String>>| async move {
+                    assert_authorized(&headers);
+                    assert_eq!(query["limit"], "100");
+                    assert_eq!(query["start_at"], "2024-01-01T00:00:00Z");
+
+                    let items = match query["page"].as_str() {
+                        "1" => json!([{
+                            "id": 2,
+                            "watched_at": "2024-03-01T20:00:00.000Z",
+                            "action": "watch",
+                            "type": "episode",
+                            "episode": {
+                                "season": 1,
+                                "number": 2,
+                                "title": "Pilot, Part 2",
+                                "ids": { "trakt": 20, "tvdb": 200 },
+                            },
+                            "show": {
+                                "title": "Lost",
+                                "year": 2004,
+                                "ids": { "trakt": 10, "slug": "lost-2004", "tmdb": 4607 },
+                            },
+                        }]),
+                        "2" => json!([{
+                            "id": 1,
+                            "watched_at": "2024-02-01T20:00:00.000Z",
+                            "action": "scrobble",
+                            "type": "movie",
+                            "movie": {
+                                "title": "Heat",
+                                "year": 1995,
+                                "ids": { "trakt": 1, "imdb": "tt0113277", "tmdb": 949 },
+                            },
+                        }]),
+                        page => panic!("unexpected page {page}"),
+                    };
+
+                    ([("x-pagination-page-count", "2")], Json(items))
+                },
+            ),
+        );
+        let api = mock_api(router).await;
+        let start_at: Timestamp = "2024-01-01T00:00:00Z".parse().unwrap();
+
+        let first = api
+            .fetch_history("access-token", Some(&start_at), 1)
+            .await
+            .unwrap();
+        assert_eq!(first.page_count, 2);
+        let [entry] = first.items.as_slice() else {
+            panic!("expected one entry, got {:?}", first.items);
+        };
+        assert_eq!(entry.watched_at, "2024-03-01T20:00:00Z".parse().unwrap());
+        let TraktMedia::Episode { episode, show } = &entry.media else {
+            panic!("expected an episode, got {:?}", entry.media);
+        };
+        assert_eq!((episode.season_number, episode.number), (1, 2));
+        assert_eq!(show.title, "Lost");
+        assert_eq!(show.ids.tmdb, Some(4607));
+
+        let second = api
+            .fetch_history("access-token", Some(&start_at), 2)
+            .await
+            .unwrap();
+        assert_eq!(second.page_count, 2);
+        let [entry] = second.items.as_slice() else {
+            panic!("expected one entry, got {:?}", second.items);
+        };
+        let TraktMedia::Movie { movie } = &entry.media else {
+            panic!("expected a movie, got {:?}", entry.media);
+        };
+        assert_eq!(movie.title, "Heat");
+        assert_eq!(movie.ids.imdb.as_deref(), Some("tt0113277"));
+    }
+
+    #[tokio::test]
+    async fn add_to_history() {
+        let received = Arc::new(Mutex::new(None));
+        let router = Router::new().route(
+            "/sync/history",
+            post({
+                let received = received.clone();
+                move |headers: HeaderMap, Json(body): Json<Value>| async move {
+                    assert_authorized(&headers);
+                    *received.lock().unwrap() = Some(body);
+
+                    (
+                        StatusCode::CREATED,
+                        Json(json!({
+                            "added": { "movies": 1, "episodes": 0 },
+                            "not_found": {
+                                "movies": [],
+                                "shows": [{
+                                    "title": "Unknown",
+                                    "year": 2020,
+                                    "ids": {},
+                                    "seasons": [{ "number": 1, "episodes": [{ "number": 3 }] }],
+                                }],
+                                "seasons": [],
+                                "episodes": [],
+                            },
+                        })),
+                    )
+                }
+            }),
+        );
+        let api = mock_api(router).await;
+        let watched_at: Timestamp = "2024-02-01T20:00:00Z".parse().unwrap();
+
+        let items = SyncItems {
+            movies: vec![SyncMovie {
+                title: "Heat".to_string(),
+                year: Some(1995),
+                watched_at: Some(watched_at),
+                ids: TraktExternalIds {
+                    tmdb: Some(949),
+                    ..Default::default()
+                },
+            }],
+            shows: vec![SyncShow {
+                title: "Unknown".to_string(),
+                year: Some(2020),
+                ids: TraktExternalIds::default(),
+                seasons: vec![SyncSeason {
+                    number: 1,
+                    episodes: vec![SyncEpisode {
+                        number: 3,
+                        watched_at: Some(watched_at),
+                    }],
+                }],
+            }],
+        };
+        let res = api.add_to_history("access-token", &items).await.unwrap();
+
+        assert_eq!(
+            received.lock().unwrap().take().unwrap(),
+            json!({
+                "movies": [{
+                    "title": "Heat",
+                    "year": 1995,
+                    "watched_at": "2024-02-01T20:00:00Z",
+                    "ids": { "tmdb": 949 },
+                }],
+                "shows": [{
+                    "title": "Unknown",
+                    "year": 2020,
+                    "ids": {},
+                    "seasons": [{
+                        "number": 1,
+                        "episodes": [{ "number": 3, "watched_at": "2024-02-01T20:00:00Z" }],
+                    }],
+                }],
+            })
+        );
+        assert_eq!(res.added.movies, 1);
+        assert!(res.not_found.movies.is_empty());
+        let [show] = res.not_found.shows.as_slice() else {
+            panic!("expected one show, got {:?}", res.not_found.shows);
+        };
+        assert_eq!(show.title.as_deref(), Some("Unknown"));
+        assert_eq!(show.seasons[0].episodes[0].number, 3);
+    }
+
+    #[tokio::test]
+    async fn expired_access_token() {
+        let router = Router::new().route(
+            "/sync/last_activities",
+            get(|| async { StatusCode::UNAUTHORIZED }),
+        );
+        let api = mock_api(router).await;
+
+        assert!(matches!(
+            api.fetch_last_activities("access-token").await,
+            Err(ApiError::Unauthorized)
+        ));
+    }
+}
@@ -0,0 +1,174 @@
+use std::io::Write;
+
+use deadpool_postgres::{
+    GenericClient,
+    tokio_postgres::{Row, types::ToSql},
+};
+use jiff::Timestamp;
+use zip::write::SimpleFileOptions;
+
+use super::{
+    TraktEpisode, TraktExternalIds, TraktMedia, TraktMovie, TraktSeason, TraktShow,
+    WatchHistoryEntry, WatchlistEntry,
+};
+use crate::db::{MediaExternalId, MediaKind};
+
+/// Directory the exported files are placed in. Trakt exports have the files
+/// inside a directory named after the user, which the importer relies on.
+const ROOT_DIR: &str = "grimoire";
+
+const HISTORY_ENTRIES_PER_FILE: usize = 1000;
+
+// TODO: error handling
+/// Writes a user's watch history and watchlist as a ZIP laid out like a Trakt
+/// data export, which [`super::import::import_zip`] can read back.
+pub async fn export_zip<C: GenericClient, W: Write + std::io::Seek>(
+    conn: &C,
+    user_id: i32,
+    writer: W,
+) -> anyhow::Result<()> {
+    let history: Vec<WatchHistoryEntry> = query_media(
+        conn,
+        "SELECT wh.media_id, wh.watched_at AS at, wh.id FROM watch_history wh
+        WHERE wh.user_id = $1",
+        &[&user_id],
+    )
+    .await?
+    .into_iter()
+    .map(|(_, watched_at, media)| WatchHistoryEntry { watched_at, media })
+    .collect();
+
+    let watchlist: Vec<WatchlistEntry> = query_media(
+        conn,
+        "SELECT li.media_id, li.listed_at AS at, li.media_id AS id FROM list_item li
+        INNER JOIN list l ON l.id = li.list_id
+        WHERE l.user_id = $1 AND l.kind = 'WATCHLIST'",
+        &[&user_id],
+    )
+    .await?
+    .into_iter()
+    .map(|(_, listed_at, media)| WatchlistEntry { listed_at, media })
+    .collect();
+
+    let mut zip = zip::ZipWriter::new(writer);
+    let options = SimpleFileOptions::default();
+
+    // Trakt always writes at least one history file, even if it's empty.
+    let mut history_chunks: Vec<&[WatchHistoryEntry]> =
+        history.chunks(HISTORY_ENTRIES_PER_FILE).collect();
+    if history_chunks.is_empty() {
+        history_chunks.push(&[]);
+    }
+
+    for (idx, chunk) in history_chunks.iter().enumerate() {
+        zip.start_file(
+            format!("{ROOT_DIR}/watched/history-{}.json", idx + 1),
+            options,
+        )?;
+        serde_json::to_writer_pretty(&mut zip, chunk)?;
+    }
+
+    zip.start_file(format!("{ROOT_DIR}/lists/watchlist.json"), options)?;
+    serde_json::to_writer_pretty(&mut zip, &watchlist)?;
+
+    zip.finish()?;
+
+    Ok(())
+}
+
+const EXTERNAL_ID_COLUMNS: [&str; 5] = ["trakt_id", "trakt_slug", "tvdb_id", "imdb_id", "tmdb_id"];
+
+/// Resolves the media referenced by `source` into Trakt's shapes. `source`
+/// must select a `media_id` column, an `at` timestamp column and an `id`
+/// column identifying the row, which is returned along with the media and
+/// used as tie-breaker. `params` are bound to the placeholders in `source`.
+/// Entries are sorted newest first, like Trakt does.
+pub(super) async fn query_media<C: GenericClient>(
+    conn: &C,
+    source: &str,
+    params: &[&(dyn ToSql + Sync)],
+) -> anyhow::Result<Vec<(i32, Timestamp, TraktMedia)>> {
+    let external_id_columns = |alias: &str| {
+        EXTERNAL_ID_COLUMNS
+            .map(|column| format!("{alias}.{column}"))
+            .join(", ")
+    };
+
+    // Seasons and shows are joined through episodes or directly, depending on
+    // the media kind. Ids are unique across all media, so the joins that
+    // don't apply simply don't match anything.
+    let query = format!(
+        "
+        SELECT src.id, src.at, m.kind, mo.title, mo.release_year, sh.title, sh.release_year,
+        se.number, ep.title, ep.number, {}, {}
+        FROM ({source}) src
+        INNER JOIN media m ON m.id = src.media_id
+        LEFT JOIN movie mo ON mo.id = m.id
+        LEFT JOIN episode ep ON ep.id = m.id
+        LEFT JOIN season se ON se.id = COALESCE(ep.season_id, m.id)
+        LEFT JOIN show sh ON sh.id = COALESCE(se.show_id, m.id)
+        LEFT JOIN media_external_id m_ids ON m_ids.media_id = m.id
+        LEFT JOIN media_external_id sh_ids ON sh_ids.media_id = sh.id
+        ORDER BY src.at DESC, src.id DESC
+        ",
+        external_id_columns("m_ids"),
+        external_id_columns("sh_ids"),
+    );
+
+    let rows = conn.query(&query, params).await?;
+
+    let entries = rows
+        .iter()
+        .map(|row| {
+            let media_kind: MediaKind = row.get(2);
+            let ids = external_ids_from_row(row, 10);
+            let show = || TraktShow {
+                title: row.get(5),
+                year: row.get(6),
+                ids: external_ids_from_row(row, 15),
+            };
+
+            let media = match media_kind {
+                MediaKind::Movie => TraktMedia::Movie {
+                    movie: TraktMovie {
+                        title: row.get(3),
+                        year: row.get(4),
+                        ids,
+                    },
+                },
+                MediaKind::Show => TraktMedia::Show { show: show() },
+                MediaKind::Season => TraktMedia::Season {
+                    season: TraktSeason {
+                        number: row.get(7),
+                        ids,
+                    },
+                    show: show(),
+                },
+                MediaKind::Episode => TraktMedia::Episode {
+                    episode: TraktEpisode {
+                        season_number: row.get(7),
+                        number: row.get(9),
+                        title: row.get(8),
+                        ids,
+                    },
+                    show: show(),
+                },
+            };
+
+            (row.get(0), row.get(1), media)
+        })
+        .collect();
+
+    Ok(entries)
+}
+
+fn external_ids_from_row(row: &Row, idx: usize) -> TraktExternalIds {
+    MediaExternalId {
+        trakt_id: row.get(idx),
+        trakt_slug: row.get(idx + 1),
+        tvdb_id: row.get(idx + 2),
+        imdb_id: row.get(idx + 3),
+        tmdb_id: row.get(idx + 4),
+    }
+    .into()
+}
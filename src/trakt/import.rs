@@ -1,96 +1,17 @@
 use deadpool_postgres::GenericClient;
-use jiff::Timestamp;
-use serde::Deserialize;
 
+use super::{TraktEpisode, TraktMedia, TraktMovie, TraktShow, WatchHistoryEntry, WatchlistEntry};
 use crate::db::{
-    Media, MediaExternalId, NewEpisode, NewMovie, NewSeason, NewShow, WatchHistory,
-    get_media_by_trakt_id, get_season_by_show_and_number, insert_episode, insert_list_item,
-    insert_movie, insert_season, insert_show, insert_watch_history,
+    ListKind, Media, MediaKind, MediaMetadata, NewEpisode, NewMovie, NewSeason, NewShow,
+    WatchHistory, get_episode_by_season_and_number, get_list_id_by_kind, get_media_by_external_ids,
+    get_season_by_show_and_number, get_watch_history_id, insert_episode, insert_list_item,
+    insert_movie, insert_season, insert_show, insert_watch_history, set_watch_history_trakt_synced,
 };
 
-#[derive(Deserialize, Debug, Clone)]
-struct TraktExternalIds {
-    trakt: i32,
-    slug: Option<String>,
-    tvdb: Option<i32>,
-    imdb: Option<String>,
-    tmdb: Option<i32>,
-}
-
-impl From<TraktExternalIds> for MediaExternalId {
-    fn from(ids: TraktExternalIds) -> Self {
-        MediaExternalId {
-            trakt_id: Some(ids.trakt),
-            trakt_slug: ids.slug,
-            tvdb_id: ids.tvdb,
-            imdb_id: ids.imdb,
-            tmdb_id: ids.tmdb,
-        }
-    }
-}
-
-#[derive(Debug, Deserialize)]
-struct TraktMovie {
-    title: String,
-    year: Option<i32>,
-    ids: TraktExternalIds,
-}
-
-#[derive(Debug, Deserialize)]
-struct TraktEpisode {
-    #[serde(rename = "season")]
-    season_number: i32,
-    number: i32,
-    title: String,
-    ids: TraktExternalIds,
-}
-
-#[derive(Debug, Deserialize)]
-struct TraktShow {
-    title: String,
-    year: Option<i32>,
-    ids: TraktExternalIds,
-}
-
-#[derive(Debug, Deserialize)]
-struct TraktSeason {
-    number: i32,
-    ids: TraktExternalIds,
-}
-
-#[derive(Deserialize, Debug)]
-#[serde(tag = "type")]
-enum TraktMedia {
-    #[serde(rename = "movie")]
-    Movie { movie: TraktMovie },
-    #[serde(rename = "episode")]
-    Episode {
-        episode: TraktEpisode,
-        show: TraktShow,
-    },
-    #[serde(rename = "show")]
-    Show { show: TraktShow },
-    #[serde(rename = "season")]
-    Season {
-        season: TraktSeason,
-        show: TraktShow,
-    },
-}
-
-#[derive(Deserialize, Debug)]
-struct WatchHistoryEntry {
-    watched_at: Timestamp,
-    #[serde(flatten)]
-    media: TraktMedia,
-    // unused fields:
-    // action: String,
-    // progress: f32,
-    // duration: Option<i32>,
-}
-
 // TODO: error handling
 pub async fn import_zip<C: GenericClient, R: std::io::Read + std::io::Seek>(
     conn: &mut C,
+    user_id: i32,
     zip_file: &mut R,
 ) -> anyhow::Result<()> {
     let mut zip = zip::ZipArchive::new(zip_file)?;
@@ -101,11 +22,11 @@ pub async fn import_zip<C: GenericClient, R: std::io::Read + std::io::Seek>(
 
         // TODO: use regex
         if file_name.contains("/watched/history-") && file_name.ends_with(".json") {
-            import_watch_history(conn, &mut file).await?;
+            import_watch_history(conn, user_id, &mut file).await?;
         }
 
         if file_name.ends_with("/lists/watchlist.json") {
-            import_watchlist(conn, &mut file).await?;
+            import_watchlist(conn, user_id, &mut file).await?;
         }
     }
 
@@ -116,102 +37,119 @@ pub async fn import_zip<C: GenericClient, R: std::io::Read + std::io::Seek>(
 // TODO: importing is quite slow right now. Caching shows and seasons would be nicer
 pub async fn import_watch_history<C: GenericClient, R: std::io::Read>(
     conn: &mut C,
+    user_id: i32,
     history_file: &mut R,
 ) -> anyhow::Result<()> {
     let entries: Vec<WatchHistoryEntry> = serde_json::from_reader(history_file)?;
 
+    let mut synced_ids = Vec::new();
     for entry in entries {
         let media = match entry.media {
-            TraktMedia::Episode {
-                episode: trakt_episode,
-                show: trakt_show,
-            } => {
-                let show = get_or_create_show(conn, &trakt_show).await?;
-                let season = get_or_create_season(conn, &show, trakt_episode.season_number).await?;
-                get_or_create_episode(conn, &show, &season, &trakt_episode).await?
-            }
-            TraktMedia::Movie { movie: trakt_movie } => {
-                get_or_create_movie(conn, &trakt_movie).await?
+            TraktMedia::Episode { .. } | TraktMedia::Movie { .. } => {
+                get_or_create_media(conn, &entry.media).await?
             }
-            _ => panic!("Unsupported media type in watch history: {:?}", entry.media),
+            _ => anyhow::bail!("Unsupported media type in watch history: {:?}", entry.media),
+        };
+
+        let watch_history = WatchHistory {
+            watched_at: entry.watched_at,
+            media,
+        };
+
+        // Skip plays that were already imported
+        let id = match get_watch_history_id(conn, user_id, &watch_history).await? {
+            Some(id) => id,
+            None => insert_watch_history(conn, user_id, &watch_history).await?,
         };
 
-        insert_watch_history(
-            conn,
-            &WatchHistory {
-                watched_at: entry.watched_at,
-                media: media,
-            },
-        )
-        .await?
+        // Plays from Trakt's own exports are already on Trakt and mustn't be
+        // pushed again. Those always have Trakt ids, while exports made by
+        // Grimoire only have them for media that came from Trakt.
+        let trakt_id = match &entry.media {
+            TraktMedia::Movie { movie } => movie.ids.trakt,
+            TraktMedia::Episode { episode, .. } => episode.ids.trakt,
+            _ => None,
+        };
+        if trakt_id.is_some() {
+            synced_ids.push(id);
+        }
     }
 
-    Ok(())
-}
+    set_watch_history_trakt_synced(conn, &synced_ids).await?;
 
-#[derive(Deserialize, Debug)]
-struct WatchlistEntry {
-    listed_at: jiff::Timestamp,
-    #[serde(flatten)]
-    media: TraktMedia,
-    // unused fields:
-    // rank: i32,
-    // notes: Option<String>,
+    Ok(())
 }
 
 pub async fn import_watchlist<C: GenericClient, R: std::io::Read>(
     conn: &mut C,
+    user_id: i32,
     watchlist_file: &mut R,
 ) -> anyhow::Result<()> {
     let entries: Vec<WatchlistEntry> = serde_json::from_reader(watchlist_file)?;
 
+    let Some(watchlist_id) = get_list_id_by_kind(conn, user_id, ListKind::Watchlist).await? else {
+        anyhow::bail!("watchlist not found");
+    };
+
     for entry in entries {
-        let media = match entry.media {
-            TraktMedia::Episode {
-                episode: trakt_episode,
-                show: trakt_show,
-            } => {
-                let show = get_or_create_show(conn, &trakt_show).await?;
-                let season = get_or_create_season(conn, &show, trakt_episode.season_number).await?;
-                get_or_create_episode(conn, &show, &season, &trakt_episode).await?
-            }
-            TraktMedia::Movie { movie: trakt_movie } => {
-                get_or_create_movie(conn, &trakt_movie).await?
-            }
-            TraktMedia::Show { show: trakt_show } => get_or_create_show(conn, &trakt_show).await?,
-            TraktMedia::Season {
-                season: trakt_season,
-                show: trakt_show,
-            } => {
-                let show = get_or_create_show(conn, &trakt_show).await?;
-                get_or_create_season(conn, &show, trakt_season.number).await?
-            }
-        };
+        let media = get_or_create_media(conn, &entry.media).await?;
 
-        // TODO: remove hardcoded list id
-        insert_list_item(conn, &1, &media, Some(&entry.listed_at)).await?;
+        insert_list_item(conn, &watchlist_id, &media, Some(&entry.listed_at)).await?;
     }
 
     Ok(())
 }
 
+/// Returns the library media for a Trakt item, creating it (and its show and
+/// season, for episodes) when missing.
+pub(super) async fn get_or_create_media<C: GenericClient>(
+    conn: &mut C,
+    media: &TraktMedia,
+) -> anyhow::Result<Media> {
+    let media = match media {
+        TraktMedia::Episode {
+            episode: trakt_episode,
+            show: trakt_show,
+        } => {
+            let show = get_or_create_show(conn, trakt_show).await?;
+            let season = get_or_create_season(conn, &show, trakt_episode.season_number).await?;
+            get_or_create_episode(conn, &show, &season, trakt_episode).await?
+        }
+        TraktMedia::Movie { movie: trakt_movie } => get_or_create_movie(conn, trakt_movie).await?,
+        TraktMedia::Show { show: trakt_show } => get_or_create_show(conn, trakt_show).await?,
+        TraktMedia::Season {
+            season: trakt_season,
+            show: trakt_show,
+        } => {
+            let show = get_or_create_show(conn, trakt_show).await?;
+            get_or_create_season(conn, &show, trakt_season.number).await?
+        }
+    };
+
+    Ok(media)
+}
+
 async fn get_or_create_show<C: GenericClient>(
     conn: &mut C,
     trakt_show: &TraktShow,
 ) -> anyhow::Result<Media> {
-    let media = match get_media_by_trakt_id(conn, trakt_show.ids.trakt).await? {
+    let external_ids = trakt_show.ids.clone().into();
+    let media = match get_media_by_external_ids(conn, &external_ids, MediaKind::Show).await? {
         Some(show) => show,
         None => {
             insert_show(
                 conn,
                 &NewShow {
+                    slug: None,
                     title: trakt_show.title.clone(),
                     release_year: trakt_show.year,
                     overview: None,
                     tagline: None,
                     episode_runtime: None,
-                    external_ids: Some(trakt_show.ids.clone().into()),
+                    external_ids: Some(external_ids),
                     seasons: None,
+                    metadata: MediaMetadata::default(),
+                    credits: vec![],
                 },
             )
             .await?
@@ -226,12 +164,12 @@ async fn get_or_create_season<C: GenericClient>(
     show: &Media,
     season_number: i32,
 ) -> anyhow::Result<Media> {
-    let media = match get_season_by_show_and_number(conn, &show, season_number).await? {
+    let media = match get_season_by_show_and_number(conn, show, season_number).await? {
         Some(season) => season,
         None => {
             insert_season(
                 conn,
-                &show,
+                show,
                 &NewSeason {
                     title: format!("Season {}", season_number),
                     number: season_number,
@@ -253,19 +191,26 @@ async fn get_or_create_episode<C: GenericClient>(
     season: &Media,
     trakt_episode: &TraktEpisode,
 ) -> anyhow::Result<Media> {
-    let media = match get_media_by_trakt_id(conn, trakt_episode.ids.trakt).await? {
+    let external_ids = trakt_episode.ids.clone().into();
+    let existing = match get_media_by_external_ids(conn, &external_ids, MediaKind::Episode).await? {
+        Some(episode) => Some(episode),
+        None => get_episode_by_season_and_number(conn, season, trakt_episode.number).await?,
+    };
+
+    let media = match existing {
         Some(episode) => episode,
         None => {
             insert_episode(
                 conn,
-                &show,
-                &season,
+                show,
+                season,
                 &NewEpisode {
                     title: trakt_episode.title.clone(),
                     number: trakt_episode.number,
                     overview: None,
                     runtime: None,
-                    external_ids: Some(trakt_episode.ids.clone().into()),
+                    external_ids: Some(external_ids),
+                    credits: vec![],
                 },
             )
             .await?
@@ -279,18 +224,22 @@ async fn get_or_create_movie<C: GenericClient>(
     conn: &mut C,
     trakt_movie: &TraktMovie,
 ) -> anyhow::Result<Media> {
-    let media = match get_media_by_trakt_id(conn, trakt_movie.ids.trakt).await? {
+    let external_ids = trakt_movie.ids.clone().into();
+    let media = match get_media_by_external_ids(conn, &external_ids, MediaKind::Movie).await? {
         Some(media) => media,
         None => {
             insert_movie(
                 conn,
                 &NewMovie {
+                    slug: None,
                     title: trakt_movie.title.clone(),
                     release_year: trakt_movie.year,
-                    external_ids: Some(trakt_movie.ids.clone().into()),
+                    external_ids: Some(external_ids),
                     overview: None,
                     tagline: None,
                     runtime: None,
+                    metadata: MediaMetadata::default(),
+                    credits: vec![],
                 },
             )
             .await?
@@ -0,0 +1,541 @@
+//! Two-way sync with a Trakt account through the Trakt API.
+//!
+//! Plays pulled from or pushed to Trakt are marked with `trakt_synced_at`, so
+//! pushing only sends what was added locally since the last sync.
+
+use std::time::Duration;
+
+use deadpool_postgres::GenericClient;
+use jiff::{Timestamp, ToSpan};
+
+use super::{
+    TraktExternalIds, TraktMedia, TraktShow,
+    api::{
+        DeviceTokenPoll, NotFoundItem, SyncEpisode, SyncItems, SyncMovie, SyncNotFound, SyncSeason,
+        SyncShow, TraktApi,
+    },
+    export::query_media,
+    import::get_or_create_media,
+};
+use crate::db::{
+    ListKind, WatchHistory, delete_list_items, get_list_id_by_kind, get_trakt_sync_state,
+    get_watch_history_id, insert_watch_history, set_list_items_trakt_synced,
+    set_trakt_history_pulled_at, set_trakt_tokens, set_watch_history_trakt_synced,
+};
+
+/// What a [`sync`] did.
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    /// Plays received from Trakt, including ones that were already recorded.
+    pub pulled_plays: usize,
+    /// Plays Trakt added to its history.
+    pub pushed_plays: i32,
+    /// Items Trakt added to its watchlist.
+    pub pushed_watchlist_items: i32,
+    /// Synced watchlist items that were removed from the Trakt watchlist,
+    /// and so were removed locally too.
+    pub removed_watchlist_items: usize,
+}
+
+/// Access tokens expiring sooner than this are refreshed before syncing.
+const TOKEN_REFRESH_MARGIN_HOURS: i64 = 24;
+
+// TODO: error handling
+/// Logs a user into Trakt with the OAuth device flow and stores their tokens.
+/// `show_code` receives the code the user has to enter and the URL to enter
+/// it at, then this waits until the user approves it.
+pub async fn login<C: GenericClient>(
+    conn: &C,
+    user_id: i32,
+    api: &TraktApi,
+    show_code: impl FnOnce(&str, &str),
+) -> anyhow::Result<()> {
+    let device_code = api.request_device_code().await?;
+    show_code(&device_code.user_code, &device_code.verification_url);
+
+    let expires_at = Timestamp::now() + device_code.expires_in.seconds();
+    let mut interval = device_code.interval.max(1) as u64;
+
+    let token = loop {
+        if Timestamp::now() > expires_at {
+            anyhow::bail!("device code expired before it was approved");
+        }
+
+        tokio::time::sleep(Duration::from_secs(interval)).await;
+
+        match api.poll_device_token(&device_code.device_code).await? {
+            DeviceTokenPoll::Authorized(token) => break token,
+            DeviceTokenPoll::Pending => {}
+            DeviceTokenPoll::SlowDown => interval += 1,
+        }
+    };
+
+    set_trakt_tokens(
+        conn,
+        user_id,
+        &token.access_token,
+        &token.refresh_token,
+        &token.expires_at(),
+    )
+    .await?;
+
+    Ok(())
+}
+
+// TODO: error handling
+/// Pulls the watch history a user added on Trakt since their last sync and
+/// the watchlist items they removed there, then, if `push` is set, pushes
+/// their plays and watchlist items that haven't been synced yet.
+pub async fn sync<C: GenericClient>(
+    conn: &mut C,
+    user_id: i32,
+    api: &TraktApi,
+    push: bool,
+) -> anyhow::Result<SyncReport> {
+    let access_token = get_access_token(conn, user_id, api).await?;
+
+    let mut report = SyncReport {
+        pulled_plays: pull_history(conn, user_id, api, &access_token).await?,
+        removed_watchlist_items: pull_watchlist_removals(conn, user_id, api, &access_token).await?,
+        ..Default::default()
+    };
+
+    if push {
+        report.pushed_plays = push_history(conn, user_id, api, &access_token).await?;
+        report.pushed_watchlist_items = push_watchlist(conn, user_id, api, &access_token).await?;
+    }
+
+    Ok(report)
+}
+
+/// Returns a valid access token, refreshing it first if it's about to expire.
+async fn get_access_token<C: GenericClient>(
+    conn: &C,
+    user_id: i32,
+    api: &TraktApi,
+) -> anyhow::Result<String> {
+    let Some(state) = get_trakt_sync_state(conn, user_id).await? else {
+        anyhow::bail!("not logged into Trakt, run `grimoire trakt login` first");
+    };
+
+    if state.expires_at > Timestamp::now() + TOKEN_REFRESH_MARGIN_HOURS.hours() {
+        return Ok(state.access_token);
+    }
+
+    let token = api.refresh_token(&state.refresh_token).await?;
+    set_trakt_tokens(
+        conn,
+        user_id,
+        &token.access_token,
+        &token.refresh_token,
+        &token.expires_at(),
+    )
+    .await?;
+
+    Ok(token.access_token)
+}
+
+/// Days before the last pull that the next pull starts at. Trakt only filters
+/// plays by when they were watched, so this picks up plays that were logged
+/// on Trakt some time after they were watched.
+const HISTORY_PULL_OVERLAP_DAYS: i64 = 30;
+
+/// Pulls plays watched since shortly before the last pull, or the whole
+/// history on the first pull, unless the history didn't change on Trakt
+/// since. Plays that already exist locally are only marked as synced. Returns
+/// the number of plays pulled.
+async fn pull_history<C: GenericClient>(
+    conn: &mut C,
+    user_id: i32,
+    api: &TraktApi,
+    access_token: &str,
+) -> anyhow::Result<usize> {
+    let pull_started_at = Timestamp::now();
+    let changed_at = api
+        .fetch_last_activities(access_token)
+        .await?
+        .history_changed_at();
+
+    let state = get_trakt_sync_state(conn, user_id).await?;
+    if state
+        .as_ref()
+        .and_then(|state| state.history_changed_at)
+        .is_some_and(|seen| seen >= changed_at)
+    {
+        return Ok(0);
+    }
+    let start_at = state
+        .and_then(|state| state.history_pulled_at)
+        .map(|pulled_at| pulled_at - (HISTORY_PULL_OVERLAP_DAYS * 24).hours());
+
+    let mut pulled = 0;
+    let mut page = 1;
+    loop {
+        let history = api
+            .fetch_history(access_token, start_at.as_ref(), page)
+            .await?;
+
+        let mut synced_ids = Vec::with_capacity(history.items.len());
+        for entry in history.items {
+            let media = get_or_create_media(conn, &entry.media).await?;
+            let watch_history = WatchHistory {
+                watched_at: entry.watched_at,
+                media,
+            };
+
+            let id = match get_watch_history_id(conn, user_id, &watch_history).await? {
+                Some(id) => id,
+                None => insert_watch_history(conn, user_id, &watch_history).await?,
+            };
+            synced_ids.push(id);
+        }
+        set_watch_history_trakt_synced(conn, &synced_ids).await?;
+        pulled += synced_ids.len();
+
+        if page >= history.page_count {
+            break;
+        }
+        page += 1;
+    }
+
+    set_trakt_history_pulled_at(conn, user_id, &pull_started_at, &changed_at).await?;
+
+    Ok(pulled)
+}
+
+/// Plays or watchlist items sent to Trakt per request.
+const PUSH_BATCH_SIZE: usize = 100;
+
+/// Pause between pushed batches, Trakt allows one `POST` per second.
+const PUSH_BATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Pushes the plays that weren't synced yet. Only plays Trakt matched are
+/// marked as synced, the others are sent again on the next push. Returns the
+/// number of plays Trakt added.
+async fn push_history<C: GenericClient>(
+    conn: &C,
+    user_id: i32,
+    api: &TraktApi,
+    access_token: &str,
+) -> anyhow::Result<i32> {
+    let plays = query_media(
+        conn,
+        "SELECT wh.media_id, wh.watched_at AS at, wh.id FROM watch_history wh
+        WHERE wh.user_id = $1 AND wh.trakt_synced_at IS NULL",
+        &[&user_id],
+    )
+    .await?;
+
+    let mut pushed = 0;
+    for (idx, batch) in plays.chunks(PUSH_BATCH_SIZE).enumerate() {
+        if idx > 0 {
+            tokio::time::sleep(PUSH_BATCH_INTERVAL).await;
+        }
+
+        let mut items = SyncItems::default();
+        for (_, watched_at, media) in batch {
+            add_sync_item(&mut items, media, Some(*watched_at));
+        }
+
+        let res = api.add_to_history(access_token, &items).await?;
+        set_watch_history_trakt_synced(conn, &found_ids(batch, &res.not_found)).await?;
+        pushed += res.added.movies + res.added.episodes;
+    }
+
+    Ok(pushed)
+}
+
+/// Removes the watchlist items that were synced but are no longer on the
+/// Trakt watchlist, as they were removed there. Returns the number of items
+/// removed.
+async fn pull_watchlist_removals<C: GenericClient>(
+    conn: &C,
+    user_id: i32,
+    api: &TraktApi,
+    access_token: &str,
+) -> anyhow::Result<usize> {
+    let Some(watchlist_id) = get_list_id_by_kind(conn, user_id, ListKind::Watchlist).await? else {
+        return Ok(0);
+    };
+
+    let synced = query_media(
+        conn,
+        "SELECT li.media_id, li.listed_at AS at, li.media_id AS id FROM list_item li
+        WHERE li.list_id = $1 AND li.trakt_synced_at IS NOT NULL",
+        &[&watchlist_id],
+    )
+    .await?;
+
+    if synced.is_empty() {
+        return Ok(0);
+    }
+
+    let trakt_watchlist = api.fetch_watchlist(access_token).await?;
+    let removed: Vec<i32> = synced
+        .iter()
+        .filter(|(_, _, media)| {
+            !trakt_watchlist
+                .iter()
+                .any(|entry| same_media(media, &entry.media))
+        })
+        .map(|(media_id, _, _)| *media_id)
+        .collect();
+
+    delete_list_items(conn, watchlist_id, &removed).await?;
+
+    Ok(removed.len())
+}
+
+/// Pushes the watchlist items that weren't synced yet, like [`push_history`].
+/// Returns the number of items Trakt added.
+async fn push_watchlist<C: GenericClient>(
+    conn: &C,
+    user_id: i32,
+    api: &TraktApi,
+    access_token: &str,
+) -> anyhow::Result<i32> {
+    let Some(watchlist_id) = get_list_id_by_kind(conn, user_id, ListKind::Watchlist).await? else {
+        return Ok(0);
+    };
+
+    let listed = query_media(
+        conn,
+        "SELECT li.media_id, li.listed_at AS at, li.media_id AS id FROM list_item li
+        WHERE li.list_id = $1 AND li.trakt_synced_at IS NULL",
+        &[&watchlist_id],
+    )
+    .await?;
+
+    let mut pushed = 0;
+    for (idx, batch) in listed.chunks(PUSH_BATCH_SIZE).enumerate() {
+        if idx > 0 {
+            tokio::time::sleep(PUSH_BATCH_INTERVAL).await;
+        }
+
+        let mut items = SyncItems::default();
+        for (_, _, media) in batch {
+            add_sync_item(&mut items, media, None);
+        }
+
+        let res = api.add_to_watchlist(access_token, &items).await?;
+        set_list_items_trakt_synced(conn, watchlist_id, &found_ids(batch, &res.not_found)).await?;
+        pushed += res.added.movies + res.added.shows + res.added.seasons + res.added.episodes;
+    }
+
+    Ok(pushed)
+}
+
+/// Returns the ids of the pushed entries Trakt matched.
+fn found_ids(entries: &[(i32, Timestamp, TraktMedia)], not_found: &SyncNotFound) -> Vec<i32> {
+    entries
+        .iter()
+        .filter(|(_, _, media)| !is_not_found(not_found, media))
+        .map(|(id, _, _)| *id)
+        .collect()
+}
+
+fn is_not_found(not_found: &SyncNotFound, media: &TraktMedia) -> bool {
+    fn find<'a>(
+        items: &'a [NotFoundItem],
+        title: &str,
+        year: Option<i32>,
+        ids: &TraktExternalIds,
+    ) -> Option<&'a NotFoundItem> {
+        items.iter().find(|item| {
+            same_item(
+                (ids, title, year),
+                (&item.ids, item.title.as_deref(), item.year),
+            )
+        })
+    }
+    let find_show = |show: &TraktShow| find(&not_found.shows, &show.title, show.year, &show.ids);
+
+    match media {
+        TraktMedia::Movie { movie } => {
+            find(&not_found.movies, &movie.title, movie.year, &movie.ids).is_some()
+        }
+        TraktMedia::Show { show } => find_show(show).is_some(),
+        TraktMedia::Season { season, show } => find_show(show).is_some_and(|item| {
+            item.seasons.is_empty() || item.seasons.iter().any(|s| s.number == season.number)
+        }),
+        TraktMedia::Episode { episode, show } => find_show(show).is_some_and(|item| {
+            item.seasons.is_empty()
+                || item.seasons.iter().any(|s| {
+                    s.number == episode.season_number
+                        && (s.episodes.is_empty()
+                            || s.episodes.iter().any(|e| e.number == episode.number))
+                })
+        }),
+    }
+}
+
+fn same_media(a: &TraktMedia, b: &TraktMedia) -> bool {
+    let same_show = |a: &TraktShow, b: &TraktShow| {
+        same_item((&a.ids, &a.title, a.year), (&b.ids, Some(&b.title), b.year))
+    };
+
+    match (a, b) {
+        (TraktMedia::Movie { movie: a }, TraktMedia::Movie { movie: b }) => {
+            same_item((&a.ids, &a.title, a.year), (&b.ids, Some(&b.title), b.year))
+        }
+        (TraktMedia::Show { show: a }, TraktMedia::Show { show: b }) => same_show(a, b),
+        (
+            TraktMedia::Season {
+                season: a,
+                show: a_show,
+            },
+            TraktMedia::Season {
+                season: b,
+                show: b_show,
+            },
+        ) => a.number == b.number && same_show(a_show, b_show),
+        (
+            TraktMedia::Episode {
+                episode: a,
+                show: a_show,
+            },
+            TraktMedia::Episode {
+                episode: b,
+                show: b_show,
+            },
+        ) => {
+            a.season_number == b.season_number && a.number == b.number && same_show(a_show, b_show)
+        }
+        _ => false,
+    }
+}
+
+/// Whether a local item is the same media as one from Trakt, by ids or, for
+/// items without any, which are sent to Trakt by title, by title and year.
+fn same_item(
+    (ids, title, year): (&TraktExternalIds, &str, Option<i32>),
+    (other_ids, other_title, other_year): (&TraktExternalIds, Option<&str>, Option<i32>),
+) -> bool {
+    if ids.is_empty() {
+        other_title == Some(title) && other_year == year
+    } else {
+        ids.matches(other_ids)
+    }
+}
+
+/// Adds a media to the items sent to Trakt. Seasons and episodes are sent
+/// nested in their show by number, so Trakt can match them even when they
+/// have no ids of their own.
+fn add_sync_item(items: &mut SyncItems, media: &TraktMedia, watched_at: Option<Timestamp>) {
+    let sync_show = |show: &TraktShow, seasons| SyncShow {
+        title: show.title.clone(),
+        year: show.year,
+        ids: show.ids.clone(),
+        seasons,
+    };
+
+    match media {
+        TraktMedia::Movie { movie } => items.movies.push(SyncMovie {
+            title: movie.title.clone(),
+            year: movie.year,
+            watched_at,
+            ids: movie.ids.clone(),
+        }),
+        TraktMedia::Show { show } => items.shows.push(sync_show(show, Vec::new())),
+        TraktMedia::Season { season, show } => items.shows.push(sync_show(
+            show,
+            vec![SyncSeason {
+                number: season.number,
+                episodes: Vec::new(),
+            }],
+        )),
+        TraktMedia::Episode { episode, show } => items.shows.push(sync_show(
+            show,
+            vec![SyncSeason {
+                number: episode.season_number,
+                episodes: vec![SyncEpisode {
+                    number: episode.number,
+                    watched_at,
+                }],
+            }],
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn media(value: serde_json::Value) -> TraktMedia {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn episode(show_ids: serde_json::Value, season: i32, number: i32) -> TraktMedia {
+        media(json!({
+            "type": "episode",
+            "episode": { "season": season, "number": number, "title": "", "ids": {} },
+            "show": { "title": "Lost", "year": 2004, "ids": show_ids },
+        }))
+    }
+
+    #[test]
+    fn found_ids_skips_not_found_entries() {
+        let at = Timestamp::UNIX_EPOCH;
+        let entries = [
+            (
+                1,
+                at,
+                media(json!({
+                    "type": "movie",
+                    "movie": { "title": "Heat", "year": 1995, "ids": { "tmdb": 949 } },
+                })),
+            ),
+            (
+                2,
+                at,
+                media(json!({
+                    "type": "movie",
+                    "movie": { "title": "Nameless", "year": 2001, "ids": {} },
+                })),
+            ),
+            (3, at, episode(json!({ "tvdb": 73739 }), 1, 1)),
+            (4, at, episode(json!({ "tvdb": 73739 }), 1, 2)),
+            (5, at, episode(json!({ "tvdb": 73739 }), 2, 1)),
+            (6, at, episode(json!({ "tmdb": 1 }), 1, 1)),
+        ];
+        let not_found: SyncNotFound = serde_json::from_value(json!({
+            "movies": [{ "title": "Nameless", "year": 2001, "ids": {} }],
+            "shows": [
+                {
+                    "title": "Lost",
+                    "year": 2004,
+                    "ids": { "tvdb": 73739 },
+                    "seasons": [{ "number": 1, "episodes": [{ "number": 2 }] }],
+                },
+                { "title": "Lost", "year": 2004, "ids": { "tmdb": 1 } },
+            ],
+        }))
+        .unwrap();
+
+        assert_eq!(found_ids(&entries, &not_found), [1, 3, 5]);
+    }
+
+    #[test]
+    fn same_media_matches_any_shared_id() {
+        let local = episode(json!({ "tmdb": 4607 }), 1, 2);
+
+        assert!(same_media(
+            &local,
+            &episode(json!({ "trakt": 10, "tmdb": 4607 }), 1, 2)
+        ));
+        assert!(!same_media(
+            &local,
+            &episode(json!({ "trakt": 10, "tmdb": 4607 }), 1, 3)
+        ));
+        assert!(!same_media(&local, &episode(json!({ "tmdb": 1 }), 1, 2)));
+        assert!(!same_media(
+            &local,
+            &media(json!({
+                "type": "show",
+                "show": { "title": "Lost", "year": 2004, "ids": { "tmdb": 4607 } },
+            }))
+        ));
+    }
+}